@@ -0,0 +1,141 @@
+use crate::config::{Config, ServerAccessRule};
+use crate::error_pages::error_response;
+use crate::headers::{HttpAllowHeader, HttpHeader, HttpResponse, FORBIDDEN, METHOD_NOT_ALLOWED};
+use crate::parser::HttpRequest;
+use crate::upload::matches_prefix;
+use crate::webdav::{destination_path, is_webdav_method};
+
+/// Methods allowed on paths that no `[[server.access]]` entry matches.
+const READ_ONLY_METHODS: [&str; 4] = ["GET", "HEAD", "OPTIONS", "PROPFIND"];
+
+/// Every method the server knows how to handle, used to expand `*`.
+pub const ALL_METHODS: [&str; 14] = [
+    "GET", "HEAD", "OPTIONS", "POST", "PUT", "PATCH", "DELETE", "PROPFIND", "PROPPATCH", "MKCOL", "COPY", "MOVE",
+    "LOCK", "UNLOCK",
+];
+
+/// Returns the entry with the longest prefix matching `path`.
+fn rule_for<'a>(path: &str, config: &'a Config) -> Option<&'a ServerAccessRule> {
+    config
+        .server
+        .access
+        .iter()
+        .filter(|r| matches_prefix(path, &r.path))
+        .max_by_key(|r| r.path.trim_end_matches('/').len())
+}
+
+/// Returns the methods that may be used on `path`. The WebDAV methods are left out while
+/// WebDAV is disabled, since nothing would handle them. `OPTIONS` stays, tus discovery uses it.
+pub fn allowed_methods(path: &str, config: &Config) -> Vec<String> {
+    let methods = match rule_for(path, config) {
+        None => READ_ONLY_METHODS.iter().map(|m| m.to_string()).collect(),
+        Some(rule) if rule.methods.iter().any(|m| m == "*") => ALL_METHODS.iter().map(|m| m.to_string()).collect(),
+        Some(rule) => rule.methods.clone(),
+    };
+
+    methods
+        .into_iter()
+        .filter(|m| config.server.webdav.enable || m == "OPTIONS" || !is_webdav_method(m))
+        .collect()
+}
+
+/// Returns true if `method` may be used on `path`.
+pub fn is_allowed(method: &str, path: &str, config: &Config) -> bool {
+    allowed_methods(path, config).iter().any(|m| m == method)
+}
+
+/// Checks the request against the access policy before it is dispatched. Returns a
+/// `405 Method Not Allowed` response listing the allowed methods if it is refused. `COPY`
+/// and `MOVE` also need the method to be allowed on their destination, or get `403 Forbidden`.
+pub fn enforce(request: &HttpRequest, config: &Config) -> Option<HttpResponse> {
+    if !is_allowed(&request.method, &request.path, config) {
+        let allowed = allowed_methods(&request.path, config).join(", ");
+        return Some(error_response(METHOD_NOT_ALLOWED.code, request, config).add_header(HttpAllowHeader::new_from(allowed)));
+    }
+
+    if matches!(request.method.as_str(), "COPY" | "MOVE") {
+        if let Ok(destination) = destination_path(request) {
+            if !is_allowed(&request.method, &destination, config) {
+                return Some(error_response(FORBIDDEN.code, request, config));
+            }
+        }
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{allowed_methods, enforce, ALL_METHODS};
+    use crate::config::{for_tests, Config};
+    use crate::parser::HttpRequest;
+
+    const RULES: &str = "[[server.access]]\npath = \"/up\"\nmethods = [\"GET\", \"put\", \"MOVE\", \"COPY\"]\n\
+        [[server.access]]\npath = \"/up/ro/\"\nmethods = [\"GET\"]\n\
+        [[server.access]]\npath = \"/all\"\nmethods = [\"*\"]\n";
+
+    fn config(webdav: bool) -> Config {
+        for_tests("", &format!("{RULES}[server.webdav]\nenable = {webdav}\n"))
+    }
+
+    fn status(request: &str, config: &Config) -> Option<u16> {
+        let request = HttpRequest::parse(&format!("{request}\r\n\r\n")).unwrap();
+        enforce(&request, config).map(|r| r.status_code())
+    }
+
+    #[test]
+    fn unmatched_paths_are_read_only() {
+        assert_eq!(allowed_methods("/other", &config(true)), vec!["GET", "HEAD", "OPTIONS", "PROPFIND"]);
+        assert_eq!(allowed_methods("/upload", &config(true)), vec!["GET", "HEAD", "OPTIONS", "PROPFIND"]);
+    }
+
+    #[test]
+    fn longest_prefix_wins() {
+        let config = config(true);
+        assert_eq!(allowed_methods("/up", &config), vec!["GET", "PUT", "MOVE", "COPY"]);
+        assert_eq!(allowed_methods("/up/a/b", &config), vec!["GET", "PUT", "MOVE", "COPY"]);
+        assert_eq!(allowed_methods("/up/ro", &config), vec!["GET"]);
+        assert_eq!(allowed_methods("/up/ro/file", &config), vec!["GET"]);
+        assert_eq!(allowed_methods("/up/rot", &config), vec!["GET", "PUT", "MOVE", "COPY"]);
+    }
+
+    #[test]
+    fn star_allows_every_method() {
+        assert_eq!(allowed_methods("/all/x", &config(true)), ALL_METHODS.to_vec());
+    }
+
+    #[test]
+    fn webdav_methods_are_dropped_while_webdav_is_disabled() {
+        let config = config(false);
+        assert_eq!(allowed_methods("/other", &config), vec!["GET", "HEAD", "OPTIONS"]);
+        assert_eq!(allowed_methods("/up", &config), vec!["GET", "PUT"]);
+        let all = allowed_methods("/all", &config);
+        assert!(all.iter().any(|m| m == "OPTIONS"));
+        assert!(!all.iter().any(|m| m == "PROPFIND" || m == "MOVE" || m == "LOCK"));
+    }
+
+    #[test]
+    fn options_passes_without_webdav() {
+        assert_eq!(status("OPTIONS /files HTTP/1.1", &config(false)), None);
+        assert_eq!(status("OPTIONS /all/files HTTP/1.1", &config(false)), None);
+    }
+
+    #[test]
+    fn refused_methods_get_405() {
+        let config = config(true);
+        assert_eq!(status("PUT /other HTTP/1.1", &config), Some(405));
+        assert_eq!(status("PUT /up/ro/a HTTP/1.1", &config), Some(405));
+        assert_eq!(status("PUT /up/a HTTP/1.1", &config), None);
+        assert_eq!(status("PROPFIND /up/a HTTP/1.1", &config), Some(405));
+    }
+
+    #[test]
+    fn copy_and_move_need_the_method_on_the_destination() {
+        let config = config(true);
+        assert_eq!(status("MOVE /up/a HTTP/1.1\r\nDestination: /up/b", &config), None);
+        assert_eq!(status("COPY /up/a HTTP/1.1\r\nDestination: http://host/up/b", &config), None);
+        assert_eq!(status("MOVE /up/a HTTP/1.1\r\nDestination: /up/ro/b", &config), Some(403));
+        assert_eq!(status("COPY /up/a HTTP/1.1\r\nDestination: /other", &config), Some(403));
+        assert_eq!(status("MOVE /up/a HTTP/1.1\r\nDestination: /all/b", &config), None);
+    }
+}
@@ -0,0 +1,53 @@
+use log::error;
+use std::io::Write;
+use std::net::IpAddr;
+use fs_err as fs;
+
+use crate::body::HttpBody;
+use crate::config::Config;
+use crate::datetime::DateTime;
+use crate::escape::json_escape;
+use crate::headers::HttpResponse;
+use crate::parser::HttpRequest;
+use crate::webdav::destination_path;
+
+/// Returns true if requests using `method` are recorded in the audit log.
+pub fn is_audited(method: &str, config: &Config) -> bool {
+    !config.logging.audit_log.is_empty() && matches!(method, "PUT" | "POST" | "PATCH" | "DELETE" | "COPY" | "MOVE")
+}
+
+/// Appends a JSON line describing a state-changing request and its outcome to the audit
+/// log. `response` is [`None`] if handling the request failed before a response was built.
+/// Failing to write the line is logged but doesn't affect the response.
+pub fn record(request: &HttpRequest, client: IpAddr, body: &mut HttpBody, response: Option<&HttpResponse>, config: &Config) {
+    let user = Some(config.logging.user_header.as_str())
+        .filter(|h| !h.is_empty())
+        .and_then(|h| request.header(h))
+        .map_or(String::from("null"), |u| format!("\"{}\"", json_escape(u)));
+    let destination = match request.method.as_str() {
+        "COPY" | "MOVE" => destination_path(request).map_or(String::from("null"), |d| format!("\"{}\"", json_escape(&d))),
+        _ => String::from("null"),
+    };
+    let bytes = body.bytes_read();
+    let hash = body
+        .content_hash()
+        .filter(|_| bytes > 0)
+        .map_or(String::from("null"), |h| format!("\"{h}\""));
+
+    let line = format!(
+        "{{\"timestamp\":\"{}\",\"client\":\"{client}\",\"user\":{user},\"method\":\"{}\",\"path\":\"{}\",\"destination\":{destination},\"bytes\":{bytes},\"status\":{},\"sha256\":{hash}}}\n",
+        DateTime::now().to_rfc3339(),
+        json_escape(&request.method),
+        json_escape(&request.path),
+        response.map_or(String::from("null"), |r| r.status_code().to_string()),
+    );
+
+    let written = fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&config.logging.audit_log)
+        .and_then(|mut f| f.write_all(line.as_bytes()));
+    if let Err(e) = written {
+        error!("Could not write to the audit log: {e}");
+    }
+}
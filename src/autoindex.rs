@@ -0,0 +1,288 @@
+use std::path::Path;
+use std::time::SystemTime;
+use fs_err as fs;
+
+use crate::config::Config;
+use crate::datetime::DateTime;
+use crate::errors::HttpErrors;
+use crate::escape::{html_escape, json_escape, percent_encode};
+use crate::headers;
+use crate::headers::{
+    HttpContent, HttpContentLengthHeader, HttpContentTypeHeader, HttpHeader, HttpResponse, HttpStatusHeader,
+};
+use crate::media;
+use crate::parser::HttpRequest;
+use crate::requests::is_internal_path;
+
+/// A single entry of a directory listing.
+struct DirEntry {
+    name: String,
+    is_dir: bool,
+    size: u64,
+    modified: SystemTime,
+}
+
+/// Column the listing is sorted by, selected with `?sort=`.
+#[derive(Clone, Copy, PartialEq)]
+enum SortKey {
+    Name,
+    Size,
+    Modified,
+    Type,
+}
+
+impl SortKey {
+    fn from_query(value: Option<&str>) -> Self {
+        match value {
+            Some("size") => SortKey::Size,
+            Some("modified") => SortKey::Modified,
+            Some("type") => SortKey::Type,
+            _ => SortKey::Name,
+        }
+    }
+
+    fn as_str(self) -> &'static str {
+        match self {
+            SortKey::Name => "name",
+            SortKey::Size => "size",
+            SortKey::Modified => "modified",
+            SortKey::Type => "type",
+        }
+    }
+}
+
+/// Returns true if a file name should be hidden from listings.
+pub fn is_hidden(name: &str) -> bool {
+    name.starts_with('.')
+}
+
+/// Generates a listing of `dir`, as HTML or as JSON if requested through
+/// `Accept: application/json` or `?format=json`.
+pub fn autoindex(request: &HttpRequest, dir: &Path, config: &Config) -> Result<HttpResponse, HttpErrors> {
+    let root = fs::canonicalize(&config.server.root).map_err(|e| HttpErrors::FileReadFailure(e.to_string()))?;
+    let mut entries = vec![];
+
+    for entry in fs::read_dir(dir).map_err(|e| HttpErrors::FileReadFailure(e.to_string()))? {
+        let entry = entry.map_err(|e| HttpErrors::FileReadFailure(e.to_string()))?;
+        let name = entry.file_name().to_string_lossy().to_string();
+        if !config.server.autoindex.show_hidden && is_hidden(&name) {
+            continue;
+        }
+        if is_internal_path(&format!("{}/{name}", request.path.trim_end_matches('/')), config) {
+            continue;
+        }
+
+        // Skip symlinks that lead outside of the root and entries that vanished in the meantime.
+        match fs::canonicalize(entry.path()) {
+            Ok(p) if p.starts_with(&root) => (),
+            _ => continue,
+        }
+        let metadata = match fs::metadata(entry.path()) {
+            Ok(m) => m,
+            Err(_) => continue,
+        };
+
+        entries.push(DirEntry {
+            name,
+            is_dir: metadata.is_dir(),
+            size: if metadata.is_dir() { 0 } else { metadata.len() },
+            modified: metadata.modified().unwrap_or(SystemTime::UNIX_EPOCH),
+        });
+    }
+
+    let sort = SortKey::from_query(request.query("sort"));
+    let descending = request.query("order") == Some("desc");
+    entries.sort_by(|a, b| {
+        let ordering = match sort {
+            SortKey::Name => b.is_dir.cmp(&a.is_dir).then_with(|| a.name.cmp(&b.name)),
+            SortKey::Size => a.size.cmp(&b.size),
+            SortKey::Modified => a.modified.cmp(&b.modified),
+            SortKey::Type => b
+                .is_dir
+                .cmp(&a.is_dir)
+                .then_with(|| extension(&a.name).cmp(extension(&b.name)))
+                .then_with(|| a.name.cmp(&b.name)),
+        };
+        if descending { ordering.reverse() } else { ordering }
+    });
+
+    let (content, ext) = if request.query("format") == Some("json") || request.accepts("application/json") {
+        (to_json(&entries), "json")
+    } else {
+        (to_html(&request.path, &entries, sort, descending), "html")
+    };
+
+    let content = HttpContent::from(content);
+    Ok(HttpResponse {
+        headers: headers!(
+            HttpStatusHeader::new_from(200);
+            HttpContentTypeHeader::new_from(media::lookup(ext, &config.mime).unwrap());
+            HttpContentLengthHeader::new_from(content.content.len());
+        )
+        .build(),
+        content,
+    })
+}
+
+fn extension(name: &str) -> &str {
+    Path::new(name).extension().and_then(|e| e.to_str()).unwrap_or_default()
+}
+
+fn to_json(entries: &[DirEntry]) -> String {
+    let entries = entries
+        .iter()
+        .map(|e| {
+            format!(
+                r#"{{"name":"{}","type":"{}","size":{},"modified":"{}"}}"#,
+                json_escape(&e.name),
+                if e.is_dir { "directory" } else { "file" },
+                e.size,
+                DateTime::from(e.modified).to_rfc3339()
+            )
+        })
+        .collect::<Vec<_>>()
+        .join(",");
+    format!("[{entries}]")
+}
+
+fn to_html(path: &str, entries: &[DirEntry], sort: SortKey, descending: bool) -> String {
+    let base = if path.ends_with('/') { path.to_string() } else { format!("{path}/") };
+    let title = html_escape(&base);
+
+    let column = |key: SortKey, label: &str| {
+        let order = if sort == key && !descending { "desc" } else { "asc" };
+        format!(r#"<th><a href="?sort={}&amp;order={order}">{label}</a></th>"#, key.as_str())
+    };
+
+    let mut rows = String::new();
+    if base != "/" {
+        rows.push_str(r#"<tr><td><a href="../">../</a></td><td>-</td><td>-</td><td>directory</td></tr>"#);
+        rows.push('\n');
+    }
+    for entry in entries {
+        let name = if entry.is_dir { format!("{}/", entry.name) } else { entry.name.clone() };
+        rows.push_str(&format!(
+            r#"<tr><td><a href="{}">{}</a></td><td>{}</td><td>{}</td><td>{}</td></tr>"#,
+            html_escape(&percent_encode(&format!("{base}{name}"))),
+            html_escape(&name),
+            if entry.is_dir { String::from("-") } else { entry.size.to_string() },
+            DateTime::from(entry.modified).to_short(),
+            if entry.is_dir { "directory" } else { "file" },
+        ));
+        rows.push('\n');
+    }
+
+    format!(
+        "<!DOCTYPE html>\n<html>\n<head><meta charset=\"utf-8\"><title>Index of {title}</title></head>\n<body>\n<h1>Index of {title}</h1>\n<table>\n<tr>{}{}{}{}</tr>\n{rows}</table>\n</body>\n</html>\n",
+        column(SortKey::Name, "Name"),
+        column(SortKey::Size, "Size"),
+        column(SortKey::Modified, "Modified"),
+        column(SortKey::Type, "Type"),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::autoindex;
+    use crate::config::{for_tests, Config};
+    use crate::parser::HttpRequest;
+    use std::fs;
+    use std::path::PathBuf;
+
+    /// Name of a file with characters that need escaping, as it appears in JSON.
+    const ODD: &str = r#"x&<\"y\">.txt"#;
+
+    /// A root with a few files, a subdirectory, a hidden file and a symlink out of the root.
+    struct Root {
+        path: PathBuf,
+        config: Config,
+    }
+
+    impl Root {
+        fn new(name: &str, show_hidden: bool) -> Self {
+            let path = std::env::temp_dir().join(format!("http-server-rs-{}-autoindex-{name}", std::process::id()));
+            let _ = fs::remove_dir_all(&path);
+            fs::create_dir_all(path.join("dir/sub")).unwrap();
+            fs::create_dir_all(path.join("dir/.tus")).unwrap();
+            fs::write(path.join("dir/b.txt"), "abc").unwrap();
+            fs::write(path.join("dir/a.md"), "0123456789").unwrap();
+            fs::write(path.join("dir/x&<\"y\">.txt"), "ab").unwrap();
+            fs::write(path.join("dir/.hidden"), "secret").unwrap();
+            std::os::unix::fs::symlink("/", path.join("dir/outside")).unwrap();
+
+            let mut config = for_tests(
+                "",
+                &format!("[server.autoindex]\nenable = true\nshow_hidden = {show_hidden}\n[server.tus]\nenable = true\nstaging_dir = \"/dir/.tus\"\n"),
+            );
+            config.server.root = path.display().to_string();
+            Root { path, config }
+        }
+
+        fn list(&self, target: &str, headers: &str) -> String {
+            let request = HttpRequest::parse(&format!("GET {target} HTTP/1.1\r\n{headers}\r\n\r\n")).unwrap();
+            let response = autoindex(&request, &self.path.join("dir"), &self.config).unwrap();
+            String::from_utf8(response.content.content).unwrap()
+        }
+
+        /// Returns the names in a JSON listing, in order.
+        fn names(&self, target: &str) -> Vec<String> {
+            self.list(target, "")
+                .split(r#"{"name":""#)
+                .skip(1)
+                .map(|e| e.split(r#"","type""#).next().unwrap().to_string())
+                .collect()
+        }
+    }
+
+    impl Drop for Root {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.path);
+        }
+    }
+
+    #[test]
+    fn lists_directories_first_then_by_name() {
+        let root = Root::new("order", false);
+        assert_eq!(root.names("/dir?format=json"), vec!["sub", "a.md", "b.txt", ODD]);
+    }
+
+    #[test]
+    fn sorts_by_the_requested_column() {
+        let root = Root::new("sort", false);
+        assert_eq!(root.names("/dir?format=json&sort=size"), vec!["sub", ODD, "b.txt", "a.md"]);
+        assert_eq!(root.names("/dir?format=json&sort=size&order=desc"), vec!["a.md", "b.txt", ODD, "sub"]);
+        assert_eq!(root.names("/dir?format=json&sort=type"), vec!["sub", "a.md", "b.txt", ODD]);
+        assert_eq!(root.names("/dir?format=json&sort=bogus"), root.names("/dir?format=json"));
+    }
+
+    #[test]
+    fn hidden_files_are_listed_only_when_enabled() {
+        assert!(!Root::new("hidden", false).list("/dir", "Accept: application/json").contains(".hidden"));
+        let shown = Root::new("shown", true).names("/dir?format=json");
+        assert!(shown.contains(&String::from(".hidden")));
+        // The staging directory stays hidden either way, so do symlinks out of the root.
+        assert!(!shown.contains(&String::from(".tus")));
+        assert!(!shown.contains(&String::from("outside")));
+    }
+
+    #[test]
+    fn json_describes_entries() {
+        let json = Root::new("json", false).list("/dir", "Accept: application/json");
+        assert!(json.starts_with('[') && json.ends_with(']'));
+        assert!(json.contains(r#"{"name":"sub","type":"directory","size":0,"modified":""#));
+        assert!(json.contains(r#"{"name":"a.md","type":"file","size":10,"modified":""#));
+        assert!(json.contains(&format!(r#""name":"{ODD}""#)));
+    }
+
+    #[test]
+    fn html_escapes_names_and_links() {
+        let html = Root::new("html", false).list("/dir", "");
+        assert!(html.contains("<title>Index of /dir/</title>"));
+        assert!(html.contains(r#"<a href="../">../</a>"#));
+        assert!(html.contains(r#"<a href="/dir/sub/">sub/</a>"#));
+        assert!(html.contains(r#"<a href="/dir/x%26%3C%22y%22%3E.txt">x&amp;&lt;&quot;y&quot;&gt;.txt</a>"#));
+        assert!(html.contains(r#"<a href="?sort=name&amp;order=desc">Name</a>"#));
+        assert!(html.contains(r#"<a href="?sort=size&amp;order=asc">Size</a>"#));
+        assert!(!html.contains(".hidden"));
+    }
+}
@@ -0,0 +1,295 @@
+use std::io::{self, BufReader, Cursor, Read, Write};
+use std::net::TcpStream;
+use sha2::{Digest, Sha256};
+
+use crate::errors::HttpErrors;
+use crate::parser::HttpRequest;
+
+/// Streams the body of a request, whether it is sent with a `Content-Length` or
+/// with `Transfer-Encoding: chunked`. Bytes that were read along with the request
+/// head are returned first.
+pub struct HttpBody<'a> {
+    prefix: Cursor<Vec<u8>>,
+    stream: BufReader<&'a TcpStream>,
+    /// Whether the prefix read along with the head has been used up.
+    prefix_done: bool,
+    framing: Framing,
+    /// `Expect: 100-continue` was sent and the interim response is still owed.
+    continue_pending: bool,
+    /// Amount of body bytes handed out so far.
+    bytes_read: u64,
+    /// Hash of the body bytes handed out so far, if requested through [`HttpBody::hash_content`].
+    hasher: Option<Sha256>,
+}
+
+enum Framing {
+    Length { remaining: u64 },
+    Chunked { remaining: u64, done: bool },
+}
+
+impl<'a> HttpBody<'a> {
+    /// Prepares the body of `request`. `prefix` holds any bytes that were read past the
+    /// end of the head.
+    pub fn new(request: &HttpRequest, prefix: Vec<u8>, stream: &'a TcpStream) -> Result<Self, HttpErrors> {
+        let chunked = request
+            .header("Transfer-Encoding")
+            .is_some_and(|t| t.to_ascii_lowercase().contains("chunked"));
+
+        let framing = if chunked {
+            Framing::Chunked { remaining: 0, done: false }
+        } else {
+            Framing::Length { remaining: request.content_length()?.unwrap_or(0) }
+        };
+
+        Ok(HttpBody {
+            prefix: Cursor::new(prefix),
+            stream: BufReader::new(stream),
+            prefix_done: false,
+            framing,
+            continue_pending: request
+                .header("Expect")
+                .is_some_and(|e| e.eq_ignore_ascii_case("100-continue")),
+            bytes_read: 0,
+            hasher: None,
+        })
+    }
+
+    /// Starts computing the SHA-256 of the body as it is read.
+    pub fn hash_content(&mut self) {
+        self.hasher = Some(Sha256::new());
+    }
+
+    /// Returns the amount of body bytes read so far.
+    pub fn bytes_read(&self) -> u64 {
+        self.bytes_read
+    }
+
+    /// Returns the SHA-256 of the body bytes read so far as hex, if hashing was enabled.
+    pub fn content_hash(&mut self) -> Option<String> {
+        self.hasher
+            .take()
+            .map(|h| h.finalize().iter().map(|b| format!("{b:02x}")).collect())
+    }
+
+    /// Reads a line from the raw body, used to parse chunk sizes.
+    fn read_raw_line(&mut self) -> io::Result<String> {
+        let mut line = vec![];
+        loop {
+            let mut byte = [0; 1];
+            if self.read_raw(&mut byte)? == 0 {
+                return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "truncated chunked body"));
+            }
+            if byte[0] == b'\n' {
+                break;
+            }
+            line.push(byte[0]);
+        }
+        Ok(String::from_utf8_lossy(&line).trim().to_string())
+    }
+
+    /// Reads bytes from the prefix, then from the stream.
+    fn read_raw(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if !self.prefix_done {
+            let read = self.prefix.read(buf)?;
+            if read > 0 {
+                return Ok(read);
+            }
+            self.prefix_done = true;
+        }
+
+        if self.continue_pending {
+            self.continue_pending = false;
+            let mut stream = *self.stream.get_ref();
+            stream.write_all(b"HTTP/1.1 100 Continue\r\n\r\n")?;
+        }
+        self.stream.read(buf)
+    }
+}
+
+impl Read for HttpBody<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.read_content(buf)?;
+        self.bytes_read += read as u64;
+        if let Some(hasher) = &mut self.hasher {
+            hasher.update(&buf[..read]);
+        }
+        Ok(read)
+    }
+}
+
+impl HttpBody<'_> {
+    /// Reads decoded body bytes, undoing the chunked framing if there is one.
+    fn read_content(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
+        match self.framing {
+            Framing::Length { remaining } => {
+                if remaining == 0 {
+                    return Ok(0);
+                }
+                let max = buf.len().min(remaining.try_into().unwrap_or(usize::MAX));
+                let read = self.read_raw(&mut buf[..max])?;
+                if read == 0 {
+                    return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "body shorter than Content-Length"));
+                }
+                self.framing = Framing::Length { remaining: remaining - read as u64 };
+                Ok(read)
+            }
+            Framing::Chunked { done: true, .. } => Ok(0),
+            Framing::Chunked { remaining, .. } => {
+                let mut remaining = remaining;
+                if remaining == 0 {
+                    let line = self.read_raw_line()?;
+                    // `from_str_radix` would also take a sign, as in `+a`.
+                    let size = line.split(';').next().unwrap_or_default().trim();
+                    remaining = Some(size)
+                        .filter(|s| !s.is_empty() && s.bytes().all(|b| b.is_ascii_hexdigit()))
+                        .and_then(|s| u64::from_str_radix(s, 16).ok())
+                        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "invalid chunk size"))?;
+
+                    if remaining == 0 {
+                        // Skip the trailers up to the final empty line.
+                        while !self.read_raw_line()?.is_empty() {}
+                        self.framing = Framing::Chunked { remaining: 0, done: true };
+                        return Ok(0);
+                    }
+                }
+
+                let max = buf.len().min(remaining.try_into().unwrap_or(usize::MAX));
+                let read = self.read_raw(&mut buf[..max])?;
+                if read == 0 {
+                    return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "truncated chunked body"));
+                }
+                remaining -= read as u64;
+                if remaining == 0 && !self.read_raw_line()?.is_empty() {
+                    // Every chunk is followed by a CRLF.
+                    return Err(io::Error::new(io::ErrorKind::InvalidData, "chunk longer than its size"));
+                }
+                self.framing = Framing::Chunked { remaining, done: false };
+                Ok(read)
+            }
+        }
+    }
+}
+
+/// Reads from the stream until the end of the request head. Returns the head and
+/// whatever was read past it, which belongs to the body.
+pub fn read_head(stream: &mut TcpStream, max_length: usize) -> Result<(String, Vec<u8>), HttpErrors> {
+    let mut buf = vec![];
+    let mut chunk = [0; 1024];
+
+    loop {
+        let bytes_read = stream.read(&mut chunk).map_err(|e| HttpErrors::StreamReadFailure(e.to_string()))?;
+        if bytes_read == 0 {
+            return Err(HttpErrors::GeneralReadFailure);
+        }
+        buf.extend_from_slice(&chunk[..bytes_read]);
+
+        if let Some(end) = buf.windows(4).position(|w| w == b"\r\n\r\n") {
+            let rest = buf.split_off(end + 4);
+            let head = String::from_utf8(buf).map_err(|_| HttpErrors::Utf8ConversionFailure)?;
+            return Ok((head, rest));
+        }
+
+        if buf.len() > max_length {
+            return Err(HttpErrors::InvalidRequest(String::from("request head too large")));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::HttpBody;
+    use crate::parser::HttpRequest;
+    use std::io::{ErrorKind, Read, Write};
+    use std::net::{TcpListener, TcpStream};
+
+    /// Reads the body of a request whose head is `head`, with `body` already read along with
+    /// the head and `rest` still to come from the client, which then hangs up.
+    fn read_body(head: &str, body: &[u8], rest: &[u8]) -> std::io::Result<Vec<u8>> {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (stream, _) = listener.accept().unwrap();
+        client.write_all(rest).unwrap();
+        drop(client);
+
+        let request = HttpRequest::parse(&format!("POST / HTTP/1.1\r\n{head}\r\n\r\n")).unwrap();
+        let mut body = HttpBody::new(&request, body.to_vec(), &stream).unwrap();
+        let mut content = vec![];
+        body.read_to_end(&mut content)?;
+        assert_eq!(body.bytes_read(), content.len() as u64);
+        Ok(content)
+    }
+
+    fn chunked(body: &[u8]) -> std::io::Result<Vec<u8>> {
+        read_body("Transfer-Encoding: chunked", body, b"")
+    }
+
+    #[test]
+    fn reads_content_length_bodies() {
+        assert_eq!(read_body("Content-Length: 5", b"hel", b"lo, and more").unwrap(), b"hello");
+        assert_eq!(read_body("", b"ignored", b"").unwrap(), b"");
+        let error = read_body("Content-Length: 10", b"short", b"").unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn decodes_chunks() {
+        assert_eq!(chunked(b"5\r\nhello\r\n7\r\n, world\r\n0\r\n\r\n").unwrap(), b"hello, world");
+        assert_eq!(chunked(b"A\r\n0123456789\r\n0\r\n\r\n").unwrap(), b"0123456789");
+        assert_eq!(chunked(b"0\r\n\r\n").unwrap(), b"");
+        // Bare LFs are accepted too.
+        assert_eq!(chunked(b"2\nab\n0\n\n").unwrap(), b"ab");
+    }
+
+    #[test]
+    fn reads_chunks_across_the_stream() {
+        let body = read_body("Transfer-Encoding: chunked", b"3\r\nab", b"c\r\n3\r\ndef\r\n0\r\n\r\n").unwrap();
+        assert_eq!(body, b"abcdef");
+    }
+
+    #[test]
+    fn skips_extensions_and_trailers() {
+        let body = b"4;name=value\r\nwiki\r\n5 ; quoted=\"a;b\"\r\npedia\r\n0\r\nExpires: never\r\nX-Other: 1\r\n\r\n";
+        assert_eq!(chunked(body).unwrap(), b"wikipedia");
+    }
+
+    #[test]
+    fn rejects_invalid_chunk_sizes() {
+        for body in [&b"x\r\n"[..], b"+a\r\n0123456789\r\n0\r\n\r\n", b"-0\r\n\r\n", b"\r\n", b"0x5\r\nhello\r\n0\r\n\r\n"] {
+            assert_eq!(chunked(body).unwrap_err().kind(), ErrorKind::InvalidData);
+        }
+        // Too large for a u64.
+        assert_eq!(chunked(b"10000000000000000\r\n").unwrap_err().kind(), ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn rejects_chunks_longer_than_their_size() {
+        assert_eq!(chunked(b"2\r\nabc\r\n0\r\n\r\n").unwrap_err().kind(), ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn rejects_truncated_chunks() {
+        assert_eq!(chunked(b"5\r\nhel").unwrap_err().kind(), ErrorKind::UnexpectedEof);
+        assert_eq!(chunked(b"5\r\nhello\r\n").unwrap_err().kind(), ErrorKind::UnexpectedEof);
+        assert_eq!(chunked(b"0\r\nTrailer: 1\r\n").unwrap_err().kind(), ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn hashes_the_decoded_body() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let _client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (stream, _) = listener.accept().unwrap();
+
+        let request = HttpRequest::parse("POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n").unwrap();
+        let mut body = HttpBody::new(&request, b"1\r\na\r\n2\r\nbc\r\n0\r\n\r\n".to_vec(), &stream).unwrap();
+        body.hash_content();
+        body.read_to_end(&mut vec![]).unwrap();
+        assert_eq!(
+            body.content_hash().as_deref(),
+            Some("ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad")
+        );
+    }
+}
@@ -1,7 +1,11 @@
+use std::collections::HashMap;
 use std::io::Read;
 use serde::Deserialize;
 use fs_err as fs;
 use crate::errors::HttpErrors;
+use crate::headers::HttpContentType;
+use crate::media;
+use crate::status;
 
 const CONFIG_FILE_NAME: &str = "config.toml";
 
@@ -10,6 +14,13 @@ pub struct Config {
     pub server: ServerConfig,
     pub logging: LoggingConfig,
     pub extra: ExtraConfig,
+    #[serde(default)]
+    pub mime: MimeConfig,
+    /// Maps status codes to error documents under the root, e.g. `404 = "/errors/404.html"`.
+    #[serde(default)]
+    pub error_pages: HashMap<String, String>,
+    #[serde(default)]
+    pub tracing: TracingConfig,
 }
 
 #[derive(Deserialize)]
@@ -17,27 +28,416 @@ pub struct ServerConfig {
     pub address: String,
     pub port: String,
     pub root: String,
+    // The server handles one connection at a time so far, these are only read from the
+    // config file to keep it valid.
+    #[allow(dead_code)]
     pub threading: ServerThreadingConfig,
+    #[allow(dead_code)]
+    #[serde(rename = "async")]
     pub async_: ServerAsyncConfig,
+    #[serde(default)]
+    pub autoindex: ServerAutoIndexConfig,
+    #[serde(default)]
+    pub uploads: ServerUploadsConfig,
+    #[serde(default)]
+    pub delete: ServerDeleteConfig,
+    #[serde(default)]
+    pub forms: ServerFormsConfig,
+    #[serde(default)]
+    pub tus: ServerTusConfig,
+    #[serde(default)]
+    pub webdav: ServerWebDavConfig,
+    #[serde(default)]
+    pub access: Vec<ServerAccessRule>,
+    #[serde(default)]
+    pub quotas: ServerQuotasConfig,
+    #[serde(default)]
+    pub stats: ServerStatsConfig,
+    #[serde(default)]
+    pub metrics: ServerMetricsConfig,
+    #[serde(default)]
+    pub status: ServerStatusConfig,
+    #[serde(default)]
+    pub server_timing: bool,
+    /// Longest time a request in flight may take to finish on shutdown, in seconds.
+    #[serde(default = "default_drain_timeout")]
+    pub drain_timeout: u64,
+}
+
+fn default_drain_timeout() -> u64 {
+    30
 }
 
 #[derive(Deserialize)]
+#[allow(dead_code)]
 pub struct ServerThreadingConfig {
     pub enable: bool,
     pub max_threads: u32,
 }
 
 #[derive(Deserialize)]
+#[allow(dead_code)]
 pub struct ServerAsyncConfig {
     pub enable: bool,
     pub max_tasks: u32,
 }
 
+#[derive(Deserialize, Default)]
+pub struct ServerAutoIndexConfig {
+    pub enable: bool,
+    #[serde(default)]
+    pub show_hidden: bool,
+}
+
+#[derive(Deserialize, Default)]
+pub struct ServerUploadsConfig {
+    pub enable: bool,
+    #[serde(default)]
+    pub allowed_prefixes: Vec<String>,
+    #[serde(default)]
+    pub create_dirs: bool,
+}
+
+#[derive(Deserialize, Default)]
+pub struct ServerDeleteConfig {
+    #[serde(default)]
+    pub directories: DeleteDirectories,
+    /// Directory under the root that deleted files are moved to, empty to remove them.
+    #[serde(default)]
+    pub trash_dir: String,
+}
+
+#[derive(Deserialize)]
+pub struct ServerFormsConfig {
+    pub enable: bool,
+    #[serde(default = "default_upload_dir")]
+    pub upload_dir: String,
+    #[serde(default = "default_max_part_size")]
+    pub max_part_size: u64,
+    #[serde(default = "default_max_total_size")]
+    pub max_total_size: u64,
+}
+
+impl Default for ServerFormsConfig {
+    fn default() -> Self {
+        ServerFormsConfig {
+            enable: false,
+            upload_dir: default_upload_dir(),
+            max_part_size: default_max_part_size(),
+            max_total_size: default_max_total_size(),
+        }
+    }
+}
+
+fn default_upload_dir() -> String {
+    String::from("/uploads")
+}
+
+fn default_max_part_size() -> u64 {
+    64 * 1024 * 1024
+}
+
+fn default_max_total_size() -> u64 {
+    256 * 1024 * 1024
+}
+
+#[derive(Deserialize)]
+pub struct ServerTusConfig {
+    pub enable: bool,
+    #[serde(default = "default_tus_endpoint")]
+    pub endpoint: String,
+    #[serde(default = "default_tus_staging_dir")]
+    pub staging_dir: String,
+    /// Directory under the root that finished uploads are moved to.
+    #[serde(default = "default_tus_destination_dir")]
+    pub destination_dir: String,
+    #[serde(default)]
+    pub max_size: u64,
+}
+
+impl Default for ServerTusConfig {
+    fn default() -> Self {
+        ServerTusConfig {
+            enable: false,
+            endpoint: default_tus_endpoint(),
+            staging_dir: default_tus_staging_dir(),
+            destination_dir: default_tus_destination_dir(),
+            max_size: 0,
+        }
+    }
+}
+
+fn default_tus_endpoint() -> String {
+    String::from("/files")
+}
+
+fn default_tus_staging_dir() -> String {
+    String::from("/.tus")
+}
+
+fn default_tus_destination_dir() -> String {
+    String::from("/uploads")
+}
+
+#[derive(Deserialize)]
+pub struct ServerWebDavConfig {
+    pub enable: bool,
+    #[serde(default = "default_lock_timeout")]
+    pub lock_timeout: u64,
+}
+
+impl Default for ServerWebDavConfig {
+    fn default() -> Self {
+        ServerWebDavConfig {
+            enable: false,
+            lock_timeout: default_lock_timeout(),
+        }
+    }
+}
+
+fn default_lock_timeout() -> u64 {
+    3600
+}
+
+#[derive(Deserialize, Default)]
+pub struct ServerQuotasConfig {
+    #[serde(default)]
+    pub max_upload_size: u64,
+    #[serde(default)]
+    pub min_free_space: u64,
+    /// Maps directories under the root to the most bytes they may hold.
+    #[serde(default)]
+    pub directories: HashMap<String, u64>,
+}
+
+#[derive(Deserialize)]
+pub struct ServerStatsConfig {
+    pub enable: bool,
+    #[serde(default = "default_stats_path")]
+    pub path: String,
+}
+
+impl Default for ServerStatsConfig {
+    fn default() -> Self {
+        ServerStatsConfig {
+            enable: false,
+            path: default_stats_path(),
+        }
+    }
+}
+
+fn default_stats_path() -> String {
+    String::from("/.stats")
+}
+
+#[derive(Deserialize)]
+pub struct ServerMetricsConfig {
+    pub enable: bool,
+    #[serde(default = "default_metrics_path")]
+    pub path: String,
+    /// Separate address to serve the metrics on, e.g. `127.0.0.1:9100`.
+    #[serde(default)]
+    pub address: String,
+}
+
+impl Default for ServerMetricsConfig {
+    fn default() -> Self {
+        ServerMetricsConfig {
+            enable: false,
+            path: default_metrics_path(),
+            address: String::new(),
+        }
+    }
+}
+
+fn default_metrics_path() -> String {
+    String::from("/metrics")
+}
+
+#[derive(Deserialize)]
+pub struct ServerStatusConfig {
+    pub enable: bool,
+    #[serde(default = "default_healthz_path")]
+    pub healthz_path: String,
+    #[serde(default = "default_readyz_path")]
+    pub readyz_path: String,
+    #[serde(default = "default_status_path")]
+    pub status_path: String,
+    /// Addresses or CIDR networks allowed to see these pages; anyone if empty.
+    #[serde(default)]
+    pub allowed_clients: Vec<String>,
+}
+
+impl Default for ServerStatusConfig {
+    fn default() -> Self {
+        ServerStatusConfig {
+            enable: false,
+            healthz_path: default_healthz_path(),
+            readyz_path: default_readyz_path(),
+            status_path: default_status_path(),
+            allowed_clients: vec![],
+        }
+    }
+}
+
+fn default_healthz_path() -> String {
+    String::from("/healthz")
+}
+
+fn default_readyz_path() -> String {
+    String::from("/readyz")
+}
+
+fn default_status_path() -> String {
+    String::from("/server-status")
+}
+
+/// Methods that may be used on paths under `path`.
+#[derive(Deserialize)]
+pub struct ServerAccessRule {
+    pub path: String,
+    pub methods: Vec<String>,
+}
+
+/// Which directories a `DELETE` request may remove.
+#[derive(Deserialize, Default, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum DeleteDirectories {
+    /// Only files can be deleted.
+    #[default]
+    None,
+    /// Empty directories can be deleted too.
+    Empty,
+    /// Directories are deleted along with everything in them.
+    Recursive,
+}
+
+/// How diagnostic log messages are written.
+#[derive(Deserialize, Default, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    /// Human readable lines.
+    #[default]
+    Text,
+    /// One JSON object per line, with a single record per request.
+    Json,
+}
+
+/// System logging daemon the log is also sent to.
+#[derive(Deserialize, Default, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum SystemLogProtocol {
+    #[default]
+    None,
+    /// RFC 5424 messages to the syslog socket.
+    Syslog,
+    /// journald's native protocol, with structured fields.
+    Journald,
+}
+
+/// When log files are rotated regardless of their size.
+#[derive(Deserialize, Default, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum RotateInterval {
+    #[default]
+    Never,
+    Hourly,
+    Daily,
+}
+
 #[derive(Deserialize)]
 pub struct LoggingConfig {
     pub default_level: String,
     pub log_file_level: String,
     pub log_file: String,
+    /// Maps modules, e.g. `requests`, to the level they log at, overriding `default_level`
+    /// and `log_file_level`.
+    #[serde(default)]
+    pub modules: HashMap<String, String>,
+    #[serde(default)]
+    pub format: LogFormat,
+    #[serde(default)]
+    pub access_log: String,
+    #[serde(default = "default_access_log_format")]
+    pub access_log_format: String,
+    #[serde(default)]
+    pub audit_log: String,
+    #[serde(default)]
+    pub user_header: String,
+    #[serde(default)]
+    pub rotate_size: u64,
+    #[serde(default)]
+    pub rotate_interval: RotateInterval,
+    #[serde(default = "default_rotate_keep")]
+    pub rotate_keep: u32,
+    #[serde(default)]
+    pub rotate_compress: bool,
+    #[serde(default)]
+    pub system_log: SystemLogProtocol,
+    #[serde(default = "default_system_log_level")]
+    pub system_log_level: String,
+    #[serde(default)]
+    pub system_log_socket: String,
+    #[serde(default = "default_syslog_facility")]
+    pub syslog_facility: String,
+}
+
+fn default_system_log_level() -> String {
+    String::from("info")
+}
+
+fn default_syslog_facility() -> String {
+    String::from("daemon")
+}
+
+fn default_rotate_keep() -> u32 {
+    7
+}
+
+fn default_access_log_format() -> String {
+    String::from("combined")
+}
+
+#[derive(Deserialize)]
+pub struct TracingConfig {
+    pub enable: bool,
+    #[serde(default = "default_tracing_endpoint")]
+    pub endpoint: String,
+    #[serde(default = "default_service_name")]
+    pub service_name: String,
+    #[serde(default = "default_batch_size")]
+    pub batch_size: usize,
+    /// Longest time spans wait to be exported, in seconds.
+    #[serde(default = "default_flush_interval")]
+    pub flush_interval: u64,
+}
+
+impl Default for TracingConfig {
+    fn default() -> Self {
+        TracingConfig {
+            enable: false,
+            endpoint: default_tracing_endpoint(),
+            service_name: default_service_name(),
+            batch_size: default_batch_size(),
+            flush_interval: default_flush_interval(),
+        }
+    }
+}
+
+fn default_tracing_endpoint() -> String {
+    String::from("http://127.0.0.1:4318/v1/traces")
+}
+
+fn default_service_name() -> String {
+    String::from("http-server-rs")
+}
+
+fn default_batch_size() -> usize {
+    512
+}
+
+fn default_flush_interval() -> u64 {
+    5
 }
 
 #[derive(Deserialize)]
@@ -45,12 +445,84 @@ pub struct ExtraConfig {
     pub panic_if_not_impl: bool,
 }
 
+#[derive(Deserialize)]
+pub struct MimeConfig {
+    #[serde(default)]
+    pub files: Vec<String>,
+    #[serde(default = "default_mime_type")]
+    pub default_type: String,
+    #[serde(default)]
+    pub types: HashMap<String, String>,
+    #[serde(default)]
+    pub sniff: bool,
+    #[serde(default = "default_charset")]
+    pub charset: String,
+    /// Extension table built from `files` and `types` by [`media::load_table`].
+    #[serde(skip)]
+    pub table: HashMap<String, HttpContentType>,
+}
+
+impl Default for MimeConfig {
+    fn default() -> Self {
+        MimeConfig {
+            files: vec![],
+            default_type: default_mime_type(),
+            types: HashMap::new(),
+            sniff: false,
+            charset: default_charset(),
+            table: HashMap::new(),
+        }
+    }
+}
+
+fn default_mime_type() -> String {
+    String::from("application/octet-stream")
+}
+
+fn default_charset() -> String {
+    String::from("utf-8")
+}
+
 pub fn parse() -> Result<Config, HttpErrors>  {
     let mut config_file = fs::File::open(format!("./{CONFIG_FILE_NAME}")).map_err(|e| HttpErrors::ConfigReadFailure(e.to_string()))?;
     let mut config: String = String::new();
     config_file.read_to_string(&mut config).map_err(|e| HttpErrors::ConfigReadFailure(e.to_string()))?;
+    from_str(&config)
+}
+
+/// Parses and validates the contents of a configuration file.
+fn from_str(config: &str) -> Result<Config, HttpErrors> {
+    let mut toml = toml::from_str::<Config>(config).map_err(|e| HttpErrors::ConfigParseFailure(e.to_string()))?;
+
+    if media::parse_content_type(&toml.mime.default_type).is_none() {
+        return Err(HttpErrors::ConfigParseFailure(format!("invalid default content type `{}`", toml.mime.default_type)));
+    }
+    toml.mime.table = media::load_table(&toml.mime)?;
 
-    let toml = toml::from_str::<Config>(&config).map_err(|e| HttpErrors::ConfigParseFailure(e.to_string()))?;
+    for rule in &mut toml.server.access {
+        if !rule.path.starts_with('/') {
+            return Err(HttpErrors::ConfigParseFailure(format!("access path `{}` doesn't start with `/`", rule.path)));
+        }
+        rule.methods.iter_mut().for_each(|m| m.make_ascii_uppercase());
+    }
+
+    if let Some(client) = toml.server.status.allowed_clients.iter().find(|c| status::parse_network(c).is_none()) {
+        return Err(HttpErrors::ConfigParseFailure(format!("invalid status client address `{client}`")));
+    }
 
     Ok(toml)
 }
+
+/// Builds a configuration for tests: `server` is added to the `[server]` table and `rest`
+/// after the required sections, which are otherwise left at their defaults.
+#[cfg(test)]
+pub fn for_tests(server: &str, rest: &str) -> Config {
+    from_str(&format!(
+        "[server]\naddress = \"127.0.0.1\"\nport = \"0\"\nroot = \".\"\n{server}\n\
+         [server.threading]\nenable = false\nmax_threads = 1\n\
+         [server.async]\nenable = false\nmax_tasks = 1\n\
+         [logging]\ndefault_level = \"off\"\nlog_file_level = \"off\"\nlog_file = \"\"\n\
+         [extra]\npanic_if_not_impl = false\n{rest}"
+    ))
+    .unwrap()
+}
@@ -0,0 +1,244 @@
+use std::fs::Metadata;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use fs_err as fs;
+
+use crate::config::{Config, DeleteDirectories};
+use crate::datetime::DateTime;
+use crate::error_pages::error_response;
+use crate::errors::HttpErrors;
+use crate::headers::{HttpResponse, CONFLICT, FORBIDDEN, NOT_FOUND, NO_CONTENT, PRECONDITION_FAILED, UNAUTHORIZED};
+use crate::parser::HttpRequest;
+use crate::preconditions;
+use crate::quota;
+use crate::requests::{empty_response, to_local_path};
+use crate::upload::{is_under_root, is_writable, matches_prefix};
+
+/// Removes a file or directory under the root, or moves it into the trash directory if one
+/// is configured.
+pub fn delete(request: &HttpRequest, config: &Config) -> Result<HttpResponse, HttpErrors> {
+    if request.path.contains("..") {
+        return Ok(error_response(UNAUTHORIZED.code, request, config));
+    }
+
+    if !is_writable(&request.path, config) || request.path.trim_end_matches('/').is_empty() {
+        return Ok(error_response(FORBIDDEN.code, request, config));
+    }
+    if is_trash_path(&request.path, config) {
+        return Ok(error_response(NOT_FOUND.code, request, config));
+    }
+
+    let target = PathBuf::from(to_local_path(&config.server.root, request.path.trim_end_matches('/')));
+    let metadata = match fs::symlink_metadata(&target) {
+        Ok(m) => m,
+        Err(_) => return Ok(error_response(NOT_FOUND.code, request, config)),
+    };
+
+    if !is_under_root(target.parent().unwrap_or(Path::new(".")), config) {
+        return Ok(error_response(UNAUTHORIZED.code, request, config));
+    }
+
+    if !preconditions::check(request, Some(&metadata)) {
+        return Ok(error_response(PRECONDITION_FAILED.code, request, config));
+    }
+
+    // Only walk the tree when its size matters to a quota.
+    let size = if quota::is_limited(&request.path, config) { quota::directory_size(&target) } else { 0 };
+    if let Some(code) = remove(&target, &metadata, config)? {
+        return Ok(error_response(code, request, config));
+    }
+    quota::record_removal(&request.path, size);
+    if !config.server.delete.trash_dir.is_empty() {
+        quota::record_write(&config.server.delete.trash_dir, size, 0);
+    }
+
+    Ok(empty_response(NO_CONTENT.code))
+}
+
+/// Returns true if `path` lies in the trash directory, whose contents aren't served or listed.
+pub fn is_trash_path(path: &str, config: &Config) -> bool {
+    !config.server.delete.trash_dir.is_empty() && matches_prefix(path, &config.server.delete.trash_dir)
+}
+
+/// Removes `target`, or moves it into the trash directory, as `server.delete` allows.
+/// Returns the status code to answer with if the policy forbids it.
+pub fn remove(target: &Path, metadata: &Metadata, config: &Config) -> Result<Option<u16>, HttpErrors> {
+    let options = &config.server.delete;
+    let trash = PathBuf::from(to_local_path(&config.server.root, &options.trash_dir));
+    if !options.trash_dir.is_empty() && (options.trash_dir.contains("..") || !is_under_root(&trash, config)) {
+        return Ok(Some(UNAUTHORIZED.code));
+    }
+    if metadata.is_dir() {
+        match options.directories {
+            DeleteDirectories::None => return Ok(Some(FORBIDDEN.code)),
+            // Renaming doesn't care whether the directory is empty, so that's checked first.
+            DeleteDirectories::Empty if !options.trash_dir.is_empty() && !is_empty_dir(target)? => {
+                return Ok(Some(CONFLICT.code))
+            }
+            _ => (),
+        }
+    }
+
+    if !options.trash_dir.is_empty() {
+        move_to_trash(target, &trash)?;
+        return Ok(None);
+    }
+
+    let removed = if !metadata.is_dir() {
+        fs::remove_file(target)
+    } else if options.directories == DeleteDirectories::Empty {
+        fs::remove_dir(target)
+    } else {
+        fs::remove_dir_all(target)
+    };
+    match removed {
+        Ok(()) => Ok(None),
+        Err(e) if e.kind() == ErrorKind::DirectoryNotEmpty => Ok(Some(CONFLICT.code)),
+        Err(e) => Err(HttpErrors::FileWriteFailure(e.to_string())),
+    }
+}
+
+fn is_empty_dir(dir: &Path) -> Result<bool, HttpErrors> {
+    Ok(fs::read_dir(dir)
+        .map_err(|e| HttpErrors::FileReadFailure(e.to_string()))?
+        .next()
+        .is_none())
+}
+
+/// Moves `target` into the trash directory, prefixing its name with the current time so
+/// repeated deletions of the same path don't collide.
+fn move_to_trash(target: &Path, trash: &Path) -> Result<(), HttpErrors> {
+    fs::create_dir_all(trash).map_err(|e| HttpErrors::FileWriteFailure(e.to_string()))?;
+
+    let name = target.file_name().unwrap_or_default().to_string_lossy();
+    let timestamp = DateTime::now().to_compact();
+    let mut destination = trash.join(format!("{timestamp}-{name}"));
+    let mut counter = 1;
+    while fs::symlink_metadata(&destination).is_ok() {
+        destination = trash.join(format!("{timestamp}-{counter}-{name}"));
+        counter += 1;
+    }
+
+    fs::rename(target, &destination).map_err(|e| HttpErrors::FileWriteFailure(e.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{is_trash_path, remove};
+    use crate::config::{for_tests, Config};
+    use std::fs;
+    use std::path::PathBuf;
+
+    /// A root directory holding a file, an empty directory and a directory with a file in it.
+    struct Root {
+        path: PathBuf,
+        config: Config,
+    }
+
+    impl Root {
+        fn new(name: &str, directories: &str, trash_dir: &str) -> Self {
+            let path = std::env::temp_dir().join(format!("http-server-rs-{}-delete-{name}", std::process::id()));
+            let _ = fs::remove_dir_all(&path);
+            fs::create_dir_all(path.join("empty")).unwrap();
+            fs::create_dir_all(path.join("full")).unwrap();
+            fs::write(path.join("file"), "a").unwrap();
+            fs::write(path.join("full/file"), "b").unwrap();
+
+            let mut config = for_tests(
+                "",
+                &format!("[server.delete]\ndirectories = \"{directories}\"\ntrash_dir = \"{trash_dir}\"\n"),
+            );
+            config.server.root = path.display().to_string();
+            Root { path, config }
+        }
+
+        /// Removes `name` and returns the refusal status, if any, and whether it's gone.
+        fn remove(&self, name: &str) -> (Option<u16>, bool) {
+            let target = self.path.join(name);
+            let metadata = fs::symlink_metadata(&target).unwrap();
+            let refused = remove(&target, &metadata, &self.config).unwrap();
+            (refused, !target.exists())
+        }
+
+        fn trash(&self) -> Vec<String> {
+            let mut names: Vec<_> = fs::read_dir(self.path.join(".trash"))
+                .map(|entries| entries.flatten().map(|e| e.file_name().to_string_lossy().to_string()).collect())
+                .unwrap_or_default();
+            names.sort();
+            names
+        }
+    }
+
+    impl Drop for Root {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.path);
+        }
+    }
+
+    #[test]
+    fn none_policy_only_removes_files() {
+        let root = Root::new("none", "none", "");
+        assert_eq!(root.remove("file"), (None, true));
+        assert_eq!(root.remove("empty"), (Some(403), false));
+        assert_eq!(root.remove("full"), (Some(403), false));
+    }
+
+    #[test]
+    fn empty_policy_removes_empty_directories() {
+        let root = Root::new("empty", "empty", "");
+        assert_eq!(root.remove("full"), (Some(409), false));
+        assert!(root.path.join("full/file").exists());
+        assert_eq!(root.remove("empty"), (None, true));
+        assert_eq!(root.remove("file"), (None, true));
+    }
+
+    #[test]
+    fn recursive_policy_removes_everything() {
+        let root = Root::new("recursive", "recursive", "");
+        assert_eq!(root.remove("full"), (None, true));
+        assert_eq!(root.remove("empty"), (None, true));
+    }
+
+    #[test]
+    fn trash_follows_the_same_policy() {
+        let root = Root::new("trash-empty", "empty", "/.trash");
+        assert_eq!(root.remove("full"), (Some(409), false));
+        assert_eq!(root.remove("empty"), (None, true));
+        assert_eq!(root.remove("file"), (None, true));
+
+        let trash = root.trash();
+        assert_eq!(trash.len(), 2);
+        assert!(trash.iter().any(|n| n.ends_with("-empty")));
+        assert!(trash.iter().any(|n| n.ends_with("-file")));
+    }
+
+    #[test]
+    fn repeated_deletions_do_not_collide_in_the_trash() {
+        let root = Root::new("trash-repeat", "recursive", "/.trash/");
+        for _ in 0..3 {
+            fs::write(root.path.join("file"), "a").unwrap();
+            assert_eq!(root.remove("file"), (None, true));
+        }
+        assert_eq!(root.trash().len(), 3);
+    }
+
+    #[test]
+    fn trash_stays_under_the_root() {
+        let root = Root::new("trash-escape", "recursive", "/../trash");
+        assert_eq!(root.remove("file"), (Some(401), false));
+
+
+        let root = Root::new("trash-link", "recursive", "/link/trash");
+        std::os::unix::fs::symlink(std::env::temp_dir(), root.path.join("link")).unwrap();
+        assert_eq!(root.remove("file"), (Some(401), false));
+    }
+
+    #[test]
+    fn trash_paths_are_recognised() {
+        let root = Root::new("trash-paths", "none", "/.trash");
+        assert!(is_trash_path("/.trash", &root.config));
+        assert!(is_trash_path("/.trash/x", &root.config));
+        assert!(!is_trash_path("/.trashcan", &root.config));
+        assert!(!is_trash_path("/.trash", &Root::new("no-trash", "none", "").config));
+    }
+}
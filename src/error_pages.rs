@@ -0,0 +1,167 @@
+use log::warn;
+use std::io::Read;
+use fs_err as fs;
+
+use crate::config::Config;
+use crate::escape::{html_escape, json_escape};
+use crate::headers::{
+    HttpContent, HttpContentLengthHeader, HttpContentType, HttpContentTypeHeader, HttpHeader, HttpResponse,
+    HttpStatusCode, HttpStatusHeader,
+};
+use crate::media;
+use crate::parser::HttpRequest;
+use crate::requests::{extension, to_local_path};
+use crate::{headers, status_code_string};
+
+/// Builds the response for an error status code. Serves the error document configured in
+/// `[error_pages]` if there is one, otherwise a built-in page in the format the client
+/// prefers (HTML, RFC 9457 problem details as JSON, or plain text).
+pub fn error_response(code: u16, request: &HttpRequest, config: &Config) -> HttpResponse {
+    let status = HttpStatusCode::new_from(code);
+
+    if let Some(page) = config.error_pages.get(&code.to_string()) {
+        match read_error_page(page, config) {
+            Some((content, content_type)) => return build(code, content, content_type),
+            None => warn!("Could not read error page `{page}` for status {code}, using the built-in page"),
+        }
+    }
+
+    if request.accepts("text/html") {
+        let content = format!(
+            "<!DOCTYPE html>\n<html>\n<head><meta charset=\"utf-8\"><title>{code} {phrase}</title>\n<style>\nbody {{ font-family: sans-serif; margin: 4em auto; max-width: 40em; color: #333; }}\nh1 {{ font-weight: normal; border-bottom: 1px solid #ccc; padding-bottom: 0.3em; }}\nspan {{ color: #999; }}\n</style></head>\n<body>\n<h1><span>{code}</span> {phrase}</h1>\n<p>{path}</p>\n</body>\n</html>\n",
+            phrase = html_escape(status.phrase),
+            path = html_escape(&request.path),
+        );
+        build(code, HttpContent::from(content), media::lookup("html", &config.mime).unwrap())
+    } else if request.accepts("application/problem+json") || request.accepts("application/json") {
+        let content = format!(
+            r#"{{"type":"about:blank","title":"{}","status":{code},"instance":"{}"}}"#,
+            json_escape(status.phrase),
+            json_escape(&request.path),
+        );
+        build(code, HttpContent::from(content), media::parse_content_type("application/problem+json").unwrap())
+    } else {
+        let content = status_code_string!(status.code, status.phrase);
+        build(code, HttpContent::from(content), media::lookup("txt", &config.mime).unwrap())
+    }
+}
+
+/// Reads a configured error document from under the root.
+fn read_error_page(page: &str, config: &Config) -> Option<(HttpContent, HttpContentType)> {
+    if page.contains("..") {
+        return None;
+    }
+
+    let path = to_local_path(&config.server.root, page);
+    let mut content = vec![];
+    fs::File::open(&path).ok()?.read_to_end(&mut content).ok()?;
+    let (content_type, _) = media::resolve(extension(&path), &content, &config.mime);
+
+    Some((HttpContent::from(content), content_type))
+}
+
+fn build(code: u16, content: HttpContent, content_type: HttpContentType) -> HttpResponse {
+    HttpResponse {
+        headers: headers!(
+            HttpStatusHeader::new_from(code);
+            HttpContentTypeHeader::new_from(content_type);
+            HttpContentLengthHeader::new_from(content.content.len());
+        )
+        .build(),
+        content,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::error_response;
+    use crate::config::{for_tests, Config};
+    use crate::headers::HttpResponse;
+    use crate::parser::HttpRequest;
+    use std::fs;
+    use std::path::PathBuf;
+
+    /// A root directory holding an error document, with `404` and `500` pages configured.
+    struct Root {
+        path: PathBuf,
+        config: Config,
+    }
+
+    impl Root {
+        fn new(name: &str) -> Self {
+            let path = std::env::temp_dir().join(format!("http-server-rs-{}-error-pages-{name}", std::process::id()));
+            let _ = fs::remove_dir_all(&path);
+            fs::create_dir_all(path.join("errors")).unwrap();
+            fs::write(path.join("errors/404.html"), "<p>gone</p>").unwrap();
+
+            let mut config = for_tests("", "");
+            config.server.root = path.display().to_string();
+            config.error_pages.insert(String::from("404"), String::from("/errors/404.html"));
+            config.error_pages.insert(String::from("500"), String::from("/errors/missing.html"));
+            config.error_pages.insert(String::from("403"), String::from("/../etc/passwd"));
+            Root { path, config }
+        }
+
+        fn respond(&self, code: u16, accept: &str) -> HttpResponse {
+            let request = HttpRequest::parse(&format!("GET /a%3Cb HTTP/1.1\r\nAccept: {accept}\r\n\r\n")).unwrap();
+            error_response(code, &request, &self.config)
+        }
+    }
+
+    impl Drop for Root {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.path);
+        }
+    }
+
+    fn body(response: &HttpResponse) -> &str {
+        std::str::from_utf8(&response.content.content).unwrap()
+    }
+
+    #[test]
+    fn serves_the_configured_page() {
+        let root = Root::new("configured");
+        let response = root.respond(404, "application/json");
+        assert_eq!(response.status_code(), 404);
+        assert_eq!(body(&response), "<p>gone</p>");
+        assert!(response.headers.contains("Content-Type: text/html; charset=utf-8\r\n"));
+        assert!(response.headers.contains("Content-Length: 11\r\n"));
+    }
+
+    #[test]
+    fn falls_back_to_the_built_in_page() {
+        let root = Root::new("fallback");
+
+        // The page doesn't exist.
+        let response = root.respond(500, "text/html");
+        assert_eq!(response.status_code(), 500);
+        assert!(body(&response).contains("<h1><span>500</span> Internal Server Error</h1>"));
+
+        // The page is outside the root.
+        let response = root.respond(403, "");
+        assert_eq!(response.status_code(), 403);
+        assert!(!body(&response).contains("root:"));
+    }
+
+    #[test]
+    fn negotiates_the_built_in_format() {
+        let root = Root::new("negotiate");
+
+        let html = root.respond(405, "text/html,application/json;q=0.9");
+        assert!(html.headers.contains("Content-Type: text/html; charset=utf-8\r\n"));
+        assert!(body(&html).contains("<p>/a&lt;b</p>"));
+
+        let json = root.respond(405, "application/problem+json");
+        assert!(json.headers.contains("Content-Type: application/problem+json\r\n"));
+        assert_eq!(
+            body(&json),
+            r#"{"type":"about:blank","title":"Method Not Allowed","status":405,"instance":"/a<b"}"#
+        );
+        assert!(root.respond(405, "application/json").headers.contains("application/problem+json"));
+
+        let text = root.respond(405, "*/*");
+        assert!(text.headers.contains("Content-Type: text/plain; charset=utf-8\r\n"));
+        assert_eq!(text.status_code(), 405);
+        assert!(body(&text).contains("405"));
+    }
+}
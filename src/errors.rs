@@ -40,6 +40,12 @@ pub enum HttpErrors {
     #[error("Invalid request: `{0}`")]
     InvalidRequest(String),
 
+    #[error("Failed to set up logging: `{0}`")]
+    LoggerInitFailure(String),
+
+    #[error("Request body too large: `{0}`")]
+    PayloadTooLarge(String),
+
     #[error("Requester attempted to access path outside authorized root: `{0}`")]
     UnauthorizedPath(String),
 
@@ -49,6 +55,9 @@ pub enum HttpErrors {
     #[error("Failed to read file: `{0}`")]
     FileReadFailure(String),
 
+    #[error("Failed to write file: `{0}`")]
+    FileWriteFailure(String),
+
     #[error("Failed to read configuration file: `{0}`")]
     ConfigReadFailure(String),
 
@@ -0,0 +1,268 @@
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use fs_err as fs;
+
+use crate::body::HttpBody;
+use crate::config::Config;
+use crate::error_pages::error_response;
+use crate::errors::HttpErrors;
+use crate::escape::{html_escape, json_escape, percent_encode};
+use crate::headers;
+use crate::headers::{
+    HttpContent, HttpContentLengthHeader, HttpContentTypeHeader, HttpHeader, HttpResponse, HttpStatusHeader,
+    BAD_REQUEST, FORBIDDEN, OK, PAYLOAD_TOO_LARGE, UNAUTHORIZED, UNSUPPORTED_MEDIA_TYPE,
+};
+use crate::media;
+use crate::multipart::MultipartParser;
+use crate::parser::{parse_urlencoded, HttpRequest};
+use crate::quota;
+use crate::requests::to_local_path;
+use crate::upload::{create_temp_file, is_under_root, is_writable};
+
+/// Name and value of every field of a form, in submission order.
+type FormFields = Vec<(String, String)>;
+
+/// A file that was saved out of a `multipart/form-data` body.
+struct SavedFile {
+    field: String,
+    filename: String,
+    path: String,
+    size: u64,
+}
+
+/// Handles an HTML form submission, either `application/x-www-form-urlencoded` or
+/// `multipart/form-data`. Files are saved into the configured upload directory and a
+/// summary of the submission is returned as HTML, or as JSON if the client prefers it.
+pub fn post(request: &HttpRequest, body: &mut HttpBody, config: &Config) -> Result<HttpResponse, HttpErrors> {
+    let content_type = request.header("Content-Type").unwrap_or_default();
+    let parsed = media::parse_content_type(content_type);
+    let options = &config.server.forms;
+
+    let allowance = quota::allowance(&options.upload_dir, 0, config);
+    if !allowance.permits(request.content_length()?.unwrap_or(0)) {
+        return Ok(error_response(allowance.code, request, config));
+    }
+
+    let result = match parsed.as_ref().map(|c| (c.first.as_ref(), c.second.as_ref())) {
+        Some(("application", "x-www-form-urlencoded")) => read_urlencoded(body, options.max_total_size).map(|f| (f, vec![])),
+        // Files are saved like a PUT to the upload directory would, so the same rules apply.
+        Some(("multipart", "form-data")) if !is_writable(&options.upload_dir, config) => {
+            return Ok(error_response(FORBIDDEN.code, request, config))
+        }
+        Some(("multipart", "form-data")) => read_multipart(request, body, allowance.max, config),
+        _ => return Ok(error_response(UNSUPPORTED_MEDIA_TYPE.code, request, config)),
+    };
+
+    let (fields, files) = match result {
+        Ok(r) => r,
+        Err(HttpErrors::PayloadTooLarge(_)) => return Ok(error_response(PAYLOAD_TOO_LARGE.code, request, config)),
+        Err(HttpErrors::InvalidRequest(_)) => return Ok(error_response(BAD_REQUEST.code, request, config)),
+        Err(HttpErrors::UnauthorizedPath(_)) => return Ok(error_response(UNAUTHORIZED.code, request, config)),
+        Err(e) => return Err(e),
+    };
+    quota::record_write(&options.upload_dir, files.iter().map(|f| f.size).sum(), 0);
+
+    let (content, ext) = if request.accepts("application/json") {
+        (summary_json(&fields, &files), "json")
+    } else {
+        (summary_html(&fields, &files), "html")
+    };
+
+    let content = HttpContent::from(content);
+    Ok(HttpResponse {
+        headers: headers!(
+            HttpStatusHeader::new_from(OK.code);
+            HttpContentTypeHeader::new_from(media::lookup(ext, &config.mime).unwrap());
+            HttpContentLengthHeader::new_from(content.content.len());
+        )
+        .build(),
+        content,
+    })
+}
+
+fn read_urlencoded(body: &mut impl Read, max_total_size: u64) -> Result<FormFields, HttpErrors> {
+    let mut raw = vec![];
+    body.take(max_total_size + 1)
+        .read_to_end(&mut raw)
+        .map_err(|e| HttpErrors::StreamReadFailure(e.to_string()))?;
+
+    if raw.len() as u64 > max_total_size {
+        return Err(HttpErrors::PayloadTooLarge(format!("body exceeds {max_total_size} bytes")));
+    }
+
+    let raw = String::from_utf8(raw).map_err(|_| HttpErrors::InvalidRequest(String::from("form is not UTF-8")))?;
+    Ok(parse_urlencoded(&raw))
+}
+
+/// Saves the files of a multipart body. `max_written` is how many bytes the upload quotas
+/// allow; bodies without a `Content-Length` that go over it fail with `413`.
+fn read_multipart(
+    request: &HttpRequest,
+    body: &mut HttpBody,
+    max_written: u64,
+    config: &Config,
+) -> Result<(FormFields, Vec<SavedFile>), HttpErrors> {
+    let options = &config.server.forms;
+    let content_type = request.header("Content-Type").unwrap_or_default();
+    let mut parser = MultipartParser::new(body, content_type, options.max_total_size.min(max_written))?;
+
+    let upload_dir = PathBuf::from(to_local_path(&config.server.root, &options.upload_dir));
+    if options.upload_dir.contains("..") || !is_under_root(&upload_dir, config) {
+        return Err(HttpErrors::UnauthorizedPath(options.upload_dir.clone()));
+    }
+
+    let mut fields = vec![];
+    let mut files: Vec<SavedFile> = vec![];
+
+    let result = (|| {
+        while let Some(part) = parser.next_part()? {
+            let Some(filename) = part.filename.as_deref().map(sanitize_filename) else {
+                let mut value = vec![];
+                parser.copy_part(&mut value, options.max_part_size)?;
+                fields.push((part.name, String::from_utf8_lossy(&value).to_string()));
+                continue;
+            };
+
+            // Browsers send an empty part when no file was picked.
+            if filename.is_empty() {
+                parser.copy_part(&mut std::io::sink(), options.max_part_size)?;
+                continue;
+            }
+
+            let target = unique_path(&upload_dir, &filename);
+            let (mut file, temp) = create_temp_file(&target)?;
+            let saved = parser
+                .copy_part(&mut file, options.max_part_size)
+                .and_then(|size| file.flush().map(|_| size).map_err(|e| HttpErrors::FileWriteFailure(e.to_string())))
+                .and_then(|size| fs::rename(&temp, &target).map(|_| size).map_err(|e| HttpErrors::FileWriteFailure(e.to_string())));
+
+            match saved {
+                Ok(size) => files.push(SavedFile {
+                    field: part.name,
+                    filename: part.filename.unwrap_or_default(),
+                    path: format!(
+                        "{}/{}",
+                        options.upload_dir.trim_end_matches('/'),
+                        target.file_name().unwrap_or_default().to_string_lossy()
+                    ),
+                    size,
+                }),
+                Err(e) => {
+                    let _ = fs::remove_file(&temp);
+                    return Err(e);
+                }
+            }
+        }
+        Ok(())
+    })();
+
+    // Don't leave half of a failed submission behind.
+    if let Err(e) = result {
+        for file in &files {
+            let _ = fs::remove_file(to_local_path(&config.server.root, &file.path));
+        }
+        return Err(e);
+    }
+
+    Ok((fields, files))
+}
+
+/// Strips directories and leading dots from a client provided file name.
+pub fn sanitize_filename(filename: &str) -> String {
+    filename
+        .rsplit(['/', '\\'])
+        .next()
+        .unwrap_or_default()
+        .trim_start_matches('.')
+        .chars()
+        .filter(|c| !c.is_control())
+        .collect()
+}
+
+/// Returns a path in `dir` for `filename` that doesn't exist yet, adding a counter
+/// before the extension if needed (e.g. `report-1.pdf`).
+pub fn unique_path(dir: &Path, filename: &str) -> PathBuf {
+    let candidate = dir.join(filename);
+    if fs::symlink_metadata(&candidate).is_err() {
+        return candidate;
+    }
+
+    let (stem, ext) = match filename.rsplit_once('.') {
+        Some((stem, ext)) if !stem.is_empty() => (stem, format!(".{ext}")),
+        _ => (filename, String::new()),
+    };
+    (1..)
+        .map(|i| dir.join(format!("{stem}-{i}{ext}")))
+        .find(|p| fs::symlink_metadata(p).is_err())
+        .unwrap()
+}
+
+fn summary_json(fields: &[(String, String)], files: &[SavedFile]) -> String {
+    let fields = fields
+        .iter()
+        .map(|(name, value)| format!(r#"{{"name":"{}","value":"{}"}}"#, json_escape(name), json_escape(value)))
+        .collect::<Vec<_>>()
+        .join(",");
+    let files = files
+        .iter()
+        .map(|f| {
+            format!(
+                r#"{{"field":"{}","filename":"{}","path":"{}","size":{}}}"#,
+                json_escape(&f.field),
+                json_escape(&f.filename),
+                json_escape(&f.path),
+                f.size
+            )
+        })
+        .collect::<Vec<_>>()
+        .join(",");
+    format!(r#"{{"fields":[{fields}],"files":[{files}]}}"#)
+}
+
+fn summary_html(fields: &[(String, String)], files: &[SavedFile]) -> String {
+    let mut rows = String::new();
+    for (name, value) in fields {
+        rows.push_str(&format!("<tr><td>{}</td><td>{}</td></tr>\n", html_escape(name), html_escape(value)));
+    }
+
+    let mut file_rows = String::new();
+    for file in files {
+        file_rows.push_str(&format!(
+            "<tr><td>{}</td><td>{}</td><td><a href=\"{}\">{}</a></td><td>{}</td></tr>\n",
+            html_escape(&file.field),
+            html_escape(&file.filename),
+            html_escape(&percent_encode(&file.path)),
+            html_escape(&file.path),
+            file.size
+        ));
+    }
+
+    format!(
+        "<!DOCTYPE html>\n<html>\n<head><meta charset=\"utf-8\"><title>Form received</title></head>\n<body>\n<h1>Form received</h1>\n<h2>Fields</h2>\n<table>\n<tr><th>Name</th><th>Value</th></tr>\n{rows}</table>\n<h2>Files</h2>\n<table>\n<tr><th>Field</th><th>File name</th><th>Saved as</th><th>Size</th></tr>\n{file_rows}</table>\n</body>\n</html>\n"
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{read_urlencoded, sanitize_filename};
+    use crate::errors::HttpErrors;
+
+    #[test]
+    fn reads_urlencoded_forms_up_to_the_limit() {
+        let form = read_urlencoded(&mut &b"a=1&b=x+y%21"[..], 12).unwrap();
+        assert_eq!(form, vec![(String::from("a"), String::from("1")), (String::from("b"), String::from("x y!"))]);
+        assert!(matches!(read_urlencoded(&mut &b"a=1&b=x+y%21"[..], 11), Err(HttpErrors::PayloadTooLarge(_))));
+        assert!(matches!(read_urlencoded(&mut &b"a=\xff"[..], 100), Err(HttpErrors::InvalidRequest(_))));
+        assert!(read_urlencoded(&mut &b""[..], 0).unwrap().is_empty());
+    }
+
+    #[test]
+    fn sanitizes_file_names() {
+        assert_eq!(sanitize_filename("report.pdf"), "report.pdf");
+        assert_eq!(sanitize_filename("../../etc/passwd"), "passwd");
+        assert_eq!(sanitize_filename(r"C:\Users\me\a.txt"), "a.txt");
+        assert_eq!(sanitize_filename(".htaccess"), "htaccess");
+        assert_eq!(sanitize_filename("a\nb\tc.txt"), "abc.txt");
+        assert_eq!(sanitize_filename("dir/"), "");
+    }
+}
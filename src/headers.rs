@@ -1,5 +1,7 @@
 #![allow(unused)]
 
+use std::borrow::Cow;
+
 /// Supported HTTP specification. This is hard-coded because this is a mini-http server.
 const HTTP_SPEC: f32 = 1.1;
 
@@ -21,10 +23,12 @@ pub struct HttpStatusHeader {
 }
 
 /// Holds the content type information that is used in the [`HttpContentTypeHeader`].
-/// e.g. text/plain
+/// e.g. text/plain; charset=utf-8
+#[derive(Clone)]
 pub struct HttpContentType {
-    pub first: &'static str,
-    pub second: &'static str,
+    pub first: Cow<'static, str>,
+    pub second: Cow<'static, str>,
+    pub parameters: Vec<(String, String)>,
 }
 
 /// Holds the content type provided by [`HttpContentType`].
@@ -140,6 +144,43 @@ macro_rules! status_codes {
     };
 }
 
+/// Creates header structs that hold a single string value, serialized as `Name: value`.
+macro_rules! string_headers {
+    (
+        $(
+            $(#[$docs:meta])*
+            ($name:literal, $header:ident);
+        )+
+    ) => {
+        $(
+            $(#[$docs])*
+            pub struct $header {
+                value: String,
+            }
+
+            impl HttpHeader<String> for $header {
+                /// Creates a new header with an empty value.
+                /// This is not the preferred method of creating a new header
+                /// please use the [`new_from`](fn@Self::new_from) function instead.
+                fn new() -> Self {
+                    $header { value: String::new() }
+                }
+
+                /// Creates a new header with the specified value.
+                fn new_from(value: String) -> Self {
+                    $header { value }
+                }
+            }
+
+            impl StringifyHttpHeader for $header {
+                fn to_string(&self) -> String {
+                    format!("{}: {}", $name, self.value)
+                }
+            }
+        )+
+    };
+}
+
 /// Shorthand macro to create the final status code string
 #[macro_export]
 macro_rules! status_code_string {
@@ -155,17 +196,121 @@ status_codes!(
     /// 200 OK
     (200, OK, "OK");
 
+    /// 201 Created
+    (201, CREATED, "Created");
+
+    /// 204 No Content
+    (204, NO_CONTENT, "No Content");
+
     /// 404 Not found
     (404, NOT_FOUND, "Not found");
 
     /// 401 Unauthorized
     (401, UNAUTHORIZED, "Unauthorized");
 
+    /// 207 Multi-Status
+    (207, MULTI_STATUS, "Multi-Status");
+
     /// 400 Bad Request
     (400, BAD_REQUEST, "Bad Request");
 
+    /// 403 Forbidden
+    (403, FORBIDDEN, "Forbidden");
+
+    /// 405 Method Not Allowed
+    (405, METHOD_NOT_ALLOWED, "Method Not Allowed");
+
+    /// 409 Conflict
+    (409, CONFLICT, "Conflict");
+
+    /// 411 Length Required
+    (411, LENGTH_REQUIRED, "Length Required");
+
+    /// 412 Precondition Failed
+    (412, PRECONDITION_FAILED, "Precondition Failed");
+
+    /// 413 Payload Too Large
+    (413, PAYLOAD_TOO_LARGE, "Payload Too Large");
+
+    /// 415 Unsupported Media Type
+    (415, UNSUPPORTED_MEDIA_TYPE, "Unsupported Media Type");
+
+    /// 416 Range Not Satisfiable
+    (416, RANGE_NOT_SATISFIABLE, "Range Not Satisfiable");
+
+    /// 423 Locked
+    (423, LOCKED, "Locked");
+
+    /// 428 Precondition Required
+    (428, PRECONDITION_REQUIRED, "Precondition Required");
+
+    /// 500 Internal Server Error
+    (500, INTERNAL_SERVER_ERROR, "Internal Server Error");
+
     /// 501 Not implemented
     (501, NOT_IMPLEMENTED, "Not Implemented");
+
+    /// 502 Bad Gateway
+    (502, BAD_GATEWAY, "Bad Gateway");
+
+    /// 503 Service Unavailable
+    (503, SERVICE_UNAVAILABLE, "Service Unavailable");
+
+    /// 507 Insufficient Storage
+    (507, INSUFFICIENT_STORAGE, "Insufficient Storage");
+);
+
+string_headers!(
+    /// Tells the client not to second-guess the `Content-Type` (i.e. `nosniff`).
+    ("X-Content-Type-Options", HttpContentTypeOptionsHeader);
+
+    /// Entity tag of the served file, compared against `If-Match` and `If-None-Match`.
+    ("ETag", HttpETagHeader);
+
+    /// URL of a newly created resource.
+    ("Location", HttpLocationHeader);
+
+    /// Caching directives, e.g. `no-store`.
+    ("Cache-Control", HttpCacheControlHeader);
+
+    /// tus protocol version used by the server.
+    ("Tus-Resumable", HttpTusResumableHeader);
+
+    /// tus protocol versions supported by the server.
+    ("Tus-Version", HttpTusVersionHeader);
+
+    /// tus extensions supported by the server.
+    ("Tus-Extension", HttpTusExtensionHeader);
+
+    /// Maximum size of a tus upload, in bytes.
+    ("Tus-Max-Size", HttpTusMaxSizeHeader);
+
+    /// Amount of bytes of a tus upload received so far.
+    ("Upload-Offset", HttpUploadOffsetHeader);
+
+    /// Total size of a tus upload, in bytes.
+    ("Upload-Length", HttpUploadLengthHeader);
+
+    /// Metadata the client attached to a tus upload.
+    ("Upload-Metadata", HttpUploadMetadataHeader);
+
+    /// Methods allowed on the requested resource.
+    ("Allow", HttpAllowHeader);
+
+    /// WebDAV compliance classes supported by the server.
+    ("DAV", HttpDavHeader);
+
+    /// Tells Microsoft clients to use WebDAV for authoring.
+    ("MS-Author-Via", HttpMsAuthorViaHeader);
+
+    /// ID the request was logged under.
+    ("X-Request-Id", HttpRequestIdHeader);
+
+    /// How long the server took for each step of handling the request.
+    ("Server-Timing", HttpServerTimingHeader);
+
+    /// Token of a WebDAV lock that was just created.
+    ("Lock-Token", HttpLockTokenHeader);
 );
 
 impl HttpStatusCode {
@@ -182,6 +327,38 @@ impl HttpStatusCode {
     }
 }
 
+impl HttpContentType {
+    /// Returns the value of the parameter matching `name`, case-insensitively.
+    pub fn parameter(&self, name: &str) -> Option<&str> {
+        self.parameters
+            .iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+    }
+
+    /// Sets a parameter, replacing any previous value.
+    pub fn with_parameter(mut self, name: &str, value: &str) -> Self {
+        self.parameters.retain(|(n, _)| !n.eq_ignore_ascii_case(name));
+        self.parameters.push((name.to_ascii_lowercase(), value.to_string()));
+        self
+    }
+}
+
+impl HttpResponse {
+    /// Returns the status code from the status line.
+    pub fn status_code(&self) -> u16 {
+        self.headers.split(' ').nth(1).and_then(|c| c.parse().ok()).unwrap_or(0)
+    }
+
+    /// Adds a header to an already built response.
+    pub fn add_header(mut self, header: impl StringifyHttpHeader) -> Self {
+        let end = self.headers.len() - "\r\n\r\n".len();
+        self.headers.insert_str(end, &format!("\r\n{}", header.to_string()));
+
+        self
+    }
+}
+
 impl HeaderVec {
     /// Creates an empty [`HeaderVec`].
     pub fn new() -> Self {
@@ -254,8 +431,9 @@ impl HttpHeader<HttpContentType> for HttpContentTypeHeader {
     fn new() -> Self {
         HttpContentTypeHeader {
             content_type: HttpContentType {
-                first: "text",
-                second: "plain",
+                first: Cow::Borrowed("text"),
+                second: Cow::Borrowed("plain"),
+                parameters: vec![],
             },
         }
     }
@@ -293,10 +471,14 @@ impl StringifyHttpHeader for HttpStatusHeader {
 impl StringifyHttpHeader for HttpContentTypeHeader {
     /// `.to_string()` implementation for [`HttpContentTypeHeader`].
     fn to_string(&self) -> String {
-        format!(
+        let mut header = format!(
             "Content-Type: {}/{}",
             self.content_type.first, self.content_type.second
-        )
+        );
+        for (name, value) in &self.content_type.parameters {
+            header.push_str(&format!("; {name}={value}"));
+        }
+        header
     }
 }
 
@@ -0,0 +1,285 @@
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, MutexGuard};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use crate::config::Config;
+use crate::error_pages::error_response;
+use crate::escape::{html_escape, percent_encode};
+use crate::errors::HttpErrors;
+use crate::headers::{HttpResponse, BAD_REQUEST, LOCKED, PRECONDITION_FAILED};
+use crate::parser::HttpRequest;
+use crate::requests::to_local_path;
+use crate::webdav::destination_path;
+
+/// Active WebDAV write locks. They only live as long as the process.
+static LOCKS: Mutex<Vec<Lock>> = Mutex::new(Vec::new());
+
+/// Used to keep lock tokens unique within the same nanosecond.
+static LOCK_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum LockScope {
+    /// Nobody else can hold a lock on the resource.
+    Exclusive,
+    /// Other shared locks can be held on the resource at the same time.
+    Shared,
+}
+
+/// A write lock on a resource and, if `infinite`, everything under it.
+#[derive(Clone)]
+pub struct Lock {
+    pub token: String,
+    /// URL path of the locked resource, without a trailing `/`.
+    pub path: String,
+    pub scope: LockScope,
+    pub infinite: bool,
+    pub owner: String,
+    /// Seconds the lock is held for, counting from its last refresh.
+    pub timeout: u64,
+    expires: Instant,
+}
+
+impl Lock {
+    /// Returns true if the lock applies to `path`.
+    fn covers(&self, path: &str) -> bool {
+        self.path == path || (self.infinite && is_under(path, &self.path))
+    }
+
+    /// Writes the lock as a `<D:activelock>` element.
+    pub fn to_xml(&self) -> String {
+        let scope = match self.scope {
+            LockScope::Exclusive => "<D:exclusive/>",
+            LockScope::Shared => "<D:shared/>",
+        };
+        let root = if self.path.is_empty() { "/" } else { &self.path };
+
+        format!(
+            "<D:activelock><D:locktype><D:write/></D:locktype><D:lockscope>{scope}</D:lockscope><D:depth>{}</D:depth><D:owner>{}</D:owner><D:timeout>Second-{}</D:timeout><D:locktoken><D:href>{}</D:href></D:locktoken><D:lockroot><D:href>{}</D:href></D:lockroot></D:activelock>",
+            if self.infinite { "infinity" } else { "0" },
+            html_escape(&self.owner),
+            self.timeout,
+            self.token,
+            html_escape(&percent_encode(root)),
+        )
+    }
+}
+
+/// Returns true if `path` lies strictly underneath `parent`.
+fn is_under(path: &str, parent: &str) -> bool {
+    path.starts_with(&format!("{parent}/"))
+}
+
+/// Locks the lock table, first dropping locks that expired or whose resource is gone.
+fn active(config: &Config) -> MutexGuard<'static, Vec<Lock>> {
+    let mut locks = LOCKS.lock().unwrap_or_else(|e| e.into_inner());
+    let now = Instant::now();
+    locks.retain(|l| l.expires > now && Path::new(&to_local_path(&config.server.root, &l.path)).exists());
+    locks
+}
+
+/// Takes a new lock on `path`. Returns [`None`] if it conflicts with a lock that is
+/// already held on the resource, one of its parents or (for infinite locks) its members.
+pub fn acquire(path: &str, scope: LockScope, infinite: bool, owner: String, timeout: u64, config: &Config) -> Option<Lock> {
+    let mut locks = active(config);
+    let conflict = locks.iter().any(|l| {
+        (l.covers(path) || (infinite && is_under(&l.path, path)))
+            && (l.scope == LockScope::Exclusive || scope == LockScope::Exclusive)
+    });
+    if conflict {
+        return None;
+    }
+
+    let nanos = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_nanos()).unwrap_or(0);
+    let id = format!(
+        "{:016x}{:08x}{:08x}",
+        nanos as u64,
+        std::process::id(),
+        LOCK_COUNTER.fetch_add(1, Ordering::Relaxed) as u32
+    );
+    let lock = Lock {
+        token: format!("opaquelocktoken:{}-{}-{}-{}-{}", &id[..8], &id[8..12], &id[12..16], &id[16..20], &id[20..32]),
+        path: path.to_string(),
+        scope,
+        infinite,
+        owner,
+        timeout,
+        expires: Instant::now() + Duration::from_secs(timeout),
+    };
+    locks.push(lock.clone());
+    Some(lock)
+}
+
+/// Restarts the timeout of the lock on `path` held through one of `tokens`.
+pub fn refresh(path: &str, tokens: &[String], timeout: u64, config: &Config) -> Option<Lock> {
+    let mut locks = active(config);
+    let lock = locks.iter_mut().find(|l| l.covers(path) && tokens.contains(&l.token))?;
+    lock.timeout = timeout;
+    lock.expires = Instant::now() + Duration::from_secs(timeout);
+    Some(lock.clone())
+}
+
+/// Removes the lock identified by `token`, if it applies to `path`.
+pub fn release(path: &str, token: &str, config: &Config) -> bool {
+    let mut locks = active(config);
+    let count = locks.len();
+    locks.retain(|l| !(l.token == token && l.covers(path)));
+    locks.len() != count
+}
+
+/// Returns the locks that apply to `path`.
+pub fn discover(path: &str, config: &Config) -> Vec<Lock> {
+    active(config).iter().filter(|l| l.covers(path)).cloned().collect()
+}
+
+/// Returns the lock tokens submitted through the `If` header. Tokens negated with `Not`
+/// and entity tags are ignored.
+pub fn submitted_tokens(request: &HttpRequest) -> Result<Vec<String>, HttpErrors> {
+    match request.header("If") {
+        Some(header) => parse_if(header),
+        None => Ok(vec![]),
+    }
+}
+
+/// Parses an `If` header (RFC 4918 section 10.4): lists of conditions in parentheses, each
+/// optionally preceded by the resource they apply to in angle brackets.
+fn parse_if(header: &str) -> Result<Vec<String>, HttpErrors> {
+    let invalid = || HttpErrors::InvalidRequest(format!("malformed If header `{header}`"));
+
+    let mut tokens = vec![];
+    let mut lists = 0;
+    let mut rest = header.trim_start();
+    while !rest.is_empty() {
+        if let Some(tagged) = rest.strip_prefix('<') {
+            let (_, after) = tagged.split_once('>').ok_or_else(invalid)?;
+            rest = after;
+        } else if let Some(list) = rest.strip_prefix('(') {
+            rest = parse_list(list, &mut tokens).ok_or_else(invalid)?;
+            lists += 1;
+        } else {
+            return Err(invalid());
+        }
+        rest = rest.trim_start();
+    }
+
+    if lists == 0 {
+        return Err(invalid());
+    }
+    Ok(tokens)
+}
+
+/// Parses the conditions of a list up to its closing parenthesis, adding the state tokens
+/// that aren't negated to `tokens`. Returns what follows the list, or `None` if it's malformed.
+fn parse_list<'a>(list: &'a str, tokens: &mut Vec<String>) -> Option<&'a str> {
+    let mut conditions = 0;
+    let mut rest = list.trim_start();
+    loop {
+        if let Some(after) = rest.strip_prefix(')') {
+            return (conditions > 0).then_some(after);
+        }
+
+        let negated = rest.starts_with("Not");
+        if negated {
+            rest = rest["Not".len()..].trim_start();
+        }
+        if let Some(token) = rest.strip_prefix('<') {
+            let (token, after) = token.split_once('>')?;
+            if !negated {
+                tokens.push(token.to_string());
+            }
+            rest = after;
+        } else if let Some(etag) = rest.strip_prefix('[') {
+            let (_, after) = etag.split_once(']')?;
+            rest = after;
+        } else {
+            return None;
+        }
+        conditions += 1;
+        rest = rest.trim_start();
+    }
+}
+
+/// Checks the request against the held locks before it is dispatched. Returns the response
+/// to send instead if it would modify a locked resource without submitting the lock token
+/// in its `If` header, or if it submits tokens of which none are held.
+pub fn enforce(request: &HttpRequest, config: &Config) -> Option<HttpResponse> {
+    let path = request.path.trim_end_matches('/');
+    // Paths the request modifies, and whether everything under them is modified as well.
+    let mut targets = match request.method.as_str() {
+        "PUT" | "PATCH" | "MKCOL" | "PROPPATCH" => vec![(path.to_string(), false)],
+        "DELETE" | "MOVE" => vec![(path.to_string(), true)],
+        "COPY" => vec![],
+        _ => return None,
+    };
+    if matches!(request.method.as_str(), "COPY" | "MOVE") {
+        if let Ok(destination) = destination_path(request) {
+            targets.push((destination.trim_end_matches('/').to_string(), true));
+        }
+    }
+
+    let tokens = match submitted_tokens(request) {
+        Ok(t) => t,
+        Err(_) => return Some(error_response(BAD_REQUEST.code, request, config)),
+    };
+    let locks = active(config);
+
+    if !tokens.is_empty() && !locks.iter().any(|l| tokens.contains(&l.token)) {
+        return Some(error_response(PRECONDITION_FAILED.code, request, config));
+    }
+
+    // Shared locks on the same resource are satisfied by any one of their tokens.
+    let locked = targets.iter().any(|(target, members)| {
+        let relevant: Vec<_> = locks.iter().filter(|l| l.covers(target) || (*members && is_under(&l.path, target))).collect();
+        relevant
+            .iter()
+            .any(|l| !relevant.iter().any(|o| o.path == l.path && tokens.contains(&o.token)))
+    });
+    if locked {
+        return Some(error_response(LOCKED.code, request, config));
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse_if;
+
+    #[test]
+    fn unterminated_token_is_malformed() {
+        assert!(parse_if("(<").is_err());
+        assert!(parse_if("(<opaquelocktoken:a").is_err());
+        assert!(parse_if("<http://example.com/a").is_err());
+    }
+
+    #[test]
+    fn resource_tag_without_list_is_malformed() {
+        assert!(parse_if("<a>").is_err());
+        assert!(parse_if("").is_err());
+        assert!(parse_if("()").is_err());
+        assert!(parse_if("(<a>").is_err());
+        assert!(parse_if("token").is_err());
+    }
+
+    #[test]
+    fn negated_tokens_are_ignored() {
+        assert_eq!(parse_if("(Not <a>)").unwrap(), Vec::<String>::new());
+        assert_eq!(parse_if("(Not <a> <b>)").unwrap(), vec!["b"]);
+    }
+
+    #[test]
+    fn entity_tags_are_ignored() {
+        assert_eq!(parse_if(r#"(["etag"])"#).unwrap(), Vec::<String>::new());
+        assert_eq!(parse_if(r#"(<a> ["etag"])"#).unwrap(), vec!["a"]);
+        assert!(parse_if(r#"(["etag")"#).is_err());
+    }
+
+    #[test]
+    fn tokens_of_every_list_are_collected() {
+        assert_eq!(parse_if("(<a>) (<b>)").unwrap(), vec!["a", "b"]);
+        assert_eq!(
+            parse_if("<http://example.com/x> (<a>) <http://example.com/y> (Not <b> <c>) ([\"e\"])").unwrap(),
+            vec!["a", "c"]
+        );
+    }
+}
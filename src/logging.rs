@@ -0,0 +1,742 @@
+use flate2::{Compression, GzBuilder};
+use log::{debug, error, info, LevelFilter, Log, Metadata, Record};
+use pretty_env_logger::env_logger;
+use pretty_env_logger::env_logger::filter::{self, Filter};
+use std::cell::RefCell;
+use std::io::{self, BufWriter, Write};
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, OnceLock, RwLock};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use fs_err as fs;
+
+use crate::config::{self, LogFormat, LoggingConfig, RotateInterval};
+use crate::datetime::DateTime;
+use crate::errors::HttpErrors;
+use crate::escape::json_escape;
+use crate::parser::HttpRequest;
+use crate::signals;
+use crate::syslog::SystemLog;
+
+/// How often buffered log lines are written out.
+const FLUSH_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Apache's Common Log Format.
+const COMMON_FORMAT: &str = "%h %l %u %t \"%r\" %s %b";
+
+/// Apache's Combined Log Format.
+const COMBINED_FORMAT: &str = "%h %l %u %t \"%r\" %s %b \"%{Referer}i\" \"%{User-Agent}i\"";
+
+static LOGGER: OnceLock<ServerLogger> = OnceLock::new();
+
+/// Set on SIGUSR1 for the flush thread to reopen the log files.
+static REOPEN: AtomicBool = AtomicBool::new(false);
+
+/// Set on SIGHUP for the flush thread to reload the log levels from the config file.
+static RELOAD: AtomicBool = AtomicBool::new(false);
+
+/// Longest `X-Request-Id` accepted from a client.
+const MAX_REQUEST_ID_LENGTH: usize = 128;
+
+/// Used to keep request IDs unique within the same nanosecond.
+static REQUEST_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+thread_local! {
+    /// ID of the request being handled on this thread, added to JSON records.
+    static REQUEST_ID: RefCell<Option<String>> = const { RefCell::new(None) };
+    /// Extra fields of the record being logged.
+    static FIELDS: RefCell<Vec<(&'static str, FieldValue)>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Value of an extra field of a log record.
+pub enum FieldValue {
+    Text(String),
+    Number(u128),
+    Null,
+}
+
+/// A log file, shared when the access log and the diagnostic log are the same file.
+type LogFile = Arc<Mutex<RotatingFile>>;
+
+/// When log files are rotated and how many rotated files are kept.
+#[derive(Clone, Copy)]
+struct Rotation {
+    size: u64,
+    /// Length of the rotation interval in seconds.
+    interval: Option<u64>,
+    keep: u32,
+    compress: bool,
+}
+
+/// Buffered log file that is renamed to `<path>.1` once it grows too big or too old.
+struct RotatingFile {
+    path: PathBuf,
+    writer: BufWriter<fs::File>,
+    rotation: Rotation,
+    /// Size of the file, including buffered lines.
+    size: u64,
+    /// Interval the file was started in, counted from the epoch.
+    period: u64,
+    /// Compression of the last rotated file, which runs outside the lock.
+    compressing: Option<JoinHandle<()>>,
+}
+
+/// Logs to stderr like `pretty_env_logger` does, and also to `logging.log_file`, which
+/// receives the diagnostic log filtered at `logging.log_file_level` and the access log.
+struct ServerLogger {
+    format: LogFormat,
+    levels: RwLock<Levels>,
+    file: Option<LogFile>,
+    system: Option<SystemLog>,
+    access: Option<(Vec<Directive>, LogFile)>,
+    user_header: String,
+}
+
+/// Which messages reach stderr and `logging.log_file`, replaced as a whole on reload.
+struct Levels {
+    stderr: env_logger::Logger,
+    file: Filter,
+    system: Filter,
+}
+
+/// Piece of an access log format string.
+enum Directive {
+    Literal(String),
+    Host,
+    User,
+    Time,
+    RequestLine,
+    Status,
+    /// Response size, or `-` for an empty response if `dash_if_empty`.
+    Bytes { dash_if_empty: bool },
+    Micros,
+    Seconds,
+    Method,
+    Path,
+    Query,
+    Protocol,
+    RequestHeader(String),
+    RequestId,
+}
+
+/// What is known about a request once its response was sent.
+pub struct AccessEntry<'a> {
+    pub request: &'a HttpRequest,
+    pub request_id: &'a str,
+    pub peer: SocketAddr,
+    pub status: u16,
+    pub bytes: usize,
+    pub duration: Duration,
+    /// Time taken to parse the request.
+    pub parse: Duration,
+    /// Time taken to write the response to the client.
+    pub send: Duration,
+}
+
+/// Installs the global logger. Messages go to stderr at the levels given with `--log-level`,
+/// through `RUST_LOG`, or else `logging.default_level` and `logging.modules`, and to
+/// `logging.log_file` if it's set.
+pub fn init(config: &LoggingConfig) -> Result<(), HttpErrors> {
+    let levels = levels(config)?;
+
+    let rotation = Rotation {
+        size: config.rotate_size,
+        interval: match config.rotate_interval {
+            RotateInterval::Never => None,
+            RotateInterval::Hourly => Some(60 * 60),
+            RotateInterval::Daily => Some(24 * 60 * 60),
+        },
+        keep: config.rotate_keep,
+        compress: config.rotate_compress,
+    };
+    let file = match config.log_file.as_str() {
+        "" => None,
+        path => Some(open(path, rotation)?),
+    };
+    let access_file = match (config.access_log.as_str(), &file) {
+        ("", Some(file)) => Some(file.clone()),
+        ("", None) => None,
+        (path, _) => Some(open(path, rotation)?),
+    };
+    let access = match access_file {
+        Some(f) => Some((parse_format(&config.access_log_format)?, f)),
+        None => None,
+    };
+
+    let logger = ServerLogger {
+        format: config.format,
+        levels: RwLock::new(levels),
+        file,
+        system: SystemLog::connect(config)?,
+        access,
+        user_header: config.user_header.clone(),
+    };
+
+    let logger = LOGGER.get_or_init(|| logger);
+    log::set_logger(logger).map_err(|e| HttpErrors::LoggerInitFailure(e.to_string()))?;
+    log::set_max_level(logger.max_level());
+
+    signals::handle(&[libc::SIGUSR1], request_reopen);
+    signals::handle(&[libc::SIGHUP], request_reload);
+
+    thread::spawn(move || loop {
+        thread::sleep(FLUSH_INTERVAL);
+        if REOPEN.swap(false, Ordering::Relaxed) {
+            logger.reopen();
+        }
+        if RELOAD.swap(false, Ordering::Relaxed) {
+            logger.reload();
+        }
+        log::logger().flush();
+    });
+
+    Ok(())
+}
+
+/// Builds the level filters from the config, unless they're overridden for stderr.
+fn levels(config: &LoggingConfig) -> Result<Levels, HttpErrors> {
+    let modules = module_filters(&config.modules)?;
+    let stderr_filters = override_filters().unwrap_or_else(|| format!("{}{modules}", config.default_level));
+
+    let mut stderr = match config.format {
+        LogFormat::Text => pretty_env_logger::formatted_timed_builder(),
+        LogFormat::Json => {
+            let mut builder = env_logger::Builder::new();
+            builder.format(|buf, record| writeln!(buf, "{}", json_record(record)));
+            builder
+        }
+    };
+
+    Ok(Levels {
+        stderr: stderr.parse_filters(&stderr_filters).build(),
+        file: filter::Builder::new().parse(&format!("{}{modules}", config.log_file_level)).build(),
+        system: filter::Builder::new().parse(&format!("{}{modules}", config.system_log_level)).build(),
+    })
+}
+
+/// Turns `logging.modules` into `,http_server_rs::requests=trace` style directives.
+fn module_filters(modules: &HashMap<String, String>) -> Result<String, HttpErrors> {
+    let mut filters = String::new();
+    for (module, level) in modules {
+        if level.parse::<LevelFilter>().is_err() {
+            return Err(HttpErrors::ConfigParseFailure(format!("invalid log level `{level}` for module `{module}`")));
+        }
+        match module.contains("::") {
+            true => filters.push_str(&format!(",{module}={level}")),
+            false => filters.push_str(&format!(",http_server_rs::{module}={level}")),
+        }
+    }
+    Ok(filters)
+}
+
+/// Returns the stderr filters given with `--log-level`, or else through `RUST_LOG`.
+fn override_filters() -> Option<String> {
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        if arg == "--log-level" {
+            return args.next();
+        }
+        if let Some(filters) = arg.strip_prefix("--log-level=") {
+            return Some(filters.to_string());
+        }
+    }
+    std::env::var("RUST_LOG").ok()
+}
+
+/// Returns the `X-Request-Id` the client sent, if it's short and made of safe characters,
+/// or else a new ID.
+pub fn request_id(request: &HttpRequest) -> String {
+    match request.header("X-Request-Id") {
+        Some(id)
+            if !id.is_empty()
+                && id.len() <= MAX_REQUEST_ID_LENGTH
+                && id.chars().all(|c| c.is_ascii_alphanumeric() || "-_.:@+/=".contains(c)) =>
+        {
+            id.to_string()
+        }
+        _ => new_request_id(),
+    }
+}
+
+/// Returns a new ID to tell requests apart in the logs.
+fn new_request_id() -> String {
+    let nanos = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_nanos()).unwrap_or(0);
+    format!("{:012x}{:04x}", nanos as u64 & 0xffff_ffff_ffff, REQUEST_COUNTER.fetch_add(1, Ordering::Relaxed) & 0xffff)
+}
+
+/// Sets the ID of the request handled on this thread, or clears it.
+pub fn set_request_id(id: Option<&str>) {
+    REQUEST_ID.with(|r| *r.borrow_mut() = id.map(String::from));
+}
+
+fn is_json() -> bool {
+    LOGGER.get().is_some_and(|l| l.format == LogFormat::Json)
+}
+
+/// Logs that a request was received. JSON logs only do so at debug level, they get a single
+/// record per request from [`request_completed`].
+pub fn request_received(request: &HttpRequest, peer: SocketAddr) {
+    if is_json() {
+        debug!(target: "http_server_rs::requests", "Received {} request", request.method);
+    } else {
+        info!(target: "http_server_rs::requests", "Received {} request from {peer:#?}", request.method);
+    }
+}
+
+/// Logs that the response to a request was sent, with the details of the request as fields
+/// of the record in JSON logs and the system log.
+pub fn request_completed(entry: &AccessEntry) {
+    let request = entry.request;
+    let fields = vec![
+        ("method", FieldValue::Text(request.method.clone())),
+        ("path", FieldValue::Text(request.path.clone())),
+        ("status", FieldValue::Number(entry.status as u128)),
+        ("bytes", FieldValue::Number(entry.bytes as u128)),
+        ("duration_us", FieldValue::Number(entry.duration.as_micros())),
+        ("send_us", FieldValue::Number(entry.send.as_micros())),
+        ("peer", FieldValue::Text(entry.peer.to_string())),
+        ("user_agent", request.header("User-Agent").map_or(FieldValue::Null, |u| FieldValue::Text(u.to_string()))),
+    ];
+
+    FIELDS.with(|f| *f.borrow_mut() = fields);
+    if is_json() {
+        info!(target: "http_server_rs::requests", "Request completed");
+    } else {
+        info!(target: "http_server_rs::requests", "Sent response to {}", entry.peer);
+    }
+    FIELDS.with(|f| f.borrow_mut().clear());
+}
+
+/// Calls `f` with the current request ID and the extra fields of the record being logged.
+fn with_context<R>(f: impl FnOnce(Option<&str>, &[(&'static str, FieldValue)]) -> R) -> R {
+    REQUEST_ID.with(|id| FIELDS.with(|fields| f(id.borrow().as_deref(), &fields.borrow())))
+}
+
+/// Formats a record as a JSON object, with the current request ID and extra fields.
+fn json_record(record: &Record) -> String {
+    let mut line = format!(
+        "{{\"timestamp\":\"{}\",\"level\":\"{}\",\"target\":\"{}\",\"message\":\"{}\"",
+        DateTime::now().to_rfc3339(),
+        record.level(),
+        json_escape(record.target()),
+        json_escape(&record.args().to_string()),
+    );
+    with_context(|request_id, fields| {
+        if let Some(id) = request_id {
+            line.push_str(&format!(",\"request_id\":\"{}\"", json_escape(id)));
+        }
+        for (name, value) in fields {
+            match value {
+                FieldValue::Text(text) => line.push_str(&format!(",\"{name}\":\"{}\"", json_escape(text))),
+                FieldValue::Number(number) => line.push_str(&format!(",\"{name}\":{number}")),
+                FieldValue::Null => line.push_str(&format!(",\"{name}\":null")),
+            }
+        }
+    });
+    line.push('}');
+    line
+}
+
+/// Writes the access log line of a request.
+pub fn access(entry: &AccessEntry) {
+    let Some(logger) = LOGGER.get() else {
+        return;
+    };
+    let Some((format, file)) = &logger.access else {
+        return;
+    };
+
+    let mut line = format_access(format, entry, &logger.user_header);
+    line.push('\n');
+    write_line(file, &line);
+}
+
+extern "C" fn request_reopen(_: libc::c_int) {
+    REOPEN.store(true, Ordering::Relaxed);
+}
+
+extern "C" fn request_reload(_: libc::c_int) {
+    RELOAD.store(true, Ordering::Relaxed);
+}
+
+fn open(path: &str, rotation: Rotation) -> Result<LogFile, HttpErrors> {
+    let file = RotatingFile::open(Path::new(path), rotation).map_err(|e| HttpErrors::LoggerInitFailure(e.to_string()))?;
+    Ok(Arc::new(Mutex::new(file)))
+}
+
+fn write_line(file: &LogFile, line: &str) {
+    let mut file = file.lock().unwrap_or_else(|e| e.into_inner());
+    // There's nowhere left to report a failing log file.
+    let _ = file.write_line(line);
+}
+
+/// Compresses `from` into `to` and removes it. `to` only appears once it's complete.
+fn compress_file(from: &Path, to: &Path) -> io::Result<()> {
+    let mtime = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs() as u32);
+    let mut partial = to.as_os_str().to_owned();
+    partial.push(".tmp");
+    let mut encoder = GzBuilder::new().mtime(mtime).write(fs::File::create(&partial)?, Compression::default());
+    io::copy(&mut fs::File::open(from)?, &mut encoder)?;
+    encoder.finish()?;
+    fs::rename(&partial, to)?;
+    fs::remove_file(from)
+}
+
+/// Returns which rotation interval `time` falls in.
+fn period(time: SystemTime, interval: Option<u64>) -> u64 {
+    interval.map_or(0, |i| time.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs() / i))
+}
+
+impl RotatingFile {
+    fn open(path: &Path, rotation: Rotation) -> io::Result<Self> {
+        let file = fs::OpenOptions::new().create(true).append(true).open(path)?;
+        let metadata = file.metadata()?;
+        let modified = metadata.modified().unwrap_or_else(|_| SystemTime::now());
+
+        Ok(RotatingFile {
+            path: path.to_path_buf(),
+            writer: BufWriter::new(file),
+            rotation,
+            size: metadata.len(),
+            period: period(modified, rotation.interval),
+            compressing: None,
+        })
+    }
+
+    fn write_line(&mut self, line: &str) -> io::Result<()> {
+        let too_big = self.rotation.size > 0 && self.size > 0 && self.size + line.len() as u64 > self.rotation.size;
+        let too_old = self.rotation.interval.is_some() && period(SystemTime::now(), self.rotation.interval) != self.period;
+        if too_big || too_old {
+            self.rotate()?;
+        }
+
+        self.writer.write_all(line.as_bytes())?;
+        self.size += line.len() as u64;
+        Ok(())
+    }
+
+    /// Opens the file at its path again, in case it was moved away by another program.
+    fn reopen(&mut self) -> io::Result<()> {
+        self.writer.flush()?;
+        let compressing = self.compressing.take();
+        *self = RotatingFile::open(&self.path.clone(), self.rotation)?;
+        self.compressing = compressing;
+        Ok(())
+    }
+
+    /// Shifts `<path>.1` to `<path>.2` and so on, dropping the oldest, then moves the
+    /// current file to `<path>.1` and starts a new one. Compression happens on another
+    /// thread, so logging doesn't stall meanwhile.
+    fn rotate(&mut self) -> io::Result<()> {
+        self.writer.flush()?;
+
+        // The last rotated file has to be complete before it's shifted along. If the server
+        // stopped while compressing it, that's finished here.
+        if let Some(compressing) = self.compressing.take() {
+            let _ = compressing.join();
+        }
+        let uncompressed = self.uncompressed();
+        if self.rotation.compress && uncompressed.exists() {
+            compress_file(&uncompressed, &self.rotated(1))?;
+        }
+
+        let keep = self.rotation.keep;
+        if keep == 0 {
+            fs::remove_file(&self.path)?;
+        } else {
+            // The oldest file may not exist yet.
+            let _ = fs::remove_file(self.rotated(keep));
+            for n in (1..keep).rev() {
+                let from = self.rotated(n);
+                if from.exists() {
+                    fs::rename(&from, self.rotated(n + 1))?;
+                }
+            }
+
+            if self.rotation.compress {
+                fs::rename(&self.path, &uncompressed)?;
+                let compressed = self.rotated(1);
+                // There's nowhere left to report a failure, the file stays uncompressed then.
+                self.compressing = Some(thread::spawn(move || {
+                    let _ = compress_file(&uncompressed, &compressed);
+                }));
+            } else {
+                fs::rename(&self.path, self.rotated(1))?;
+            }
+        }
+
+        self.reopen()
+    }
+
+    /// Where the current file waits to be compressed into `<path>.1.gz`.
+    fn uncompressed(&self) -> PathBuf {
+        let mut name = self.path.clone().into_os_string();
+        name.push(".1");
+        PathBuf::from(name)
+    }
+
+    fn rotated(&self, n: u32) -> PathBuf {
+        let mut name = self.path.clone().into_os_string();
+        name.push(format!(".{n}{}", if self.rotation.compress { ".gz" } else { "" }));
+        PathBuf::from(name)
+    }
+}
+
+impl ServerLogger {
+    fn files(&self) -> impl Iterator<Item = &LogFile> {
+        self.file.iter().chain(self.access.iter().map(|(_, f)| f))
+    }
+
+    fn max_level(&self) -> LevelFilter {
+        let levels = self.levels.read().unwrap_or_else(|e| e.into_inner());
+        let file = if self.file.is_some() { levels.file.filter() } else { LevelFilter::Off };
+        let system = if self.system.is_some() { levels.system.filter() } else { LevelFilter::Off };
+        file.max(system).max(levels.stderr.filter())
+    }
+
+    /// Sends a record to every sink whose level lets it through.
+    fn write(&self, record: &Record) {
+        let levels = self.levels.read().unwrap_or_else(|e| e.into_inner());
+        if levels.stderr.matches(record) {
+            levels.stderr.log(record);
+        }
+
+        if let Some(file) = &self.file {
+            if levels.file.matches(record) {
+                let line = match self.format {
+                    LogFormat::Text => format!(
+                        "{} {:<5} {} > {}\n",
+                        DateTime::now().to_rfc3339(),
+                        record.level(),
+                        record.target(),
+                        record.args()
+                    ),
+                    LogFormat::Json => format!("{}\n", json_record(record)),
+                };
+                write_line(file, &line);
+            }
+        }
+
+        if let Some(system) = &self.system {
+            if levels.system.matches(record) {
+                with_context(|request_id, fields| system.send(record, request_id, fields));
+            }
+        }
+    }
+
+    /// Applies the levels of the config file as it is now. The other logging settings
+    /// only change on restart.
+    fn reload(&self) {
+        match config::parse().and_then(|c| levels(&c.logging)) {
+            Ok(levels) => {
+                *self.levels.write().unwrap_or_else(|e| e.into_inner()) = levels;
+                log::set_max_level(self.max_level());
+                info!("Reloaded log levels");
+            }
+            Err(e) => error!("Failed to reload log levels: {e}"),
+        }
+    }
+
+    fn reopen(&self) {
+        for file in self.files() {
+            let result = file.lock().unwrap_or_else(|e| e.into_inner()).reopen();
+            if let Err(e) = result {
+                error!("Failed to reopen log file: {e}");
+            }
+        }
+    }
+}
+
+impl Log for ServerLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        let levels = self.levels.read().unwrap_or_else(|e| e.into_inner());
+        levels.stderr.enabled(metadata)
+            || (self.file.is_some() && levels.file.enabled(metadata))
+            || (self.system.is_some() && levels.system.enabled(metadata))
+    }
+
+    fn log(&self, record: &Record) {
+        let request_id = REQUEST_ID.with(|r| r.borrow().clone());
+        match request_id {
+            // Text lines have no fields, the request ID goes in front of the message instead.
+            Some(id) if self.format == LogFormat::Text => self.write(
+                &Record::builder()
+                    .args(format_args!("[{id}] {}", record.args()))
+                    .metadata(record.metadata().clone())
+                    .module_path(record.module_path())
+                    .file(record.file())
+                    .line(record.line())
+                    .build(),
+            ),
+            _ => self.write(record),
+        }
+    }
+
+    fn flush(&self) {
+        self.levels.read().unwrap_or_else(|e| e.into_inner()).stderr.flush();
+        for file in self.files() {
+            let _ = file.lock().unwrap_or_else(|e| e.into_inner()).writer.flush();
+        }
+    }
+}
+
+/// Parses `common`, `combined` or a custom access log format string.
+fn parse_format(format: &str) -> Result<Vec<Directive>, HttpErrors> {
+    let format = match format {
+        "common" => COMMON_FORMAT,
+        "combined" => COMBINED_FORMAT,
+        f => f,
+    };
+
+    let mut directives = vec![];
+    let mut literal = String::new();
+    let mut chars = format.chars();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            literal.push(c);
+            continue;
+        }
+
+        let mut name = chars.next().unwrap_or_default();
+        let mut argument = String::new();
+        if name == '{' {
+            argument = chars.by_ref().take_while(|c| *c != '}').collect();
+            name = chars.next().unwrap_or_default();
+        }
+        // `%>s` is the final status in Apache, which is the only one there is here.
+        if name == '>' {
+            name = chars.next().unwrap_or_default();
+        }
+
+        let directive = match name {
+            '%' => {
+                literal.push('%');
+                continue;
+            }
+            'l' => {
+                literal.push('-');
+                continue;
+            }
+            'h' => Directive::Host,
+            'u' => Directive::User,
+            't' => Directive::Time,
+            'r' => Directive::RequestLine,
+            's' => Directive::Status,
+            'b' => Directive::Bytes { dash_if_empty: true },
+            'B' => Directive::Bytes { dash_if_empty: false },
+            'D' => Directive::Micros,
+            'L' => Directive::RequestId,
+            'T' => Directive::Seconds,
+            'm' => Directive::Method,
+            'U' => Directive::Path,
+            'q' => Directive::Query,
+            'H' => Directive::Protocol,
+            'i' if !argument.is_empty() => Directive::RequestHeader(argument),
+            _ => return Err(HttpErrors::ConfigParseFailure(format!("unknown access log directive `%{name}` in `{format}`"))),
+        };
+
+        if !literal.is_empty() {
+            directives.push(Directive::Literal(std::mem::take(&mut literal)));
+        }
+        directives.push(directive);
+    }
+    if !literal.is_empty() {
+        directives.push(Directive::Literal(literal));
+    }
+
+    Ok(directives)
+}
+
+fn format_access(format: &[Directive], entry: &AccessEntry, user_header: &str) -> String {
+    let request = entry.request;
+    let header = |name: &str| request.header(name).filter(|v| !v.is_empty()).map(escape).unwrap_or_else(|| String::from("-"));
+
+    let mut line = String::new();
+    for directive in format {
+        match directive {
+            Directive::Literal(l) => line.push_str(l),
+            Directive::Host => line.push_str(&entry.peer.ip().to_string()),
+            Directive::User if user_header.is_empty() => line.push('-'),
+            Directive::User => line.push_str(&header(user_header)),
+            Directive::Time => line.push_str(&format!("[{}]", DateTime::now().to_clf())),
+            Directive::RequestLine => {
+                line.push_str(&escape(&format!("{} {} {}", request.method, request.target, request.version)))
+            }
+            Directive::Status => line.push_str(&entry.status.to_string()),
+            Directive::Bytes { dash_if_empty: true } if entry.bytes == 0 => line.push('-'),
+            Directive::Bytes { .. } => line.push_str(&entry.bytes.to_string()),
+            Directive::Micros => line.push_str(&entry.duration.as_micros().to_string()),
+            Directive::RequestId => line.push_str(entry.request_id),
+            Directive::Seconds => line.push_str(&entry.duration.as_secs().to_string()),
+            Directive::Method => line.push_str(&escape(&request.method)),
+            Directive::Path => line.push_str(&escape(&request.path)),
+            Directive::Query => {
+                if let Some((_, query)) = request.target.split_once('?') {
+                    line.push('?');
+                    line.push_str(&escape(query));
+                }
+            }
+            Directive::Protocol => line.push_str(&escape(&request.version)),
+            Directive::RequestHeader(name) => line.push_str(&header(name)),
+        }
+    }
+    line
+}
+
+/// Escapes quotes, backslashes and control characters the way Apache does, so a client
+/// can't forge log lines.
+fn escape(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            c if c.is_control() => escaped.push_str(&format!("\\x{:02x}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::compress_file;
+    use flate2::read::GzDecoder;
+    use std::fs;
+    use std::io::Read;
+    use std::path::PathBuf;
+
+    /// A directory in the temporary directory, removed on drop.
+    struct Directory(PathBuf);
+
+    impl Drop for Directory {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    #[test]
+    fn compresses_rotated_files() {
+        let directory = Directory(std::env::temp_dir().join(format!("http-server-rs-{}-logging", std::process::id())));
+        let _ = fs::remove_dir_all(&directory.0);
+        fs::create_dir_all(&directory.0).unwrap();
+
+        let from = directory.0.join("access.log.1");
+        let to = directory.0.join("access.log.1.gz");
+        let content = "127.0.0.1 - - \"GET / HTTP/1.1\" 200 5\n".repeat(10_000);
+        fs::write(&from, &content).unwrap();
+
+        compress_file(&from, &to).unwrap();
+        assert!(!from.exists());
+        assert!(!directory.0.join("access.log.1.gz.tmp").exists());
+
+        let compressed = fs::read(&to).unwrap();
+        assert!(compressed.len() < content.len() / 10);
+        let mut decompressed = String::new();
+        GzDecoder::new(&compressed[..]).read_to_string(&mut decompressed).unwrap();
+        assert_eq!(decompressed, content);
+    }
+}
@@ -1,27 +1,49 @@
 use log::error;
 
+mod access;
+mod audit;
+mod autoindex;
+mod body;
+mod delete;
 mod errors;
+mod error_pages;
+mod datetime;
+mod escape;
+mod forms;
 mod headers;
+mod locks;
+mod logging;
 mod media;
+mod metrics;
+mod multipart;
 mod config;
+mod parser;
+mod patch;
+mod preconditions;
+mod quota;
 mod requests;
 mod server;
+mod signals;
+mod stats;
+mod status;
+mod syslog;
+mod trace;
+mod tus;
+mod upload;
+mod webdav;
+mod xml;
 
 fn main() {
-    pretty_env_logger::init_timed();
-  
-    let config = match config::parse() {
+    let config = match config::parse().and_then(|c| logging::init(&c.logging).map(|_| c)) {
         Ok(c) => c,
         Err(e) => {
+            // The configured logger isn't available, fall back to the plain one to report why.
+            pretty_env_logger::init_timed();
             error!("{e}");
             std::process::exit(2);
         }
     };
 
-    if std::env::var("RUST_LOG").is_err() {
-        std::env::set_var("RUST_LOG", &config.logging.default_level);
-    }
-
     match server::serve(config) {
         Ok(_) => (),
         Err(e) => {
@@ -1,8 +1,16 @@
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::io::Read;
+use fs_err as fs;
+
+use crate::config::MimeConfig;
+use crate::errors::HttpErrors;
 use crate::headers::HttpContentType;
 
 /// Contains an [`HttpContentType`] with its corresponding file extension.
 pub struct ContentExtension {
     pub content_type: HttpContentType,
+    #[allow(dead_code)]
     pub file_extension: &'static str,
 }
 
@@ -16,13 +24,13 @@ macro_rules! content_types {
     ) => {
         $(
             $(#[$docs])*
-            pub const $konst: ContentExtension = ContentExtension { content_type: HttpContentType { first:  $content_type_first, second: $content_type_second }, file_extension: $content_extension };
+            pub const $konst: ContentExtension = ContentExtension { content_type: HttpContentType { first: Cow::Borrowed($content_type_first), second: Cow::Borrowed($content_type_second), parameters: Vec::new() }, file_extension: $content_extension };
         )+
 
         /// Converts a file extension into a [`ContentExtension`] if a constant for that
-        /// extension exists.
+        /// extension exists. Matching is case-insensitive.
         pub fn ext_to_type(ext: &str) -> Option<ContentExtension> {
-            match ext {
+            match ext.to_ascii_lowercase().as_str() {
                 $(
                 $content_extension => Some($konst),
                 )+
@@ -32,11 +40,459 @@ macro_rules! content_types {
     };
 }
 
-// TODO: Add more MIME types
 content_types!(
     "text" / "plain" "txt" TXT;
+    "text" / "plain" "text" TEXT;
+    "text" / "plain" "log" LOG;
+    "text" / "plain" "conf" CONF;
     "text" / "html" "html" HTML;
+    "text" / "html" "htm" HTM;
     "text" / "css" "css" CSS;
+    "text" / "csv" "csv" CSV;
+    "text" / "markdown" "md" MD;
+    "text" / "xml" "xml" XML;
+    "text" / "calendar" "ics" ICS;
+    "text" / "javascript" "js" JS;
+    "text" / "javascript" "mjs" MJS;
+    "application" / "json" "json" JSON;
+    "application" / "ld+json" "jsonld" JSONLD;
+    "application" / "manifest+json" "webmanifest" WEBMANIFEST;
+    "application" / "wasm" "wasm" WASM;
+    "application" / "pdf" "pdf" PDF;
+    "application" / "rtf" "rtf" RTF;
+    "application" / "xhtml+xml" "xhtml" XHTML;
+    "application" / "atom+xml" "atom" ATOM;
+    "application" / "rss+xml" "rss" RSS;
+    "application" / "toml" "toml" TOML;
+    "application" / "yaml" "yaml" YAML;
+    "application" / "yaml" "yml" YML;
+    "application" / "zip" "zip" ZIP;
+    "application" / "gzip" "gz" GZ;
+    "application" / "x-tar" "tar" TAR;
+    "application" / "x-bzip2" "bz2" BZ2;
+    "application" / "x-xz" "xz" XZ;
+    "application" / "zstd" "zst" ZST;
+    "application" / "x-7z-compressed" "7z" SEVEN_Z;
+    "application" / "vnd.rar" "rar" RAR;
+    "application" / "java-archive" "jar" JAR;
+    "application" / "vnd.debian.binary-package" "deb" DEB;
+    "application" / "x-rpm" "rpm" RPM;
+    "application" / "vnd.android.package-archive" "apk" APK;
+    "application" / "x-msdownload" "exe" EXE;
+    "application" / "x-apple-diskimage" "dmg" DMG;
+    "application" / "x-iso9660-image" "iso" ISO;
+    "application" / "x-sh" "sh" SH;
+    "application" / "octet-stream" "bin" BIN;
+    "application" / "msword" "doc" DOC;
+    "application" / "vnd.openxmlformats-officedocument.wordprocessingml.document" "docx" DOCX;
+    "application" / "vnd.ms-excel" "xls" XLS;
+    "application" / "vnd.openxmlformats-officedocument.spreadsheetml.sheet" "xlsx" XLSX;
+    "application" / "vnd.ms-powerpoint" "ppt" PPT;
+    "application" / "vnd.openxmlformats-officedocument.presentationml.presentation" "pptx" PPTX;
+    "application" / "vnd.oasis.opendocument.text" "odt" ODT;
+    "application" / "vnd.oasis.opendocument.spreadsheet" "ods" ODS;
+    "application" / "epub+zip" "epub" EPUB;
     "image" / "png" "png" PNG;
+    "image" / "jpeg" "jpg" JPG;
+    "image" / "jpeg" "jpeg" JPEG;
+    "image" / "gif" "gif" GIF;
+    "image" / "webp" "webp" WEBP;
+    "image" / "avif" "avif" AVIF;
+    "image" / "bmp" "bmp" BMP;
+    "image" / "tiff" "tif" TIF;
+    "image" / "tiff" "tiff" TIFF;
     "image" / "svg+xml" "svg" SVG;
+    "image" / "x-icon" "ico" ICO;
+    "font" / "woff" "woff" WOFF;
+    "font" / "woff2" "woff2" WOFF2;
+    "font" / "ttf" "ttf" TTF;
+    "font" / "otf" "otf" OTF;
+    "audio" / "mpeg" "mp3" MP3;
+    "audio" / "ogg" "ogg" OGG;
+    "audio" / "opus" "opus" OPUS;
+    "audio" / "wav" "wav" WAV;
+    "audio" / "flac" "flac" FLAC;
+    "audio" / "aac" "aac" AAC;
+    "audio" / "mp4" "m4a" M4A;
+    "video" / "mp4" "mp4" MP4;
+    "video" / "webm" "webm" WEBM;
+    "video" / "ogg" "ogv" OGV;
+    "video" / "x-matroska" "mkv" MKV;
+    "video" / "quicktime" "mov" MOV;
+    "video" / "x-msvideo" "avi" AVI;
 );
+
+/// Parses a `type/subtype; name=value` string into an [`HttpContentType`].
+pub fn parse_content_type(content_type: &str) -> Option<HttpContentType> {
+    let mut sections = content_type.split(';');
+    let (first, second) = sections.next()?.trim().split_once('/')?;
+    if first.is_empty() || second.is_empty() || second.contains('/') {
+        return None;
+    }
+
+    let mut parsed = HttpContentType {
+        first: Cow::Owned(first.to_ascii_lowercase()),
+        second: Cow::Owned(second.to_ascii_lowercase()),
+        parameters: vec![],
+    };
+    for parameter in sections {
+        let (name, value) = parameter.split_once('=')?;
+        parsed = parsed.with_parameter(name.trim(), value.trim().trim_matches('"'));
+    }
+
+    Some(parsed)
+}
+
+/// Adds the configured default charset to textual content types that don't specify one.
+pub fn with_default_charset(content_type: HttpContentType, config: &MimeConfig) -> HttpContentType {
+    let textual = content_type.first == "text"
+        || (content_type.first == "application" && matches!(content_type.second.as_ref(), "json" | "javascript"));
+
+    if !textual || config.charset.is_empty() || content_type.parameter("charset").is_some() {
+        return content_type;
+    }
+
+    content_type.with_parameter("charset", &config.charset)
+}
+
+/// Builds the extension table from the `mime.types` files and `[mime.types]` overrides
+/// in the configuration. Later files take precedence over earlier ones, and the
+/// overrides take precedence over every file.
+pub fn load_table(config: &MimeConfig) -> Result<HashMap<String, HttpContentType>, HttpErrors> {
+    let mut table = HashMap::new();
+
+    for path in &config.files {
+        let mut file = fs::File::open(path).map_err(|e| HttpErrors::ConfigReadFailure(e.to_string()))?;
+        let mut contents = String::new();
+        file.read_to_string(&mut contents).map_err(|e| HttpErrors::ConfigReadFailure(e.to_string()))?;
+
+        // Each line is `type/subtype ext1 ext2 ...`, with `#` starting a comment.
+        for line in contents.lines() {
+            let line = line.split('#').next().unwrap_or_default();
+            let mut fields = line.split_whitespace();
+            let Some(content_type) = fields.next().and_then(parse_content_type) else {
+                continue;
+            };
+
+            for ext in fields {
+                table.insert(ext.to_ascii_lowercase(), content_type.clone());
+            }
+        }
+    }
+
+    for (ext, content_type) in &config.types {
+        let parsed = parse_content_type(content_type)
+            .ok_or_else(|| HttpErrors::ConfigParseFailure(format!("invalid content type `{content_type}` for `{ext}`")))?;
+        table.insert(ext.trim_start_matches('.').to_ascii_lowercase(), parsed);
+    }
+
+    Ok(table)
+}
+
+/// Resolves a file extension into its content type, checking the configured table before
+/// the built-in one. Returns [`None`] if the extension is unknown.
+pub fn lookup(ext: &str, config: &MimeConfig) -> Option<HttpContentType> {
+    config
+        .table
+        .get(&ext.to_ascii_lowercase())
+        .cloned()
+        .or_else(|| ext_to_type(ext).map(|c| c.content_type))
+        .map(|c| with_default_charset(c, config))
+}
+
+/// Magic numbers recognized by [`sniff`], paired with the content type they identify.
+const MAGIC_NUMBERS: [(&[u8], ContentExtension); 8] = [
+    (b"\x89PNG\r\n\x1a\n", PNG),
+    (b"\xff\xd8\xff", JPG),
+    (b"GIF87a", GIF),
+    (b"GIF89a", GIF),
+    (b"%PDF-", PDF),
+    (b"PK\x03\x04", ZIP),
+    (b"\x1f\x8b", GZ),
+    (b"\0asm", WASM),
+];
+
+/// Amount of bytes inspected when checking whether content is text.
+const SNIFF_TEXT_LENGTH: usize = 512;
+
+/// Guesses the content type from the first bytes of the content. Content without a known
+/// magic number is reported as `text/plain` if it looks like UTF-8 text.
+pub fn sniff(content: &[u8]) -> Option<HttpContentType> {
+    if let Some((_, ext)) = MAGIC_NUMBERS.into_iter().find(|(magic, _)| content.starts_with(magic)) {
+        return Some(ext.content_type);
+    }
+
+    let head = &content[..content.len().min(SNIFF_TEXT_LENGTH)];
+    let text = match std::str::from_utf8(head) {
+        Ok(t) => t,
+        // The cut may have landed in the middle of a character, which is fine.
+        Err(e) if e.error_len().is_none() => std::str::from_utf8(&head[..e.valid_up_to()]).unwrap_or_default(),
+        Err(_) => return None,
+    };
+
+    if text.chars().any(|c| c.is_control() && !matches!(c, '\t' | '\n' | '\r' | '\x0c' | '\x1b')) {
+        return None;
+    }
+
+    Some(TXT.content_type)
+}
+
+/// Resolves the content type of a file from its extension, falling back to sniffing the
+/// content (if enabled) and then to the configured `default_type`. The returned flag is true
+/// if the type came from the extension, which makes it safe to send `X-Content-Type-Options: nosniff`.
+pub fn resolve(ext: Option<&str>, content: &[u8], config: &MimeConfig) -> (HttpContentType, bool) {
+    if let Some(content_type) = ext.and_then(|e| lookup(e, config)) {
+        return (content_type, true);
+    }
+
+    let sniffed = if config.sniff { sniff(content) } else { None };
+    let content_type = sniffed.unwrap_or_else(|| {
+        parse_content_type(&config.default_type).unwrap_or(BIN.content_type)
+    });
+
+    (with_default_charset(content_type, config), false)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ext_to_type, load_table, lookup, parse_content_type, resolve, sniff, with_default_charset, SNIFF_TEXT_LENGTH};
+    use crate::config::MimeConfig;
+    use crate::errors::HttpErrors;
+    use crate::headers::{HttpContentType, HttpContentTypeHeader, HttpHeader, StringifyHttpHeader};
+    use std::fs;
+    use std::path::PathBuf;
+
+    fn render(content_type: &HttpContentType) -> String {
+        let mut rendered = format!("{}/{}", content_type.first, content_type.second);
+        for (name, value) in &content_type.parameters {
+            rendered.push_str(&format!("; {name}={value}"));
+        }
+        rendered
+    }
+
+    fn config(types: &[(&str, &str)], files: Vec<String>) -> MimeConfig {
+        let mut config = MimeConfig {
+            files,
+            types: types.iter().map(|(e, t)| (e.to_string(), t.to_string())).collect(),
+            ..MimeConfig::default()
+        };
+        config.table = load_table(&config).unwrap();
+        config
+    }
+
+    /// A `mime.types` file in the temporary directory, removed on drop.
+    struct TypesFile(PathBuf);
+
+    impl TypesFile {
+        fn new(name: &str, contents: &str) -> Self {
+            let path = std::env::temp_dir().join(format!("http-server-rs-{}-media-{name}.types", std::process::id()));
+            fs::write(&path, contents).unwrap();
+            TypesFile(path)
+        }
+
+        fn path(&self) -> String {
+            self.0.display().to_string()
+        }
+    }
+
+    impl Drop for TypesFile {
+        fn drop(&mut self) {
+            let _ = fs::remove_file(&self.0);
+        }
+    }
+
+    #[test]
+    fn built_in_table_ignores_case() {
+        assert_eq!(render(&ext_to_type("js").unwrap().content_type), "text/javascript");
+        assert_eq!(render(&ext_to_type("JSON").unwrap().content_type), "application/json");
+        assert_eq!(render(&ext_to_type("Wasm").unwrap().content_type), "application/wasm");
+        assert_eq!(render(&ext_to_type("pdf").unwrap().content_type), "application/pdf");
+        assert!(ext_to_type("unknown").is_none());
+        assert!(ext_to_type("").is_none());
+    }
+
+    #[test]
+    fn reads_mime_types_files() {
+        let first = TypesFile::new("first", "# comment\ntext/x-a   a aa # trailing comment\n\nnot-a-type b\nimage/X-Shared shared\n");
+        let second = TypesFile::new("second", "image/x-second shared SHOUT\n");
+        let config = config(&[], vec![first.path(), second.path()]);
+
+        assert_eq!(render(&config.table["a"]), "text/x-a");
+        assert_eq!(render(&config.table["aa"]), "text/x-a");
+        assert!(!config.table.contains_key("b"));
+        assert!(!config.table.contains_key("comment"));
+        // Later files win, and extensions and types are lowercased.
+        assert_eq!(render(&config.table["shared"]), "image/x-second");
+        assert_eq!(render(&config.table["shout"]), "image/x-second");
+    }
+
+    #[test]
+    fn overrides_win_over_files() {
+        let file = TypesFile::new("overrides", "text/x-file one two\n");
+        let config = config(&[("one", "text/x-override"), (".Dotted", "application/x-dotted")], vec![file.path()]);
+
+        assert_eq!(render(&config.table["one"]), "text/x-override");
+        assert_eq!(render(&config.table["two"]), "text/x-file");
+        assert_eq!(render(&config.table["dotted"]), "application/x-dotted");
+    }
+
+    #[test]
+    fn rejects_bad_configuration() {
+        let invalid = MimeConfig {
+            types: [(String::from("x"), String::from("not a type"))].into_iter().collect(),
+            ..MimeConfig::default()
+        };
+        assert!(matches!(load_table(&invalid), Err(HttpErrors::ConfigParseFailure(_))));
+
+        let missing = MimeConfig {
+            files: vec![String::from("/nonexistent/mime.types")],
+            ..MimeConfig::default()
+        };
+        assert!(matches!(load_table(&missing), Err(HttpErrors::ConfigReadFailure(_))));
+    }
+
+    #[test]
+    fn lookup_prefers_the_configured_table() {
+        let config = config(&[("js", "application/javascript"), ("dat", "application/x-data")], vec![]);
+        assert_eq!(render(&lookup("JS", &config).unwrap()), "application/javascript; charset=utf-8");
+        assert_eq!(render(&lookup("dat", &config).unwrap()), "application/x-data");
+        assert_eq!(render(&lookup("png", &config).unwrap()), "image/png");
+        assert!(lookup("unknown", &config).is_none());
+    }
+
+    #[test]
+    fn falls_back_to_the_default_type() {
+        let mut config = config(&[], vec![]);
+        let (content_type, from_extension) = resolve(Some("html"), b"", &config);
+        assert_eq!(render(&content_type), "text/html; charset=utf-8");
+        assert!(from_extension);
+
+        let (content_type, from_extension) = resolve(Some("unknown"), b"text", &config);
+        assert_eq!(render(&content_type), "application/octet-stream");
+        assert!(!from_extension);
+
+        config.default_type = String::from("text/plain");
+        assert_eq!(render(&resolve(None, b"", &config).0), "text/plain; charset=utf-8");
+
+        // An unusable default still gives a type.
+        config.default_type = String::from("nonsense");
+        assert_eq!(render(&resolve(None, b"", &config).0), "application/octet-stream");
+    }
+
+    #[test]
+    fn sniffs_magic_numbers() {
+        let cases: [(&[u8], &str); 8] = [
+            (b"\x89PNG\r\n\x1a\n....", "image/png"),
+            (b"\xff\xd8\xff\xe0", "image/jpeg"),
+            (b"GIF87a", "image/gif"),
+            (b"GIF89a..", "image/gif"),
+            (b"%PDF-1.7\n", "application/pdf"),
+            (b"PK\x03\x04rest", "application/zip"),
+            (b"\x1f\x8b\x08", "application/gzip"),
+            (b"\0asm\x01\0\0\0", "application/wasm"),
+        ];
+        for (content, expected) in cases {
+            assert_eq!(render(&sniff(content).unwrap()), expected);
+        }
+    }
+
+    #[test]
+    fn sniffs_text() {
+        assert_eq!(render(&sniff(b"plain text\r\n\twith\x0cbreaks\x1b[0m").unwrap()), "text/plain");
+        assert_eq!(render(&sniff("crème brûlée".as_bytes()).unwrap()), "text/plain");
+        assert_eq!(render(&sniff(b"").unwrap()), "text/plain");
+        // A truncated magic number is just text.
+        assert_eq!(render(&sniff(b"GIF8").unwrap()), "text/plain");
+    }
+
+    #[test]
+    fn tolerates_a_character_cut_at_the_limit() {
+        let mut content = vec![b'a'; SNIFF_TEXT_LENGTH - 1];
+        content.extend_from_slice("é".as_bytes());
+        assert_eq!(render(&sniff(&content).unwrap()), "text/plain");
+
+        // Binary content past the limit isn't looked at.
+        content.extend_from_slice(b"\0\0\0");
+        assert!(sniff(&content).is_some());
+    }
+
+    #[test]
+    fn rejects_binary_content() {
+        assert!(sniff(b"text\0with a nul").is_none());
+        assert!(sniff(b"bell\x07").is_none());
+        assert!(sniff(b"\xff\xfe not utf-8").is_none());
+        // An invalid sequence that isn't at the cut.
+        assert!(sniff(b"a\xc3(b").is_none());
+    }
+
+    #[test]
+    fn resolve_sniffs_only_when_enabled() {
+        let mut config = config(&[], vec![]);
+        let (content_type, _) = resolve(None, b"GIF89a", &config);
+        assert_eq!(render(&content_type), "application/octet-stream");
+
+        config.sniff = true;
+        let (content_type, from_extension) = resolve(Some("unknown"), b"GIF89a", &config);
+        assert_eq!(render(&content_type), "image/gif");
+        assert!(!from_extension);
+        assert_eq!(render(&resolve(None, b"hello", &config).0), "text/plain; charset=utf-8");
+        assert_eq!(render(&resolve(None, b"\0\x01", &config).0), "application/octet-stream");
+
+        // The extension still wins over the content.
+        assert_eq!(render(&resolve(Some("txt"), b"GIF89a", &config).0), "text/plain; charset=utf-8");
+    }
+
+    #[test]
+    fn parses_content_type_parameters() {
+        let parsed = parse_content_type(" Text/HTML ; Charset=\"ISO-8859-1\" ;level = 1").unwrap();
+        assert_eq!(render(&parsed), "text/html; charset=ISO-8859-1; level=1");
+        assert_eq!(parsed.parameter("CHARSET"), Some("ISO-8859-1"));
+        assert_eq!(parsed.parameter("boundary"), None);
+
+        // A repeated parameter keeps the last value.
+        assert_eq!(render(&parse_content_type("text/plain; charset=a; charset=b").unwrap()), "text/plain; charset=b");
+
+        assert!(parse_content_type("text").is_none());
+        assert!(parse_content_type("/plain").is_none());
+        assert!(parse_content_type("text/").is_none());
+        assert!(parse_content_type("a/b/c").is_none());
+        assert!(parse_content_type("text/plain; charset").is_none());
+    }
+
+    #[test]
+    fn adds_the_default_charset_to_text() {
+        let config = MimeConfig::default();
+        let with_charset = |t: &str| render(&with_default_charset(parse_content_type(t).unwrap(), &config));
+
+        assert_eq!(with_charset("text/css"), "text/css; charset=utf-8");
+        assert_eq!(with_charset("application/json"), "application/json; charset=utf-8");
+        assert_eq!(with_charset("application/javascript"), "application/javascript; charset=utf-8");
+        assert_eq!(with_charset("text/html; charset=latin1"), "text/html; charset=latin1");
+        assert_eq!(with_charset("image/svg+xml"), "image/svg+xml");
+        assert_eq!(with_charset("application/ld+json"), "application/ld+json");
+        assert_eq!(with_charset("application/octet-stream"), "application/octet-stream");
+    }
+
+    #[test]
+    fn uses_the_configured_charset() {
+        let mut config = MimeConfig {
+            charset: String::from("iso-8859-1"),
+            ..MimeConfig::default()
+        };
+        assert_eq!(render(&lookup("txt", &config).unwrap()), "text/plain; charset=iso-8859-1");
+
+        // An empty charset turns the default off.
+        config.charset = String::new();
+        assert_eq!(render(&lookup("txt", &config).unwrap()), "text/plain");
+    }
+
+    #[test]
+    fn serializes_the_header() {
+        let content_type = lookup("html", &MimeConfig::default()).unwrap();
+        assert_eq!(
+            HttpContentTypeHeader::new_from(content_type).to_string(),
+            "Content-Type: text/html; charset=utf-8"
+        );
+        assert_eq!(HttpContentTypeHeader::new().to_string(), "Content-Type: text/plain");
+    }
+}
@@ -0,0 +1,203 @@
+use log::{error, info};
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::net::{TcpListener, TcpStream};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
+
+use crate::access;
+use crate::body::read_head;
+use crate::config::Config;
+use crate::errors::HttpErrors;
+use crate::headers;
+use crate::headers::{
+    HttpCacheControlHeader, HttpContent, HttpContentLengthHeader, HttpContentTypeHeader, HttpHeader, HttpResponse,
+    HttpStatusHeader, BAD_REQUEST, INTERNAL_SERVER_ERROR, NOT_FOUND, NOT_IMPLEMENTED, OK, PAYLOAD_TOO_LARGE,
+};
+use crate::logging::AccessEntry;
+use crate::media;
+use crate::parser::HttpRequest;
+use crate::requests::empty_response;
+use crate::status;
+use crate::trace;
+
+/// Content type of the Prometheus text exposition format.
+const CONTENT_TYPE: &str = "text/plain; version=0.0.4; charset=utf-8";
+
+/// Upper bounds of the request duration histogram buckets, in seconds.
+const DURATION_BUCKETS: [f64; 12] = [0.001, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0];
+
+/// Maximum size of a request to the separate metrics listener, in bytes.
+const MAX_HEAD_LENGTH: usize = 8 * 1024;
+
+/// How long the separate metrics listener waits for a client to send or take data.
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+static METRICS: Mutex<Metrics> = Mutex::new(Metrics {
+    requests: BTreeMap::new(),
+    response_bytes: 0,
+    durations: [0; DURATION_BUCKETS.len()],
+    duration_sum: 0.0,
+    duration_count: 0,
+});
+
+static ACCEPT_ERRORS: AtomicU64 = AtomicU64::new(0);
+
+/// Counters of the requests handled so far.
+struct Metrics {
+    /// Requests by method and status.
+    requests: BTreeMap<(&'static str, u16), u64>,
+    response_bytes: u64,
+    /// Requests by the first bucket their duration fits in, slower ones are only counted.
+    durations: [u64; DURATION_BUCKETS.len()],
+    duration_sum: f64,
+    duration_count: u64,
+}
+
+/// Returns true if the request is addressed to the metrics path of the main listener.
+pub fn is_metrics_request(path: &str, config: &Config) -> bool {
+    let metrics = &config.server.metrics;
+    metrics.enable && metrics.address.is_empty() && path == metrics.path
+}
+
+pub fn accept_failed() {
+    ACCEPT_ERRORS.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Counts a request whose response was sent.
+pub fn record(entry: &AccessEntry) {
+    let seconds = entry.duration.as_secs_f64();
+
+    let mut metrics = METRICS.lock().unwrap_or_else(|e| e.into_inner());
+    *metrics.requests.entry((method_label(&entry.request.method), entry.status)).or_insert(0) += 1;
+    metrics.response_bytes += entry.bytes as u64;
+    if let Some(bucket) = DURATION_BUCKETS.iter().position(|b| seconds <= *b) {
+        metrics.durations[bucket] += 1;
+    }
+    metrics.duration_sum += seconds;
+    metrics.duration_count += 1;
+}
+
+/// Counts a request that failed before its response was sent, under the status that
+/// best describes the failure.
+pub fn record_failure(method: &str, error: &HttpErrors) {
+    let status = match error {
+        HttpErrors::InvalidRequest(_)
+        | HttpErrors::Utf8ConversionFailure
+        | HttpErrors::UnsupportedProtocol
+        | HttpErrors::GeneralReadFailure
+        | HttpErrors::StreamReadFailure(_) => BAD_REQUEST.code,
+        HttpErrors::PayloadTooLarge(_) => PAYLOAD_TOO_LARGE.code,
+        HttpErrors::UnsupportedRequestType(_) => NOT_IMPLEMENTED.code,
+        _ => INTERNAL_SERVER_ERROR.code,
+    };
+
+    let mut metrics = METRICS.lock().unwrap_or_else(|e| e.into_inner());
+    *metrics.requests.entry((method_label(method), status)).or_insert(0) += 1;
+}
+
+/// Unknown methods share a label, so clients can't create new series at will.
+fn method_label(method: &str) -> &'static str {
+    access::ALL_METHODS.iter().find(|m| **m == method).copied().unwrap_or("OTHER")
+}
+
+/// Reports the counters in the Prometheus text exposition format. Files are read from disk
+/// for every request, there is no file cache whose hit rate could be reported.
+pub fn metrics() -> Result<HttpResponse, HttpErrors> {
+    let metrics = METRICS.lock().unwrap_or_else(|e| e.into_inner());
+    let mut text = String::new();
+
+    text.push_str("# HELP http_requests_total Requests handled, by method and status.\n");
+    text.push_str("# TYPE http_requests_total counter\n");
+    for ((method, status), count) in &metrics.requests {
+        text.push_str(&format!("http_requests_total{{method=\"{method}\",status=\"{status}\"}} {count}\n"));
+    }
+
+    text.push_str("# HELP http_response_bytes_total Bytes of response bodies sent.\n");
+    text.push_str("# TYPE http_response_bytes_total counter\n");
+    text.push_str(&format!("http_response_bytes_total {}\n", metrics.response_bytes));
+
+    text.push_str("# HELP http_request_duration_seconds Time taken to handle requests.\n");
+    text.push_str("# TYPE http_request_duration_seconds histogram\n");
+    let mut cumulative = 0;
+    for (bound, count) in DURATION_BUCKETS.iter().zip(metrics.durations) {
+        cumulative += count;
+        text.push_str(&format!("http_request_duration_seconds_bucket{{le=\"{bound}\"}} {cumulative}\n"));
+    }
+    text.push_str(&format!("http_request_duration_seconds_bucket{{le=\"+Inf\"}} {}\n", metrics.duration_count));
+    text.push_str(&format!("http_request_duration_seconds_sum {}\n", metrics.duration_sum));
+    text.push_str(&format!("http_request_duration_seconds_count {}\n", metrics.duration_count));
+
+    text.push_str("# HELP http_active_connections Connections being handled.\n");
+    text.push_str("# TYPE http_active_connections gauge\n");
+    text.push_str(&format!("http_active_connections {}\n", status::active_connections()));
+
+    text.push_str("# HELP http_accept_errors_total Connections that failed to be accepted.\n");
+    text.push_str("# TYPE http_accept_errors_total counter\n");
+    text.push_str(&format!("http_accept_errors_total {}\n", ACCEPT_ERRORS.load(Ordering::Relaxed)));
+
+    text.push_str("# HELP http_dropped_spans_total Trace spans dropped because the exporter fell behind.\n");
+    text.push_str("# TYPE http_dropped_spans_total counter\n");
+    text.push_str(&format!("http_dropped_spans_total {}\n", trace::dropped_spans()));
+
+    let content = HttpContent::from(text);
+    Ok(HttpResponse {
+        headers: headers!(
+            HttpStatusHeader::new_from(OK.code);
+            HttpContentTypeHeader::new_from(media::parse_content_type(CONTENT_TYPE).unwrap());
+            HttpContentLengthHeader::new_from(content.content.len());
+            HttpCacheControlHeader::new_from(String::from("no-store"));
+        )
+        .build(),
+        content,
+    })
+}
+
+/// Serves the metrics on their own address, if `server.metrics.address` is set, so they can
+/// be kept off the public listener.
+pub fn spawn_listener(config: &Config) -> Result<(), HttpErrors> {
+    let metrics = &config.server.metrics;
+    if !metrics.enable || metrics.address.is_empty() {
+        return Ok(());
+    }
+
+    let listener = TcpListener::bind(&metrics.address)
+        .map_err(|e| HttpErrors::TcpListenerBindFailure(metrics.address.clone(), e.to_string()))?;
+    info!("Metrics available at http://{}{}", metrics.address, metrics.path);
+
+    let path = metrics.path.clone();
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let result = stream
+                .map_err(|e| HttpErrors::StreamAcceptFailure(e.to_string()))
+                .and_then(|mut stream| serve_metrics(&mut stream, &path));
+            if let Err(e) = result {
+                error!("{e}");
+            }
+        }
+    });
+
+    Ok(())
+}
+
+fn serve_metrics(stream: &mut TcpStream, path: &str) -> Result<(), HttpErrors> {
+    // The listener serves one connection at a time, which a stalled client mustn't block.
+    stream
+        .set_read_timeout(Some(TIMEOUT))
+        .and_then(|_| stream.set_write_timeout(Some(TIMEOUT)))
+        .map_err(|e| HttpErrors::StreamReadFailure(e.to_string()))?;
+    let (head, _) = read_head(stream, MAX_HEAD_LENGTH)?;
+    let request = HttpRequest::parse(&head)?;
+    let response = match request.method.as_str() {
+        "GET" if request.path == path => metrics()?,
+        _ => empty_response(NOT_FOUND.code),
+    };
+
+    stream
+        .write_all(response.headers.as_bytes())
+        .and_then(|_| stream.write_all(&response.content.content))
+        .and_then(|_| stream.flush())
+        .map_err(|e| HttpErrors::StreamWriteFailure(e.to_string()))
+}
@@ -0,0 +1,342 @@
+use std::io::{Read, Write};
+
+use crate::errors::HttpErrors;
+use crate::media;
+
+/// Maximum size of the headers of a single part, in bytes.
+const MAX_PART_HEADERS_LENGTH: usize = 8 * 1024;
+
+/// Amount of bytes requested from the underlying reader at once.
+const READ_CHUNK_LENGTH: usize = 64 * 1024;
+
+/// Headers of a single part of a `multipart/form-data` body.
+pub struct PartHeaders {
+    pub name: String,
+    pub filename: Option<String>,
+    pub content_type: Option<String>,
+}
+
+/// Streaming `multipart/form-data` parser. Only a small window of the body is held in
+/// memory at any time, parts are written out as they are read.
+pub struct MultipartParser<R: Read> {
+    reader: R,
+    /// `\r\n--boundary`, which precedes every part and the closing delimiter.
+    delimiter: Vec<u8>,
+    buf: Vec<u8>,
+    eof: bool,
+    done: bool,
+    total_read: u64,
+    max_total: u64,
+}
+
+impl<R: Read> MultipartParser<R> {
+    /// Creates a parser for the body of a request with the provided `Content-Type`. Fails if
+    /// it isn't `multipart/form-data` or lacks a boundary.
+    pub fn new(reader: R, content_type: &str, max_total: u64) -> Result<Self, HttpErrors> {
+        let parsed = media::parse_content_type(content_type)
+            .filter(|c| c.first == "multipart" && c.second == "form-data")
+            .ok_or_else(|| HttpErrors::InvalidRequest(format!("not multipart/form-data: `{content_type}`")))?;
+        let boundary = parsed
+            .parameter("boundary")
+            .filter(|b| !b.is_empty() && b.len() <= 70)
+            .ok_or_else(|| HttpErrors::InvalidRequest(String::from("missing multipart boundary")))?;
+
+        Ok(MultipartParser {
+            reader,
+            delimiter: format!("\r\n--{boundary}").into_bytes(),
+            // The first delimiter isn't preceded by a line break, pretend it is.
+            buf: b"\r\n".to_vec(),
+            eof: false,
+            done: false,
+            total_read: 0,
+            max_total,
+        })
+    }
+
+    /// Advances to the next part and returns its headers, or [`None`] after the closing delimiter.
+    pub fn next_part(&mut self) -> Result<Option<PartHeaders>, HttpErrors> {
+        if self.done {
+            return Ok(None);
+        }
+
+        // Skip the preamble, or the rest of a part that wasn't read.
+        loop {
+            if let Some(i) = find(&self.buf, &self.delimiter) {
+                self.buf.drain(..i + self.delimiter.len());
+                break;
+            }
+            let keep = self.buf.len().min(self.delimiter.len() - 1);
+            self.buf.drain(..self.buf.len() - keep);
+            if !self.fill()? {
+                return Err(malformed("missing boundary"));
+            }
+        }
+
+        while self.buf.len() < 2 {
+            if !self.fill()? {
+                return Err(malformed("truncated body"));
+            }
+        }
+        if self.buf.starts_with(b"--") {
+            self.done = true;
+            return Ok(None);
+        }
+
+        let headers_end = loop {
+            if let Some(i) = find(&self.buf, b"\r\n\r\n") {
+                break i;
+            }
+            if self.buf.len() > MAX_PART_HEADERS_LENGTH {
+                return Err(malformed("part headers too large"));
+            }
+            if !self.fill()? {
+                return Err(malformed("truncated part headers"));
+            }
+        };
+
+        let raw_headers = String::from_utf8_lossy(&self.buf[..headers_end]).to_string();
+        self.buf.drain(..headers_end + 4);
+
+        let mut headers = PartHeaders { name: String::new(), filename: None, content_type: None };
+        for line in raw_headers.lines() {
+            let Some((name, value)) = line.split_once(':') else {
+                continue;
+            };
+            if name.trim().eq_ignore_ascii_case("Content-Disposition") {
+                for (key, value) in parameters(value) {
+                    match key.as_str() {
+                        "name" => headers.name = value,
+                        "filename" => headers.filename = Some(value),
+                        _ => (),
+                    }
+                }
+            } else if name.trim().eq_ignore_ascii_case("Content-Type") {
+                headers.content_type = Some(value.trim().to_string());
+            }
+        }
+
+        Ok(Some(headers))
+    }
+
+    /// Writes the content of the current part to `out`. Fails with
+    /// [`HttpErrors::PayloadTooLarge`] if it is longer than `limit` bytes.
+    pub fn copy_part(&mut self, out: &mut dyn Write, limit: u64) -> Result<u64, HttpErrors> {
+        let mut written = 0u64;
+
+        loop {
+            let (end, found) = match find(&self.buf, &self.delimiter) {
+                Some(i) => (i, true),
+                None => (self.buf.len().saturating_sub(self.delimiter.len() - 1), false),
+            };
+
+            written += end as u64;
+            if written > limit {
+                return Err(HttpErrors::PayloadTooLarge(format!("part exceeds {limit} bytes")));
+            }
+            out.write_all(&self.buf[..end]).map_err(|e| HttpErrors::FileWriteFailure(e.to_string()))?;
+            self.buf.drain(..end);
+
+            if found {
+                return Ok(written);
+            }
+            if !self.fill()? {
+                return Err(malformed("truncated part"));
+            }
+        }
+    }
+
+    /// Reads more of the body into the buffer. Returns false at the end of the body.
+    fn fill(&mut self) -> Result<bool, HttpErrors> {
+        if self.eof {
+            return Ok(false);
+        }
+
+        let mut chunk = vec![0; READ_CHUNK_LENGTH];
+        let read = self.reader.read(&mut chunk).map_err(|e| HttpErrors::StreamReadFailure(e.to_string()))?;
+        if read == 0 {
+            self.eof = true;
+            return Ok(false);
+        }
+
+        self.total_read += read as u64;
+        if self.total_read > self.max_total {
+            return Err(HttpErrors::PayloadTooLarge(format!("body exceeds {} bytes", self.max_total)));
+        }
+        self.buf.extend_from_slice(&chunk[..read]);
+        Ok(true)
+    }
+}
+
+/// Splits the `key=value` parameters out of a header value like `form-data; name="a"`,
+/// lowercasing the keys. Quoted values may contain `;` and escaped quotes (`\"`). Other
+/// backslashes are kept, since browsers send Windows paths without escaping them.
+fn parameters(value: &str) -> Vec<(String, String)> {
+    let mut parameters = vec![];
+    let mut chars = value.chars().skip_while(|c| *c != ';').skip(1).peekable();
+
+    while chars.peek().is_some() {
+        let (mut key, mut value) = (String::new(), String::new());
+        let (mut in_value, mut quoted) = (false, false);
+        while let Some(c) = chars.next() {
+            match c {
+                '"' if in_value => quoted = !quoted,
+                '\\' if quoted && chars.peek() == Some(&'"') => value.extend(chars.next()),
+                ';' if !quoted => break,
+                '=' if !in_value => in_value = true,
+                c if in_value => {
+                    if quoted || !c.is_whitespace() {
+                        value.push(c);
+                    }
+                }
+                c => key.push(c),
+            }
+        }
+        if in_value {
+            parameters.push((key.trim().to_ascii_lowercase(), value));
+        }
+    }
+
+    parameters
+}
+
+fn malformed(reason: &str) -> HttpErrors {
+    HttpErrors::InvalidRequest(format!("malformed multipart body: {reason}"))
+}
+
+/// Returns the position of the first occurrence of `needle` in `haystack`.
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|w| w == needle)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parameters, MultipartParser, READ_CHUNK_LENGTH};
+    use crate::errors::HttpErrors;
+    use std::io::{self, Read};
+
+    const CONTENT_TYPE: &str = "multipart/form-data; boundary=XyZ";
+
+    /// Hands out a body a few bytes at a time, so delimiters get split across reads.
+    struct Trickle<'a> {
+        data: &'a [u8],
+        step: usize,
+    }
+
+    impl Read for Trickle<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let length = self.step.min(buf.len()).min(self.data.len());
+            buf[..length].copy_from_slice(&self.data[..length]);
+            self.data = &self.data[length..];
+            Ok(length)
+        }
+    }
+
+    fn body(parts: &[(&str, &str)]) -> Vec<u8> {
+        let mut body = b"preamble\r\n".to_vec();
+        for (disposition, content) in parts {
+            body.extend(format!("--XyZ\r\nContent-Disposition: form-data; {disposition}\r\n\r\n{content}\r\n").bytes());
+        }
+        body.extend(b"--XyZ--\r\nepilogue");
+        body
+    }
+
+    /// Name, file name and content of a part.
+    type Part = (String, Option<String>, Vec<u8>);
+
+    /// Parses `body` handed out `step` bytes at a time.
+    fn parse(body: &[u8], step: usize, max_total: u64, limit: u64) -> Result<Vec<Part>, HttpErrors> {
+        let mut parser = MultipartParser::new(Trickle { data: body, step }, CONTENT_TYPE, max_total)?;
+        let mut parts = vec![];
+        while let Some(part) = parser.next_part()? {
+            let mut content = vec![];
+            parser.copy_part(&mut content, limit)?;
+            parts.push((part.name, part.filename, content));
+        }
+        Ok(parts)
+    }
+
+    #[test]
+    fn parses_parts_split_across_reads() {
+        let body = body(&[("name=\"a\"", "first"), ("name=\"f\"; filename=\"x.txt\"", "line\r\n--Xy\r\n-\r")]);
+        let expected = vec![
+            (String::from("a"), None, b"first".to_vec()),
+            (String::from("f"), Some(String::from("x.txt")), b"line\r\n--Xy\r\n-\r".to_vec()),
+        ];
+        for step in [1, 2, 3, 7, 64, READ_CHUNK_LENGTH] {
+            assert_eq!(parse(&body, step, u64::MAX, u64::MAX).unwrap(), expected, "step {step}");
+        }
+    }
+
+    #[test]
+    fn parts_that_are_not_read_are_skipped() {
+        let body = body(&[("name=\"a\"", "skipped"), ("name=\"b\"", "kept")]);
+        let mut parser = MultipartParser::new(Trickle { data: &body, step: 3 }, CONTENT_TYPE, u64::MAX).unwrap();
+        assert_eq!(parser.next_part().unwrap().unwrap().name, "a");
+        assert_eq!(parser.next_part().unwrap().unwrap().name, "b");
+        let mut content = vec![];
+        parser.copy_part(&mut content, 100).unwrap();
+        assert_eq!(content, b"kept");
+        assert!(parser.next_part().unwrap().is_none());
+        assert!(parser.next_part().unwrap().is_none());
+    }
+
+    #[test]
+    fn empty_form_has_no_parts() {
+        assert!(parse(b"--XyZ--\r\n", 5, u64::MAX, u64::MAX).unwrap().is_empty());
+    }
+
+    #[test]
+    fn needs_a_boundary() {
+        assert!(MultipartParser::new(io::empty(), "multipart/form-data", 10).is_err());
+        assert!(MultipartParser::new(io::empty(), "multipart/form-data; boundary=", 10).is_err());
+        assert!(MultipartParser::new(io::empty(), &format!("multipart/form-data; boundary={}", "a".repeat(71)), 10).is_err());
+        assert!(MultipartParser::new(io::empty(), "text/plain; boundary=XyZ", 10).is_err());
+        assert!(MultipartParser::new(io::empty(), "multipart/form-data; boundary=\"XyZ\"", 10).is_ok());
+    }
+
+    #[test]
+    fn truncated_bodies_are_malformed() {
+        assert!(matches!(parse(b"no delimiter here", 4, u64::MAX, u64::MAX), Err(HttpErrors::InvalidRequest(_))));
+        assert!(matches!(
+            parse(b"--XyZ\r\nContent-Disposition: form-data; name=\"a\"\r\n\r\nunfinished", 4, u64::MAX, u64::MAX),
+            Err(HttpErrors::InvalidRequest(_))
+        ));
+        assert!(matches!(parse(b"--XyZ\r\nContent-Disposition: form-data", 4, u64::MAX, u64::MAX), Err(HttpErrors::InvalidRequest(_))));
+    }
+
+    #[test]
+    fn enforces_part_and_total_limits() {
+        let body = body(&[("name=\"a\"", "0123456789")]);
+        assert_eq!(parse(&body, 4, u64::MAX, 10).unwrap()[0].2, b"0123456789");
+        assert!(matches!(parse(&body, 4, u64::MAX, 9), Err(HttpErrors::PayloadTooLarge(_))));
+        assert!(parse(&body, 4, body.len() as u64, u64::MAX).is_ok());
+        assert!(matches!(parse(&body, 4, body.len() as u64 / 2, u64::MAX), Err(HttpErrors::PayloadTooLarge(_))));
+    }
+
+    #[test]
+    fn quoted_parameters_may_contain_separators() {
+        assert_eq!(
+            parameters(r#"form-data; name="f"; filename="a;b.txt""#),
+            vec![(String::from("name"), String::from("f")), (String::from("filename"), String::from("a;b.txt"))]
+        );
+        assert_eq!(parameters(r#"form-data; NAME = "x=y" ; filename=plain"#), vec![
+            (String::from("name"), String::from("x=y")),
+            (String::from("filename"), String::from("plain")),
+        ]);
+        assert_eq!(parameters(r#"form-data; filename="say \"hi\".txt""#)[0].1, r#"say "hi".txt"#);
+        assert_eq!(parameters(r#"form-data; filename="C:\Users\a.txt""#)[0].1, r"C:\Users\a.txt");
+        assert_eq!(parameters(r#"form-data; filename=" spaced name ""#)[0].1, " spaced name ");
+        assert!(parameters("form-data").is_empty());
+        assert!(parameters("form-data; flag").is_empty());
+    }
+
+    #[test]
+    fn reads_part_headers() {
+        let body = b"--XyZ\r\ncontent-disposition: form-data; name=\"f\"; filename=\"a;b.txt\"\r\nContent-Type: text/plain\r\n\r\nx\r\n--XyZ--";
+        let mut parser = MultipartParser::new(&body[..], CONTENT_TYPE, u64::MAX).unwrap();
+        let part = parser.next_part().unwrap().unwrap();
+        assert_eq!(part.name, "f");
+        assert_eq!(part.filename.as_deref(), Some("a;b.txt"));
+        assert_eq!(part.content_type.as_deref(), Some("text/plain"));
+    }
+}
@@ -0,0 +1,233 @@
+use crate::errors::HttpErrors;
+
+/// Holds the parsed request line and headers of an incoming request.
+pub struct HttpRequest {
+    pub method: String,
+    /// Request target as sent by the client (e.g. `/docs/index%20page.html?lang=en`).
+    pub target: String,
+    /// Protocol version from the request line (e.g. `HTTP/1.1`).
+    pub version: String,
+    /// Percent-decoded path, without the query string (e.g. `/docs/index.html`).
+    pub path: String,
+    pub query: Vec<(String, String)>,
+    pub headers: Vec<(String, String)>,
+}
+
+impl HttpRequest {
+    /// Parses the request line and headers out of the raw request.
+    pub fn parse(request: &str) -> Result<Self, HttpErrors> {
+        let head = request.split("\r\n\r\n").next().unwrap_or_default();
+        let mut lines = head.lines();
+
+        let request_line = lines
+            .next()
+            .ok_or_else(|| HttpErrors::InvalidRequest(String::from("empty request")))?;
+        let mut request_line_sections = request_line.split_whitespace();
+        let (method, target, version) = match (
+            request_line_sections.next(),
+            request_line_sections.next(),
+            request_line_sections.next(),
+        ) {
+            (Some(m), Some(t), Some(v)) => (m, t, v),
+            _ => return Err(HttpErrors::InvalidRequest(request_line.to_string())),
+        };
+
+        if !version.starts_with("HTTP/") {
+            return Err(HttpErrors::UnsupportedProtocol);
+        }
+
+        let (raw_path, raw_query) = match target.split_once('?') {
+            Some((p, q)) => (p, q),
+            None => (target, ""),
+        };
+
+        let path = percent_decode(raw_path)
+            .ok_or_else(|| HttpErrors::InvalidRequest(target.to_string()))?;
+        if !path.starts_with('/') {
+            return Err(HttpErrors::InvalidRequest(target.to_string()));
+        }
+
+        let query = parse_urlencoded(raw_query);
+
+        let headers = lines
+            .filter_map(|line| line.split_once(':'))
+            .map(|(name, value)| (name.trim().to_string(), value.trim().to_string()))
+            .collect();
+
+        Ok(HttpRequest {
+            method: method.to_string(),
+            target: target.to_string(),
+            version: version.to_string(),
+            path,
+            query,
+            headers,
+        })
+    }
+
+    /// Returns the value of the first header matching `name`, case-insensitively.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+    }
+
+    /// Returns the parsed `Content-Length` header, if the request has one.
+    pub fn content_length(&self) -> Result<Option<u64>, HttpErrors> {
+        self.header("Content-Length")
+            .map(|l| l.parse::<u64>().map_err(|_| HttpErrors::InvalidRequest(format!("invalid Content-Length `{l}`"))))
+            .transpose()
+    }
+
+    /// Returns the value of the first query parameter matching `name`.
+    pub fn query(&self, name: &str) -> Option<&str> {
+        self.query
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, v)| v.as_str())
+    }
+
+    /// Returns true if the `Accept` header lists the provided media type.
+    pub fn accepts(&self, media_type: &str) -> bool {
+        self.header("Accept").is_some_and(|accept| {
+            accept
+                .split(',')
+                .map(|t| t.split(';').next().unwrap_or_default().trim())
+                .any(|t| t.eq_ignore_ascii_case(media_type))
+        })
+    }
+}
+
+/// Decodes `application/x-www-form-urlencoded` data, as used by query strings and HTML forms.
+pub fn parse_urlencoded(input: &str) -> Vec<(String, String)> {
+    input
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+            (
+                percent_decode(&key.replace('+', " ")).unwrap_or_default(),
+                percent_decode(&value.replace('+', " ")).unwrap_or_default(),
+            )
+        })
+        .collect()
+}
+
+/// Decodes `%XX` escapes. Returns [`None`] if an escape is malformed or the
+/// result is not valid UTF-8.
+pub fn percent_decode(input: &str) -> Option<String> {
+    let bytes = input.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            // `from_str_radix` would also take a sign, as in `%+1`.
+            let hex = input.get(i + 1..i + 3).filter(|h| h.bytes().all(|b| b.is_ascii_hexdigit()))?;
+            decoded.push(u8::from_str_radix(hex, 16).ok()?);
+            i += 3;
+        } else {
+            decoded.push(bytes[i]);
+            i += 1;
+        }
+    }
+
+    String::from_utf8(decoded).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_urlencoded as pairs, percent_decode, HttpRequest};
+    use crate::errors::HttpErrors;
+
+    fn owned(pairs: &[(&str, &str)]) -> Vec<(String, String)> {
+        pairs.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect()
+    }
+
+    #[test]
+    fn decodes_urlencoded_pairs() {
+        assert_eq!(pairs("a=1&b=two+words&c=%26%3D%2B"), owned(&[("a", "1"), ("b", "two words"), ("c", "&=+")]));
+        assert_eq!(pairs("caf%C3%A9=cr%C3%A8me"), owned(&[("café", "crème")]));
+        assert_eq!(pairs("a=1=2"), owned(&[("a", "1=2")]));
+    }
+
+    #[test]
+    fn keeps_keys_without_values() {
+        assert_eq!(pairs("flag&a=&=b"), owned(&[("flag", ""), ("a", ""), ("", "b")]));
+    }
+
+    #[test]
+    fn skips_empty_pairs() {
+        assert!(pairs("").is_empty());
+        assert_eq!(pairs("&&a=1&"), owned(&[("a", "1")]));
+    }
+
+    #[test]
+    fn malformed_escapes_decode_to_nothing() {
+        assert_eq!(pairs("a=%zz&b=%C3&c=%4"), owned(&[("a", ""), ("b", ""), ("c", "")]));
+    }
+
+    #[test]
+    fn percent_decodes_paths() {
+        assert_eq!(percent_decode("/a%20b/c").as_deref(), Some("/a b/c"));
+        assert_eq!(percent_decode("%2Fetc%2fpasswd").as_deref(), Some("/etc/passwd"));
+        assert_eq!(percent_decode("/caf%C3%A9").as_deref(), Some("/café"));
+        assert_eq!(percent_decode("/café+x").as_deref(), Some("/café+x"));
+        assert_eq!(percent_decode("").as_deref(), Some(""));
+    }
+
+    #[test]
+    fn rejects_malformed_escapes() {
+        assert_eq!(percent_decode("%"), None);
+        assert_eq!(percent_decode("/a%2"), None);
+        assert_eq!(percent_decode("/a%g0"), None);
+        assert_eq!(percent_decode("/a%+1"), None);
+        assert_eq!(percent_decode("/%FF"), None);
+        assert_eq!(percent_decode("/%C3"), None);
+        // An escape that would split a multibyte character isn't one.
+        assert_eq!(percent_decode("%é"), None);
+    }
+
+    #[test]
+    fn splits_target_into_path_and_query() {
+        let request = HttpRequest::parse("GET /docs/a%20b.html?lang=en&x=%3F HTTP/1.1\r\nHost: a\r\n\r\n").unwrap();
+        assert_eq!(request.method, "GET");
+        assert_eq!(request.target, "/docs/a%20b.html?lang=en&x=%3F");
+        assert_eq!(request.version, "HTTP/1.1");
+        assert_eq!(request.path, "/docs/a b.html");
+        assert_eq!(request.query("lang"), Some("en"));
+        assert_eq!(request.query("x"), Some("?"));
+        assert_eq!(request.query("missing"), None);
+
+        // An encoded `?` belongs to the path.
+        assert_eq!(HttpRequest::parse("GET /a%3Fb HTTP/1.1\r\n\r\n").unwrap().path, "/a?b");
+    }
+
+    #[test]
+    fn rejects_bad_request_lines() {
+        assert!(matches!(HttpRequest::parse(""), Err(HttpErrors::InvalidRequest(_))));
+        assert!(matches!(HttpRequest::parse("GET /\r\n\r\n"), Err(HttpErrors::InvalidRequest(_))));
+        assert!(matches!(HttpRequest::parse("GET / FTP/1.0\r\n\r\n"), Err(HttpErrors::UnsupportedProtocol)));
+        assert!(matches!(HttpRequest::parse("GET a/b HTTP/1.1\r\n\r\n"), Err(HttpErrors::InvalidRequest(_))));
+        assert!(matches!(HttpRequest::parse("GET http://host/ HTTP/1.1\r\n\r\n"), Err(HttpErrors::InvalidRequest(_))));
+        assert!(matches!(HttpRequest::parse("GET /%zz HTTP/1.1\r\n\r\n"), Err(HttpErrors::InvalidRequest(_))));
+    }
+
+    #[test]
+    fn reads_headers() {
+        let request = HttpRequest::parse(
+            "PUT / HTTP/1.1\r\ncontent-length:  12 \r\nAccept: text/html;q=0.9, Application/JSON\r\nbroken line\r\n\r\nbody: no",
+        )
+        .unwrap();
+        assert_eq!(request.header("Content-Length"), Some("12"));
+        assert_eq!(request.content_length().unwrap(), Some(12));
+        assert!(request.accepts("application/json"));
+        assert!(request.accepts("text/html"));
+        assert!(!request.accepts("text/plain"));
+        assert_eq!(request.header("body"), None);
+        assert_eq!(request.headers.len(), 2);
+
+        let request = HttpRequest::parse("PUT / HTTP/1.1\r\nContent-Length: -1\r\n\r\n").unwrap();
+        assert!(request.content_length().is_err());
+    }
+}
@@ -0,0 +1,190 @@
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::PathBuf;
+use fs_err as fs;
+
+use crate::body::HttpBody;
+use crate::config::Config;
+use crate::error_pages::error_response;
+use crate::errors::HttpErrors;
+use crate::headers;
+use crate::headers::{
+    HttpContent, HttpContentLengthHeader, HttpETagHeader, HttpHeader, HttpResponse, HttpStatusHeader, BAD_REQUEST,
+    FORBIDDEN, NOT_FOUND, NO_CONTENT, PRECONDITION_FAILED, PRECONDITION_REQUIRED, RANGE_NOT_SATISFIABLE, UNAUTHORIZED,
+};
+use crate::parser::HttpRequest;
+use crate::preconditions;
+use crate::quota;
+use crate::requests::to_local_path;
+use crate::upload::is_writable;
+
+/// Where a `PATCH` request writes its body.
+enum PatchTarget {
+    /// At the end of the file.
+    Append,
+    /// Over the inclusive byte range `start..=end`.
+    Range { start: u64, end: u64 },
+}
+
+/// Parses the `Content-Range` and `X-Append` headers into a [`PatchTarget`].
+/// `Content-Range: bytes */*` is treated the same as `X-Append: true`.
+fn parse_target(request: &HttpRequest) -> Option<PatchTarget> {
+    if request.header("X-Append").is_some_and(|a| a.eq_ignore_ascii_case("true")) {
+        return Some(PatchTarget::Append);
+    }
+
+    let range = request.header("Content-Range")?.strip_prefix("bytes ")?.trim();
+    let (range, _total) = range.split_once('/')?;
+    if range == "*" {
+        return Some(PatchTarget::Append);
+    }
+
+    let (start, end) = range.split_once('-')?;
+    let (start, end) = (start.trim().parse::<u64>().ok()?, end.trim().parse::<u64>().ok()?);
+    if end < start {
+        return None;
+    }
+
+    Some(PatchTarget::Range { start, end })
+}
+
+/// Returns the amount of bytes in the inclusive range `start..=end`, or [`None`] if the range
+/// is reversed or ends past the largest offset a `u64` can address.
+fn range_length(start: u64, end: u64) -> Option<u64> {
+    end.checked_add(1)?.checked_sub(start).filter(|l| *l > 0)
+}
+
+/// Appends to, or overwrites part of, an existing file. The client has to prove it knows the
+/// current state of the file through `If-Match` so concurrent updates aren't lost.
+pub fn patch(request: &HttpRequest, body: &mut HttpBody, config: &Config) -> Result<HttpResponse, HttpErrors> {
+    if request.path.contains("..") {
+        return Ok(error_response(UNAUTHORIZED.code, request, config));
+    }
+
+    if !is_writable(&request.path, config) {
+        return Ok(error_response(FORBIDDEN.code, request, config));
+    }
+
+    let target = PathBuf::from(to_local_path(&config.server.root, &request.path));
+    let metadata = match fs::metadata(&target) {
+        Ok(m) if m.is_file() => m,
+        _ => return Ok(error_response(NOT_FOUND.code, request, config)),
+    };
+
+    if request.header("If-Match").is_none() {
+        return Ok(error_response(PRECONDITION_REQUIRED.code, request, config));
+    }
+
+    if !preconditions::check(request, Some(&metadata)) {
+        return Ok(error_response(PRECONDITION_FAILED.code, request, config));
+    }
+
+    let Some(patch_target) = parse_target(request) else {
+        return Ok(error_response(BAD_REQUEST.code, request, config));
+    };
+
+    let mut file = fs::OpenOptions::new()
+        .write(true)
+        .open(&target)
+        .map_err(|e| HttpErrors::FileWriteFailure(e.to_string()))?;
+
+    let allowance = quota::allowance(&request.path, 0, config);
+    match patch_target {
+        PatchTarget::Append => {
+            if !allowance.permits(request.content_length()?.unwrap_or(0)) {
+                return Ok(error_response(allowance.code, request, config));
+            }
+
+            file.seek(SeekFrom::End(0)).map_err(|e| HttpErrors::FileWriteFailure(e.to_string()))?;
+            let copied = io::copy(&mut body.take(allowance.max.saturating_add(1)), &mut file)
+                .map_err(|e| HttpErrors::StreamReadFailure(e.to_string()))?;
+            if !allowance.permits(copied) {
+                // Undo the partial append of a chunked body that turned out too large.
+                file.file().set_len(metadata.len()).map_err(|e| HttpErrors::FileWriteFailure(e.to_string()))?;
+                return Ok(error_response(allowance.code, request, config));
+            }
+        }
+        PatchTarget::Range { start, end } => {
+            let Some(length) = range_length(start, end) else {
+                return Ok(error_response(RANGE_NOT_SATISFIABLE.code, request, config));
+            };
+            if start > metadata.len() {
+                return Ok(error_response(RANGE_NOT_SATISFIABLE.code, request, config));
+            }
+            if request.content_length()? != Some(length) {
+                return Ok(error_response(BAD_REQUEST.code, request, config));
+            }
+            // `range_length` succeeded, so `end + 1` fits.
+            if !allowance.permits((end + 1).saturating_sub(metadata.len())) {
+                return Ok(error_response(allowance.code, request, config));
+            }
+
+            file.seek(SeekFrom::Start(start)).map_err(|e| HttpErrors::FileWriteFailure(e.to_string()))?;
+            io::copy(&mut body.take(length), &mut file).map_err(|e| HttpErrors::StreamReadFailure(e.to_string()))?;
+        }
+    }
+
+    file.flush().map_err(|e| HttpErrors::FileWriteFailure(e.to_string()))?;
+    file.file().sync_all().map_err(|e| HttpErrors::FileWriteFailure(e.to_string()))?;
+    let grown = fs::metadata(&target).map_err(|e| HttpErrors::FileReadFailure(e.to_string()))?;
+    quota::record_write(&request.path, grown.len(), metadata.len());
+
+    let content = HttpContent::default();
+    Ok(HttpResponse {
+        headers: headers!(
+            HttpStatusHeader::new_from(NO_CONTENT.code);
+            HttpETagHeader::new_from(preconditions::etag(&grown));
+            HttpContentLengthHeader::new_from(content.content.len());
+        )
+        .build(),
+        content,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_target, range_length, PatchTarget};
+    use crate::parser::HttpRequest;
+
+    fn target(headers: &str) -> Option<PatchTarget> {
+        parse_target(&HttpRequest::parse(&format!("PATCH /a HTTP/1.1\r\n{headers}\r\n\r\n")).unwrap())
+    }
+
+    #[test]
+    fn parses_ranges() {
+        assert!(matches!(target("Content-Range: bytes 0-4/*"), Some(PatchTarget::Range { start: 0, end: 4 })));
+        assert!(matches!(target("Content-Range: bytes 10-10/20"), Some(PatchTarget::Range { start: 10, end: 10 })));
+        assert!(matches!(
+            target("Content-Range: bytes 0-18446744073709551615/*"),
+            Some(PatchTarget::Range { start: 0, end: u64::MAX })
+        ));
+    }
+
+    #[test]
+    fn rejects_reversed_and_malformed_ranges() {
+        assert!(target("Content-Range: bytes 5-4/*").is_none());
+        assert!(target("Content-Range: bytes 0-4").is_none());
+        assert!(target("Content-Range: bytes 0-/*").is_none());
+        assert!(target("Content-Range: bytes -4/*").is_none());
+        assert!(target("Content-Range: bytes 0-18446744073709551616/*").is_none());
+        assert!(target("Content-Range: items 0-4/*").is_none());
+        assert!(target("").is_none());
+    }
+
+    #[test]
+    fn parses_appends() {
+        assert!(matches!(target("X-Append: true"), Some(PatchTarget::Append)));
+        assert!(matches!(target("X-Append: TRUE\r\nContent-Range: bytes 0-4/*"), Some(PatchTarget::Append)));
+        assert!(matches!(target("Content-Range: bytes */*"), Some(PatchTarget::Append)));
+        assert!(target("X-Append: false").is_none());
+    }
+
+    #[test]
+    fn range_lengths_do_not_overflow() {
+        assert_eq!(range_length(0, 4), Some(5));
+        assert_eq!(range_length(7, 7), Some(1));
+        assert_eq!(range_length(5, 4), None);
+        assert_eq!(range_length(0, u64::MAX), None);
+        assert_eq!(range_length(1, u64::MAX), None);
+        assert_eq!(range_length(1, u64::MAX - 1), Some(u64::MAX - 1));
+    }
+}
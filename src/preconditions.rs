@@ -0,0 +1,144 @@
+use std::fs::Metadata;
+use std::time::UNIX_EPOCH;
+
+use crate::parser::HttpRequest;
+
+/// Builds the entity tag of a file from its size and modification time.
+pub fn etag(metadata: &Metadata) -> String {
+    let modified = metadata
+        .modified()
+        .ok()
+        .and_then(|m| m.duration_since(UNIX_EPOCH).ok())
+        .map(|d| d.as_nanos())
+        .unwrap_or(0);
+
+    format!("\"{:x}-{:x}\"", metadata.len(), modified)
+}
+
+/// Evaluates `If-Match` and `If-None-Match` against the current state of the target,
+/// `None` meaning it doesn't exist. Returns false if the request has to fail with
+/// `412 Precondition Failed`.
+pub fn check(request: &HttpRequest, current: Option<&Metadata>) -> bool {
+    let current_etag = current.map(etag);
+
+    if let Some(if_match) = request.header("If-Match") {
+        let matched = match &current_etag {
+            None => false,
+            Some(_) if if_match.trim() == "*" => true,
+            Some(tag) => list(if_match).any(|t| t == tag),
+        };
+        if !matched {
+            return false;
+        }
+    }
+
+    if let Some(if_none_match) = request.header("If-None-Match") {
+        let matched = match &current_etag {
+            None => false,
+            Some(_) if if_none_match.trim() == "*" => true,
+            Some(tag) => list(if_none_match).any(|t| t.trim_start_matches("W/") == tag),
+        };
+        if matched {
+            return false;
+        }
+    }
+
+    true
+}
+
+/// Splits an entity tag list. Weak tags are kept as is, so they never match strongly.
+fn list(header: &str) -> impl Iterator<Item = &str> {
+    header.split(',').map(str::trim).filter(|t| !t.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{check, etag};
+    use crate::parser::HttpRequest;
+    use std::fs::{self, Metadata};
+    use std::path::PathBuf;
+
+    /// A file in the temporary directory, removed on drop.
+    struct File(PathBuf);
+
+    impl File {
+        fn new(name: &str) -> Self {
+            let path = std::env::temp_dir().join(format!("http-server-rs-{}-preconditions-{name}", std::process::id()));
+            fs::write(&path, "content").unwrap();
+            File(path)
+        }
+
+        fn metadata(&self) -> Metadata {
+            fs::metadata(&self.0).unwrap()
+        }
+    }
+
+    impl Drop for File {
+        fn drop(&mut self) {
+            let _ = fs::remove_file(&self.0);
+        }
+    }
+
+    fn passes(headers: &str, current: Option<&Metadata>) -> bool {
+        check(&HttpRequest::parse(&format!("PUT / HTTP/1.1\r\n{headers}\r\n\r\n")).unwrap(), current)
+    }
+
+    #[test]
+    fn builds_quoted_tags_from_size_and_time() {
+        let file = File::new("etag");
+        let tag = etag(&file.metadata());
+        assert!(tag.starts_with("\"7-") && tag.ends_with('"'));
+        assert_eq!(tag, etag(&file.metadata()));
+    }
+
+    #[test]
+    fn passes_without_preconditions() {
+        let file = File::new("none");
+        assert!(passes("", Some(&file.metadata())));
+        assert!(passes("", None));
+    }
+
+    #[test]
+    fn if_match() {
+        let file = File::new("if-match");
+        let metadata = file.metadata();
+        let tag = etag(&metadata);
+
+        assert!(passes(&format!("If-Match: {tag}"), Some(&metadata)));
+        assert!(passes(&format!("If-Match: \"other\", {tag}"), Some(&metadata)));
+        assert!(!passes("If-Match: \"other\"", Some(&metadata)));
+        // Weak tags never match strongly.
+        assert!(!passes(&format!("If-Match: W/{tag}"), Some(&metadata)));
+
+        assert!(passes("If-Match: *", Some(&metadata)));
+        assert!(!passes("If-Match: *", None));
+        assert!(!passes(&format!("If-Match: {tag}"), None));
+    }
+
+    #[test]
+    fn if_none_match() {
+        let file = File::new("if-none-match");
+        let metadata = file.metadata();
+        let tag = etag(&metadata);
+
+        assert!(!passes(&format!("If-None-Match: {tag}"), Some(&metadata)));
+        assert!(!passes(&format!("If-None-Match: \"a\",W/{tag}"), Some(&metadata)));
+        assert!(passes("If-None-Match: \"a\", W/\"b\"", Some(&metadata)));
+
+        // `*` only fails if the target exists, which makes PUT create-only.
+        assert!(!passes("If-None-Match: *", Some(&metadata)));
+        assert!(passes("If-None-Match: *", None));
+        assert!(passes(&format!("If-None-Match: {tag}"), None));
+    }
+
+    #[test]
+    fn checks_both_headers() {
+        let file = File::new("both");
+        let metadata = file.metadata();
+        let tag = etag(&metadata);
+
+        assert!(passes(&format!("If-Match: {tag}\r\nIf-None-Match: \"other\""), Some(&metadata)));
+        assert!(!passes(&format!("If-Match: {tag}\r\nIf-None-Match: {tag}"), Some(&metadata)));
+        assert!(!passes("If-Match: \"other\"\r\nIf-None-Match: \"other\"", Some(&metadata)));
+    }
+}
@@ -0,0 +1,230 @@
+use std::collections::BTreeMap;
+use std::ffi::CString;
+use std::os::unix::ffi::OsStrExt;
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use fs_err as fs;
+
+use crate::config::Config;
+use crate::headers::{INSUFFICIENT_STORAGE, PAYLOAD_TOO_LARGE};
+use crate::requests::to_local_path;
+use crate::upload::matches_prefix;
+
+/// How long the measured usage of a quota directory is trusted before it's walked again.
+/// Writes through the server are added up in between, so only changes made behind its back
+/// go unnoticed for that long.
+const USAGE_MAX_AGE: Duration = Duration::from_secs(30);
+
+/// Usage of each quota directory and when it was last measured.
+static USAGE: Mutex<BTreeMap<String, (u64, Instant)>> = Mutex::new(BTreeMap::new());
+
+/// How many bytes a write may add under a path, and the status code to refuse it with
+/// if it adds more.
+pub struct Allowance {
+    pub max: u64,
+    pub code: u16,
+}
+
+impl Allowance {
+    /// Returns true if writing `length` bytes stays within the allowance.
+    pub fn permits(&self, length: u64) -> bool {
+        length <= self.max
+    }
+}
+
+/// Usage of a directory that has a quota.
+pub struct DirectoryUsage {
+    pub path: String,
+    pub used: u64,
+    pub limit: u64,
+}
+
+/// Works out how many bytes may be written to `path`, given that the write frees `freed`
+/// bytes (e.g. the file it replaces). The tightest of the per-request limit, the
+/// minimum free space and the quotas of the directories containing `path` wins.
+pub fn allowance(path: &str, freed: u64, config: &Config) -> Allowance {
+    let quotas = &config.server.quotas;
+    let mut allowance = Allowance { max: u64::MAX, code: INSUFFICIENT_STORAGE.code };
+
+    if quotas.max_upload_size > 0 {
+        allowance = Allowance { max: quotas.max_upload_size, code: PAYLOAD_TOO_LARGE.code };
+    }
+
+    if quotas.min_free_space > 0 {
+        if let Some(available) = available_space(config) {
+            let remaining = available.saturating_sub(quotas.min_free_space).saturating_add(freed);
+            if remaining < allowance.max {
+                allowance = Allowance { max: remaining, code: INSUFFICIENT_STORAGE.code };
+            }
+        }
+    }
+
+    for (dir, limit) in &quotas.directories {
+        if !matches_prefix(path, dir) {
+            continue;
+        }
+        let used = used(dir, config);
+        let remaining = limit.saturating_add(freed).saturating_sub(used);
+        if remaining < allowance.max {
+            allowance = Allowance { max: remaining, code: INSUFFICIENT_STORAGE.code };
+        }
+    }
+
+    allowance
+}
+
+/// Returns true if moving `source` to `destination` changes the usage of a quota directory.
+pub fn crosses_quota(source: &str, destination: &str, config: &Config) -> bool {
+    config
+        .server
+        .quotas
+        .directories
+        .keys()
+        .any(|dir| matches_prefix(source, dir) != matches_prefix(destination, dir))
+}
+
+/// Returns the usage of every directory that has a quota, sorted by path.
+pub fn usage(config: &Config) -> Vec<DirectoryUsage> {
+    let mut usage: Vec<_> = config
+        .server
+        .quotas
+        .directories
+        .iter()
+        .map(|(dir, limit)| DirectoryUsage {
+            path: dir.clone(),
+            used: used(dir, config),
+            limit: *limit,
+        })
+        .collect();
+    usage.sort_by(|a, b| a.path.cmp(&b.path));
+    usage
+}
+
+/// Accounts for `written` bytes written under `path`, replacing `freed` bytes, in the usage
+/// of the quota directories containing it.
+pub fn record_write(path: &str, written: u64, freed: u64) {
+    adjust(&mut USAGE.lock().unwrap_or_else(|e| e.into_inner()), path, written, freed);
+}
+
+/// Accounts for `freed` bytes removed from under `path`, e.g. by a delete or by moving them
+/// somewhere else.
+pub fn record_removal(path: &str, freed: u64) {
+    record_write(path, 0, freed);
+}
+
+/// Returns true if `path` lies in a directory that has a quota, so changes to it need to be
+/// recorded.
+pub fn is_limited(path: &str, config: &Config) -> bool {
+    config.server.quotas.directories.keys().any(|dir| matches_prefix(path, dir))
+}
+
+/// Adds `written` and takes away `freed` bytes from the cached usage of every quota
+/// directory containing `path`.
+fn adjust(usage: &mut BTreeMap<String, (u64, Instant)>, path: &str, written: u64, freed: u64) {
+    for (dir, (used, _)) in usage.iter_mut() {
+        if matches_prefix(path, dir) {
+            *used = used.saturating_add(written).saturating_sub(freed);
+        }
+    }
+}
+
+/// Returns the usage of the quota directory `dir`, measuring it again if it's too old.
+fn used(dir: &str, config: &Config) -> u64 {
+    let mut usage = USAGE.lock().unwrap_or_else(|e| e.into_inner());
+    match usage.get(dir) {
+        Some((used, measured)) if measured.elapsed() < USAGE_MAX_AGE => *used,
+        _ => {
+            let used = directory_size(Path::new(&to_local_path(&config.server.root, dir)));
+            usage.insert(dir.to_string(), (used, Instant::now()));
+            used
+        }
+    }
+}
+
+/// Returns the space available to unprivileged users on the filesystem holding the root.
+pub fn available_space(config: &Config) -> Option<u64> {
+    let root = CString::new(Path::new(&config.server.root).as_os_str().as_bytes()).ok()?;
+    // SAFETY: `root` is a valid C string and `stat` is only read after statvfs filled it.
+    let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
+    if unsafe { libc::statvfs(root.as_ptr(), &mut stat) } != 0 {
+        return None;
+    }
+
+    #[allow(clippy::unnecessary_cast)]
+    Some(stat.f_bavail as u64 * stat.f_frsize as u64)
+}
+
+/// Adds up the size of every file under `path`, without following symlinks. A file counts
+/// as its own size.
+pub fn directory_size(path: &Path) -> u64 {
+    let Ok(metadata) = fs::symlink_metadata(path) else {
+        return 0;
+    };
+    if !metadata.is_dir() {
+        return metadata.len();
+    }
+
+    fs::read_dir(path)
+        .map(|entries| entries.flatten().map(|e| directory_size(&e.path())).sum())
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::adjust;
+    use std::collections::BTreeMap;
+    use std::time::Instant;
+
+    fn usage(entries: &[(&str, u64)]) -> BTreeMap<String, (u64, Instant)> {
+        entries.iter().map(|(dir, used)| (dir.to_string(), (*used, Instant::now()))).collect()
+    }
+
+    fn used(usage: &BTreeMap<String, (u64, Instant)>, dir: &str) -> u64 {
+        usage[dir].0
+    }
+
+    #[test]
+    fn writes_count_in_every_containing_directory() {
+        let mut usage = usage(&[("/a", 100), ("/a/b", 10), ("/c", 5)]);
+        adjust(&mut usage, "/a/b/file", 20, 0);
+        assert_eq!(used(&usage, "/a"), 120);
+        assert_eq!(used(&usage, "/a/b"), 30);
+        assert_eq!(used(&usage, "/c"), 5);
+    }
+
+    #[test]
+    fn prefixes_match_whole_segments() {
+        let mut usage = usage(&[("/a", 100)]);
+        adjust(&mut usage, "/ab/file", 20, 0);
+        assert_eq!(used(&usage, "/a"), 100);
+    }
+
+    #[test]
+    fn replaced_and_removed_bytes_are_credited() {
+        let mut usage = usage(&[("/a", 100)]);
+        adjust(&mut usage, "/a/file", 30, 50);
+        assert_eq!(used(&usage, "/a"), 80);
+        adjust(&mut usage, "/a/file", 0, 80);
+        assert_eq!(used(&usage, "/a"), 0);
+    }
+
+    #[test]
+    fn moves_between_directories_shift_usage() {
+        let mut usage = usage(&[("/", 100), ("/a", 60), ("/b", 0)]);
+        adjust(&mut usage, "/b/file", 40, 0);
+        adjust(&mut usage, "/a/file", 0, 40);
+        assert_eq!(used(&usage, "/"), 100);
+        assert_eq!(used(&usage, "/a"), 20);
+        assert_eq!(used(&usage, "/b"), 40);
+    }
+
+    #[test]
+    fn usage_saturates() {
+        let mut usage = usage(&[("/a", 10), ("/b", u64::MAX - 1)]);
+        adjust(&mut usage, "/a/file", 0, 50);
+        adjust(&mut usage, "/b/file", 50, 0);
+        assert_eq!(used(&usage, "/a"), 0);
+        assert_eq!(used(&usage, "/b"), u64::MAX);
+    }
+}
@@ -1,38 +1,108 @@
-use log::info;
 use std::io::Read;
+use std::time::{Duration, Instant};
 use std::{io::Write, net::TcpStream};
 use fs_err as fs;
 
 use crate::config::Config;
 use crate::errors::HttpErrors;
-use crate::{headers, status_code_string};
+use crate::headers;
 use crate::headers::{
-    HttpContent, HttpContentLengthHeader, HttpContentTypeHeader, HttpHeader, HttpResponse, HttpStatusHeader, BAD_REQUEST, NOT_FOUND, NOT_IMPLEMENTED, UNAUTHORIZED
+    HttpContent, HttpContentLengthHeader, HttpContentTypeHeader, HttpContentTypeOptionsHeader, HttpETagHeader, HttpHeader, HttpRequestIdHeader, HttpResponse, HttpServerTimingHeader, HttpStatusHeader, NOT_FOUND, NOT_IMPLEMENTED, OK, UNAUTHORIZED
 };
 
-use crate::media::ext_to_type;
+use crate::access;
+use crate::audit;
+use crate::autoindex::autoindex;
+use crate::body::HttpBody;
+use crate::delete;
+use crate::error_pages::error_response;
+use crate::forms;
+use crate::locks;
+use crate::logging::{self, AccessEntry};
+use crate::media;
+use crate::metrics;
+use crate::parser::HttpRequest;
+use crate::patch;
+use crate::preconditions::etag;
+use crate::stats;
+use crate::status::{self, ConnectionState};
+use crate::trace;
+use crate::tus;
+use crate::upload;
+use crate::webdav;
+
+/// File served in place of a directory, if it exists.
+const INDEX_FILE: &str = "index.html";
+
+pub fn handle_request(request: &str, body_prefix: Vec<u8>, stream: &mut TcpStream, config: &Config) -> Result<(), HttpErrors> {
+    let result = respond(request, body_prefix, stream, config);
+    if let Err(e) = &result {
+        // No response may have been sent, but the request still counts.
+        metrics::record_failure(request.split(' ').next().unwrap_or_default(), e);
+    }
+    result
+}
 
-pub fn handle_request(request: &str, stream: &mut TcpStream, config: &Config) -> Result<(), HttpErrors> {
-    let request_sections = request.split_whitespace().collect::<Vec<&str>>();
-    let request_type = request_sections[0];
-    let request_url = to_local_path(request_sections[1]);
-    let request_ext = request_url.split('.').last();
-    let ext = if request_ext.is_some() {
-        String::from(request_ext.unwrap())
+fn respond(request: &str, body_prefix: Vec<u8>, stream: &mut TcpStream, config: &Config) -> Result<(), HttpErrors> {
+    let started = Instant::now();
+    let request = HttpRequest::parse(request)?;
+    let request_id = logging::request_id(&request);
+    logging::set_request_id(Some(&request_id));
+    let mut body = HttpBody::new(&request, body_prefix, stream)?;
+    let request_type = request.method.as_str();
+    let parsed = started.elapsed();
+
+    let peer = stream.peer_addr().map_err(|e| HttpErrors::StreamPeerAddressUnknown(e.to_string()))?;
+    logging::request_received(&request, peer);
+
+    let audited = audit::is_audited(request_type, config);
+    if audited {
+        body.hash_content();
+    }
+
+    status::set_state(Some(ConnectionState::Handling));
+    let response = if let Some(response) = status::handle(&request, peer.ip(), config) {
+        response
+    } else if let Some(refused) = access::enforce(&request, config) {
+        Ok(refused)
+    } else if tus::is_tus_request(&request, config) {
+        tus::handle(&request, &mut body, config)
+    } else if let Some(locked) = config.server.webdav.enable.then(|| locks::enforce(&request, config)).flatten() {
+        Ok(locked)
     } else {
-        String::from("txt")
+        match request_type {
+            "GET" | "HEAD" => get(&request, config),
+            "POST" => post(&request, &mut body, config),
+            "PATCH" => patch::patch(&request, &mut body, config),
+            "PUT" => upload::put(&request, &mut body, config),
+            "DELETE" => delete::delete(&request, config),
+            _ if config.server.webdav.enable && webdav::is_webdav_method(request_type) => {
+                webdav::handle(&request, &mut body, config)
+            }
+            _ => return Err(HttpErrors::UnsupportedRequestType(request_type.to_string())),
+        }
     };
 
-    info!("Received {request_type} request from {:#?}", stream.peer_addr().map_err(|e| HttpErrors::StreamPeerAddressUnknown(e.to_string()))?);
-    let response = match request_type {
-        "GET" => get(request_url, ext),
-        "POST" => post(request_url, ext, config),
-        "PATCH" => patch(request_url, ext, config),
-        "PUT" => put(request_url, ext, config),
-        "DELETE" => delete(request_url, ext, config),
-        _ => return Err(HttpErrors::UnsupportedRequestType(request_type.to_string())),
-    }?;
+    if audited {
+        audit::record(&request, peer.ip(), &mut body, response.as_ref().ok(), config);
+    }
+    let mut response = response?.add_header(HttpRequestIdHeader::new_from(request_id.clone()));
+    if config.server.server_timing {
+        let handled = started.elapsed() - parsed;
+        response = response.add_header(HttpServerTimingHeader::new_from(format!(
+            "parse;dur={:.3}, fs;dur={:.3}",
+            millis(parsed),
+            millis(handled)
+        )));
+    }
+
+    // HEAD gets the headers GET would, Content-Length included, but no body.
+    if request_type == "HEAD" {
+        response.content = HttpContent::default();
+    }
 
+    status::set_state(Some(ConnectionState::Writing));
+    let sending = Instant::now();
     stream
         .write(response.headers.as_bytes())
         .map_err(|e| HttpErrors::StreamWriteFailure(e.to_string()))?;
@@ -42,85 +112,102 @@ pub fn handle_request(request: &str, stream: &mut TcpStream, config: &Config) ->
     stream
         .flush()
         .map_err(|e| HttpErrors::StreamFlushFailure(e.to_string()))?;
-    info!("Sent response to {}", stream.peer_addr().map_err(|e| HttpErrors::StreamPeerAddressUnknown(e.to_string()))?);
 
-    Ok(())
-}
-
-fn get(request_url: String, ext: String) -> Result<HttpResponse, HttpErrors> {
-    let mut status_code = 200;
-    let mut ext = ext;
-    let mut content_buffer = vec![];
-    let file_handle: Option<fs::File> = match get_file(&request_url) {
-        Ok(f) => Some(f),
-        Err(HttpErrors::ResourceNotFound(_)) => {
-            status_code = 404;
-            None
-        },
-        Err(HttpErrors::UnauthorizedPath(_)) => {
-            status_code = 504;
-            None
-        },
-        Err(e) => return Err(e),
+    let entry = AccessEntry {
+        request: &request,
+        request_id: &request_id,
+        peer,
+        status: response.status_code(),
+        bytes: response.content.content.len(),
+        duration: started.elapsed(),
+        parse: parsed,
+        send: sending.elapsed(),
     };
+    metrics::record(&entry);
+    status::record(&entry);
+    trace::record(&entry);
+    logging::request_completed(&entry);
+    logging::access(&entry);
+    logging::set_request_id(None);
 
-    let mut content: HttpContent;
-    if status_code == 200 {
-        file_handle.unwrap().read_to_end(&mut content_buffer).map_err(|e| HttpErrors::FileReadFailure(e.to_string()))?;
-
-        content = HttpContent::from(content_buffer);
-    } else {
-        match status_code {
-            404 => {
-                content = HttpContent::from(status_code_string!(NOT_FOUND.code, NOT_FOUND.phrase));
-            },
+    Ok(())
+}
 
-            401 => {
-                content = HttpContent::from(status_code_string!(UNAUTHORIZED.code, UNAUTHORIZED.phrase));
-            },
+fn get(request: &HttpRequest, config: &Config) -> Result<HttpResponse, HttpErrors> {
+    if stats::is_stats_request(&request.path, config) {
+        return stats::stats(config);
+    }
+    if metrics::is_metrics_request(&request.path, config) {
+        return metrics::metrics();
+    }
+    if is_internal_path(&request.path, config) {
+        return Ok(error_response(NOT_FOUND.code, request, config));
+    }
 
-            _ => panic!(),
+    let mut request_url = to_local_path(&config.server.root, &request.path);
+    let dir = std::path::PathBuf::from(&request_url);
+    if dir.is_dir() && !request_url.contains("..") {
+        let index = dir.join(INDEX_FILE);
+        if index.is_file() {
+            request_url = index.to_string_lossy().to_string();
+        } else if config.server.autoindex.enable {
+            return autoindex(request, &dir, config);
         }
     }
 
-    if ext_to_type(&ext).is_none() {
-        ext = String::from("txt");
-        status_code = BAD_REQUEST.code;
-        content = HttpContent::from(status_code_string!(BAD_REQUEST.code, BAD_REQUEST.phrase));
+    let mut content_buffer = vec![];
+    let mut file_handle = match get_file(&request_url) {
+        Ok(f) => f,
+        Err(HttpErrors::ResourceNotFound(_)) => return Ok(error_response(NOT_FOUND.code, request, config)),
+        Err(HttpErrors::UnauthorizedPath(_)) => return Ok(error_response(UNAUTHORIZED.code, request, config)),
+        Err(e) => return Err(e),
+    };
+
+    let metadata = file_handle.metadata().map_err(|e| HttpErrors::FileReadFailure(e.to_string()))?;
+    file_handle.read_to_end(&mut content_buffer).map_err(|e| HttpErrors::FileReadFailure(e.to_string()))?;
+    let content = HttpContent::from(content_buffer);
+    let (content_type, authoritative) = media::resolve(extension(&request_url), &content.content, &config.mime);
+    let mut headers = headers!(
+        HttpStatusHeader::new_from(OK.code);
+        HttpContentTypeHeader::new_from(content_type);
+        HttpContentLengthHeader::new_from(content.content.len());
+        HttpETagHeader::new_from(etag(&metadata));
+    );
+    if authoritative {
+        headers = headers.add_header(HttpContentTypeOptionsHeader::new_from(String::from("nosniff")));
     }
 
     Ok(HttpResponse {
-        headers: headers!(
-            HttpStatusHeader::new_from(status_code);
-            HttpContentTypeHeader::new_from(ext_to_type(&ext).unwrap().content_type);
-            HttpContentLengthHeader::new_from(content.content.len());
-        )
-        .build(),
+        headers: headers.build(),
         content,
     })
 }
-// TODO: Implement POST request
-fn post(_request_url: String, _ext: String, config: &Config) -> Result<HttpResponse, HttpErrors> {
-    not_impl(config)
+fn post(request: &HttpRequest, body: &mut HttpBody, config: &Config) -> Result<HttpResponse, HttpErrors> {
+    if !config.server.forms.enable {
+        return not_impl(request, config);
+    }
+
+    forms::post(request, body, config)
 }
 
-// TODO: Implement PATCH request
-fn patch(_request_url: String, _ext: String, config: &Config) -> Result<HttpResponse, HttpErrors> {
-    not_impl(config)
+fn millis(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
 }
 
-// TODO: Implement PUT request
-fn put(_request_url: String, _ext: String, config: &Config) -> Result<HttpResponse, HttpErrors> {
-    not_impl(config)
+/// Returns the extension of the last path segment, if it has one.
+pub fn extension(path: &str) -> Option<&str> {
+    std::path::Path::new(path).extension().and_then(|e| e.to_str())
 }
 
-// TODO: Implement DELETE request
-fn delete(_request_url: String, _ext: String, config: &Config) -> Result<HttpResponse, HttpErrors> {
-    not_impl(config)
+/// Returns true if `path` lies in a directory the server keeps for itself, the tus staging
+/// directory or the trash, which aren't served or listed.
+pub fn is_internal_path(path: &str, config: &Config) -> bool {
+    tus::is_staging_path(path, config) || delete::is_trash_path(path, config)
 }
 
-fn to_local_path(path: &str) -> String {
-    format!(".{path}")
+/// Maps a request path onto the filesystem, under the configured root.
+pub fn to_local_path(root: &str, path: &str) -> String {
+    format!("{}{path}", root.trim_end_matches('/'))
 }
 
 fn get_file(path: &str) -> Result<fs::File, HttpErrors> {
@@ -128,7 +215,7 @@ fn get_file(path: &str) -> Result<fs::File, HttpErrors> {
         return Err(HttpErrors::UnauthorizedPath(path.to_string()));
     }
 
-    if !std::path::PathBuf::from(path).exists() {
+    if !std::path::PathBuf::from(path).is_file() {
         return Err(HttpErrors::ResourceNotFound(path.to_string()));
     }
 
@@ -137,19 +224,22 @@ fn get_file(path: &str) -> Result<fs::File, HttpErrors> {
     Ok(file)
 }
 
-fn not_impl(config: &Config) -> Result<HttpResponse, HttpErrors> {
+fn not_impl(request: &HttpRequest, config: &Config) -> Result<HttpResponse, HttpErrors> {
     if config.extra.panic_if_not_impl {
         panic!();
     }
 
-    let content = HttpContent { content: status_code_string!(NOT_IMPLEMENTED.code, NOT_IMPLEMENTED.phrase).as_bytes().to_vec() };
-    Ok(HttpResponse {
+    Ok(error_response(NOT_IMPLEMENTED.code, request, config))
+}
+
+/// Builds a response that only consists of a status line.
+pub fn empty_response(code: u16) -> HttpResponse {
+    HttpResponse {
         headers: headers!(
-            HttpStatusHeader::new_from(501);
-            HttpContentTypeHeader::new_from(ext_to_type("txt").unwrap().content_type);
-            HttpContentLengthHeader::new_from(content.content.len());
+            HttpStatusHeader::new_from(code);
+            HttpContentLengthHeader::new_from(0);
         )
         .build(),
-        content,
-    })
+        content: HttpContent::default(),
+    }
 }
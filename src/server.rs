@@ -1,55 +1,146 @@
+use crate::body::read_head;
 use crate::errors::HttpErrors;
 use crate::config::Config;
+use crate::logging;
+use crate::metrics;
+use crate::status::{self, ConnectionState};
+use crate::trace;
 use crate::requests::handle_request;
-use log::{error, info};
-use std::{
-    io::Read,
-    net::TcpListener,
-    str::from_utf8,
-};
+use crate::signals;
+use log::{error, info, warn};
+use std::net::{Shutdown, TcpListener, TcpStream};
+use std::os::fd::AsRawFd;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Mutex, Once};
+use std::thread;
+use std::time::Duration;
+
+/// Maximum size of the request line and headers, in bytes.
+const MAX_HEAD_LENGTH: usize = 16 * 1024;
+
+/// Longest a client may go without sending anything while the head or body of its request
+/// is read. Connections are handled one at a time, so a silent client would block the server.
+const READ_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// How often the server checks whether it was asked to shut down, in milliseconds.
+const SHUTDOWN_POLL_INTERVAL: i32 = 200;
+
+/// Set on SIGTERM or SIGINT.
+static SHUTDOWN: AtomicBool = AtomicBool::new(false);
+
+/// Connection whose request head hasn't arrived yet. A shutdown closes its reading side,
+/// so an idle client can't hold the server up until the drain timeout.
+static AWAITING_HEAD: Mutex<Option<TcpStream>> = Mutex::new(None);
+
+static FINISHED: Once = Once::new();
 
 pub fn serve(config: Config) -> Result<(), HttpErrors> {
     let listener: TcpListener = TcpListener::bind(format!("{}:{}", config.server.address, config.server.port)).map_err(|e| HttpErrors::TcpListenerBindFailure(config.server.port.clone(), e.to_string()))?;
     info!("HTTP server online, open for connections on port: {}", config.server.port);
+    metrics::spawn_listener(&config)?;
+    status::mark_started();
+    trace::spawn_exporter(&config)?;
+    handle_shutdown_signals();
+    spawn_drain_watchdog(Duration::from_secs(config.server.drain_timeout));
 
-    for stream in listener.incoming() {
-        let mut stream = match stream.map_err(|e| HttpErrors::StreamAcceptFailure(e.to_string())) {
-            Ok(s) => s,
+    while wait_for_connection(&listener) {
+        let mut stream = match listener.accept().map_err(|e| HttpErrors::StreamAcceptFailure(e.to_string())) {
+            Ok((s, _)) => s,
             Err(e) => {
+                metrics::accept_failed();
                 error!("{e}");
                 continue;
             }
         };
+        status::set_state(Some(ConnectionState::Reading));
+        if let Err(e) = stream.set_read_timeout(Some(READ_TIMEOUT)) {
+            error!("Failed to set the read timeout: {e}");
+            status::set_state(None);
+            continue;
+        }
 
-        let mut buf = [0; 1024];
-        let bytes_read = match stream.read(&mut buf).map_err(|e| HttpErrors::StreamReadFailure(e.to_string())) {
-            Ok(r) => r,
-            Err(e) => {
-                error!("{e}");
-                continue;
+        await_head(Some(&stream));
+        let head = read_head(&mut stream, MAX_HEAD_LENGTH);
+        await_head(None);
+        let result = match head {
+            Err(_) if SHUTDOWN.load(Ordering::Relaxed) => {
+                info!("Closed a connection that sent no request before the shutdown");
+                Ok(())
             }
+            head => head.and_then(|(request, body_prefix)| handle_request(&request, body_prefix, &mut stream, &config)),
         };
+        status::set_state(None);
+        if let Err(e) = result {
+            error!("{e}");
+            logging::set_request_id(None);
+        }
+    }
 
-        let request = match from_utf8(&buf[..bytes_read]).map_err(|_| HttpErrors::Utf8ConversionFailure) {
-            Ok(r) => r,
-            Err(e) => {
-                error!("{e}");
-                continue;
-            }
-        };
+    info!("Server shut down");
+    finish();
+    Ok(())
+}
 
-        if bytes_read == 0 {
-            return Err(HttpErrors::StreamReadFailure(String::from("Received 0 bytes, unknown")));
+extern "C" fn request_shutdown(_: libc::c_int) {
+    SHUTDOWN.store(true, Ordering::Relaxed);
+}
+
+/// Makes SIGTERM and SIGINT stop the server once the request being handled is answered.
+fn handle_shutdown_signals() {
+    signals::handle(&[libc::SIGTERM, libc::SIGINT], request_shutdown);
+}
+
+/// Waits until a connection can be accepted. Returns false once the server should shut down.
+fn wait_for_connection(listener: &TcpListener) -> bool {
+    let mut fd = libc::pollfd {
+        fd: listener.as_raw_fd(),
+        events: libc::POLLIN,
+        revents: 0,
+    };
+    while !SHUTDOWN.load(Ordering::Relaxed) {
+        // SAFETY: `fd` is a single valid pollfd. A signal makes poll return early.
+        if unsafe { libc::poll(&mut fd, 1, SHUTDOWN_POLL_INTERVAL) } > 0 {
+            return true;
         }
+    }
+    false
+}
 
-        match handle_request(request, &mut stream, &config) {
-            Ok(_) => (),
-            Err(e) => {
-                error!("{e}");
-                continue;
-            }
-        };
+/// Once a shutdown is requested, fails the readiness check and gives the request in flight
+/// `drain_timeout` to finish before exiting anyway.
+fn spawn_drain_watchdog(drain_timeout: Duration) {
+    thread::spawn(move || {
+        while !SHUTDOWN.load(Ordering::Relaxed) {
+            thread::sleep(Duration::from_millis(SHUTDOWN_POLL_INTERVAL as u64));
+        }
+        status::start_draining();
+        info!("Shutting down, waiting up to {}s for the request in flight", drain_timeout.as_secs());
+        if let Some(stream) = AWAITING_HEAD.lock().unwrap_or_else(|e| e.into_inner()).as_ref() {
+            let _ = stream.shutdown(Shutdown::Read);
+        }
+
+        thread::sleep(drain_timeout);
+        warn!("Request still in flight after the drain timeout, exiting");
+        finish();
+        std::process::exit(0);
+    });
+}
+
+/// Tracks the connection waiting for its request head, or stops tracking it with `None`.
+/// If the server is already shutting down, the connection isn't waited on at all.
+fn await_head(stream: Option<&TcpStream>) {
+    let mut awaiting = AWAITING_HEAD.lock().unwrap_or_else(|e| e.into_inner());
+    *awaiting = stream.and_then(|s| s.try_clone().ok());
+    if let Some(stream) = awaiting.as_ref().filter(|_| SHUTDOWN.load(Ordering::Relaxed)) {
+        let _ = stream.shutdown(Shutdown::Read);
     }
+}
 
-    Ok(())
+/// Sends the remaining spans and writes out buffered log lines. Only the first call does
+/// anything, since the main thread and the drain watchdog may both get here.
+fn finish() {
+    FINISHED.call_once(|| {
+        trace::shutdown();
+        log::logger().flush();
+    });
 }
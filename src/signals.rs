@@ -0,0 +1,37 @@
+/// Installs `handler` for each of `signals`. The handler has to be async-signal-safe, which
+/// in practice means it only stores to atomics. SA_RESTART keeps the signals from
+/// interrupting the reads and writes of the request in flight.
+pub fn handle(signals: &[libc::c_int], handler: extern "C" fn(libc::c_int)) {
+    // SAFETY: `action` is fully initialized before it's installed, and it's only read by
+    // `sigaction`.
+    unsafe {
+        let mut action: libc::sigaction = std::mem::zeroed();
+        action.sa_sigaction = handler as libc::sighandler_t;
+        action.sa_flags = libc::SA_RESTART;
+        libc::sigemptyset(&mut action.sa_mask);
+        for signal in signals {
+            libc::sigaction(*signal, &action, std::ptr::null_mut());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::handle;
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    static RECEIVED: AtomicBool = AtomicBool::new(false);
+
+    extern "C" fn record(_: libc::c_int) {
+        RECEIVED.store(true, Ordering::Relaxed);
+    }
+
+    #[test]
+    fn runs_the_handler() {
+        // SIGUSR2 isn't used by the server.
+        handle(&[libc::SIGUSR2], record);
+        // SAFETY: raising a signal with a handler installed.
+        unsafe { libc::raise(libc::SIGUSR2) };
+        assert!(RECEIVED.load(Ordering::Relaxed));
+    }
+}
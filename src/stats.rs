@@ -0,0 +1,41 @@
+use crate::config::Config;
+use crate::errors::HttpErrors;
+use crate::escape::json_escape;
+use crate::headers;
+use crate::headers::{
+    HttpCacheControlHeader, HttpContent, HttpContentLengthHeader, HttpContentTypeHeader, HttpHeader, HttpResponse,
+    HttpStatusHeader, OK,
+};
+use crate::media;
+use crate::quota;
+
+/// Returns true if the request is addressed to the statistics path.
+pub fn is_stats_request(path: &str, config: &Config) -> bool {
+    config.server.stats.enable && path == config.server.stats.path
+}
+
+/// Reports disk space and the usage of every directory quota as JSON.
+pub fn stats(config: &Config) -> Result<HttpResponse, HttpErrors> {
+    let available = quota::available_space(config).map_or(String::from("null"), |a| a.to_string());
+    let quotas = quota::usage(config)
+        .iter()
+        .map(|u| format!(r#"{{"path":"{}","used":{},"limit":{}}}"#, json_escape(&u.path), u.used, u.limit))
+        .collect::<Vec<_>>()
+        .join(",");
+
+    let content = HttpContent::from(format!(
+        r#"{{"disk":{{"available":{available},"min_free_space":{}}},"max_upload_size":{},"quotas":[{quotas}]}}"#,
+        config.server.quotas.min_free_space, config.server.quotas.max_upload_size,
+    ));
+
+    Ok(HttpResponse {
+        headers: headers!(
+            HttpStatusHeader::new_from(OK.code);
+            HttpContentTypeHeader::new_from(media::lookup("json", &config.mime).unwrap());
+            HttpContentLengthHeader::new_from(content.content.len());
+            HttpCacheControlHeader::new_from(String::from("no-store"));
+        )
+        .build(),
+        content,
+    })
+}
@@ -0,0 +1,327 @@
+use std::cell::Cell;
+use std::collections::VecDeque;
+use std::net::IpAddr;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant};
+use fs_err as fs;
+
+use crate::config::Config;
+use crate::datetime::DateTime;
+use crate::error_pages::error_response;
+use crate::errors::HttpErrors;
+use crate::escape::html_escape;
+use crate::headers;
+use crate::headers::{
+    HttpCacheControlHeader, HttpContent, HttpContentLengthHeader, HttpContentTypeHeader, HttpHeader, HttpResponse,
+    HttpStatusHeader, FORBIDDEN, OK, SERVICE_UNAVAILABLE,
+};
+use crate::logging::AccessEntry;
+use crate::media;
+use crate::parser::HttpRequest;
+
+/// Number of requests listed on the status page.
+const RECENT_REQUESTS: usize = 20;
+
+static STARTED: OnceLock<Instant> = OnceLock::new();
+
+/// Set once the server stops taking new connections, which makes it unready.
+static DRAINING: AtomicBool = AtomicBool::new(false);
+
+/// Number of connections in each [`ConnectionState`].
+static CONNECTIONS: [AtomicU64; 3] = [AtomicU64::new(0), AtomicU64::new(0), AtomicU64::new(0)];
+
+/// Time spent handling requests since the server started, in microseconds.
+static BUSY_MICROS: AtomicU64 = AtomicU64::new(0);
+
+static RECENT: Mutex<VecDeque<RecentRequest>> = Mutex::new(VecDeque::new());
+
+thread_local! {
+    /// State of the connection handled on this thread.
+    static STATE: Cell<Option<ConnectionState>> = const { Cell::new(None) };
+}
+
+/// What a connection is waiting for.
+#[derive(Clone, Copy)]
+pub enum ConnectionState {
+    /// Reading the request.
+    Reading,
+    /// Building the response.
+    Handling,
+    /// Sending the response.
+    Writing,
+}
+
+impl ConnectionState {
+    const ALL: [ConnectionState; 3] = [ConnectionState::Reading, ConnectionState::Handling, ConnectionState::Writing];
+
+    fn name(self) -> &'static str {
+        match self {
+            ConnectionState::Reading => "reading",
+            ConnectionState::Handling => "handling",
+            ConnectionState::Writing => "writing",
+        }
+    }
+}
+
+struct RecentRequest {
+    time: String,
+    client: IpAddr,
+    method: String,
+    path: String,
+    status: u16,
+    duration: Duration,
+}
+
+/// Notes when the server started, for the uptime.
+pub fn mark_started() {
+    STARTED.get_or_init(Instant::now);
+}
+
+/// Makes `/readyz` fail from now on, so load balancers stop sending requests.
+pub fn start_draining() {
+    DRAINING.store(true, Ordering::Relaxed);
+}
+
+/// Moves the connection handled on this thread to `state`, or closes it with `None`.
+pub fn set_state(state: Option<ConnectionState>) {
+    let previous = STATE.with(|s| s.replace(state));
+    if let Some(previous) = previous {
+        CONNECTIONS[previous as usize].fetch_sub(1, Ordering::Relaxed);
+    }
+    if let Some(state) = state {
+        CONNECTIONS[state as usize].fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// Returns the number of connections open, whatever their state.
+pub fn active_connections() -> u64 {
+    CONNECTIONS.iter().map(|c| c.load(Ordering::Relaxed)).sum()
+}
+
+/// Adds a request whose response was sent to the recent requests.
+pub fn record(entry: &AccessEntry) {
+    BUSY_MICROS.fetch_add(entry.duration.as_micros() as u64, Ordering::Relaxed);
+
+    let mut recent = RECENT.lock().unwrap_or_else(|e| e.into_inner());
+    if recent.len() == RECENT_REQUESTS {
+        recent.pop_back();
+    }
+    recent.push_front(RecentRequest {
+        time: DateTime::now().to_rfc3339(),
+        client: entry.peer.ip(),
+        method: entry.request.method.clone(),
+        path: entry.request.path.clone(),
+        status: entry.status,
+        duration: entry.duration,
+    });
+}
+
+/// Answers `GET` and `HEAD` requests to the health and status paths, before anything else
+/// looks at the request. Clients outside `server.status.allowed_clients` get a 403.
+pub fn handle(request: &HttpRequest, client: IpAddr, config: &Config) -> Option<Result<HttpResponse, HttpErrors>> {
+    let status = &config.server.status;
+    if !status.enable || !matches!(request.method.as_str(), "GET" | "HEAD") {
+        return None;
+    }
+
+    let page = [&status.healthz_path, &status.readyz_path, &status.status_path]
+        .into_iter()
+        .position(|p| *p == request.path)?;
+    if !status.allowed_clients.is_empty() && !status.allowed_clients.iter().any(|n| in_network(client, n)) {
+        return Some(Ok(error_response(FORBIDDEN.code, request, config)));
+    }
+
+    Some(match page {
+        0 => text_response(OK.code, "ok\n", config),
+        1 => readyz(config),
+        _ => status_page(config),
+    })
+}
+
+fn readyz(config: &Config) -> Result<HttpResponse, HttpErrors> {
+    if DRAINING.load(Ordering::Relaxed) {
+        return text_response(SERVICE_UNAVAILABLE.code, "draining\n", config);
+    }
+    if fs::read_dir(Path::new(&config.server.root)).is_err() {
+        return text_response(SERVICE_UNAVAILABLE.code, "root not accessible\n", config);
+    }
+
+    text_response(OK.code, "ready\n", config)
+}
+
+fn status_page(config: &Config) -> Result<HttpResponse, HttpErrors> {
+    let uptime = STARTED.get().map_or(Duration::ZERO, |s| s.elapsed());
+    let busy = Duration::from_micros(BUSY_MICROS.load(Ordering::Relaxed));
+    let utilization = if uptime.is_zero() { 0.0 } else { busy.as_secs_f64() / uptime.as_secs_f64() * 100.0 };
+
+    let connections = ConnectionState::ALL
+        .iter()
+        .map(|s| format!("<tr><td>{}</td><td>{}</td></tr>", s.name(), CONNECTIONS[*s as usize].load(Ordering::Relaxed)))
+        .collect::<String>();
+    let requests = RECENT
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .iter()
+        .map(|r| {
+            format!(
+                "<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{:.3} ms</td></tr>\n",
+                r.time,
+                r.client,
+                html_escape(&r.method),
+                html_escape(&r.path),
+                r.status,
+                r.duration.as_secs_f64() * 1000.0
+            )
+        })
+        .collect::<String>();
+
+    let content = HttpContent::from(format!(
+        "<!DOCTYPE html>\n<html>\n<head><meta charset=\"utf-8\"><title>Server status</title></head>\n<body>\n<h1>Server status</h1>\n<p>Uptime: {}</p>\n<p>Worker utilization: {utilization:.1}% (1 worker, busy for {:.3} s)</p>\n<h2>Connections</h2>\n<table>\n<tr><th>State</th><th>Connections</th></tr>\n{connections}\n</table>\n<h2>Recent requests</h2>\n<table>\n<tr><th>Time</th><th>Client</th><th>Method</th><th>Path</th><th>Status</th><th>Duration</th></tr>\n{requests}</table>\n</body>\n</html>\n",
+        format_uptime(uptime),
+        busy.as_secs_f64(),
+    ));
+
+    Ok(HttpResponse {
+        headers: headers!(
+            HttpStatusHeader::new_from(OK.code);
+            HttpContentTypeHeader::new_from(media::lookup("html", &config.mime).unwrap());
+            HttpContentLengthHeader::new_from(content.content.len());
+            HttpCacheControlHeader::new_from(String::from("no-store"));
+        )
+        .build(),
+        content,
+    })
+}
+
+fn text_response(code: u16, text: &str, config: &Config) -> Result<HttpResponse, HttpErrors> {
+    let content = HttpContent::from(text.to_string());
+    Ok(HttpResponse {
+        headers: headers!(
+            HttpStatusHeader::new_from(code);
+            HttpContentTypeHeader::new_from(media::lookup("txt", &config.mime).unwrap());
+            HttpContentLengthHeader::new_from(content.content.len());
+            HttpCacheControlHeader::new_from(String::from("no-store"));
+        )
+        .build(),
+        content,
+    })
+}
+
+/// Formats a duration as e.g. `3 days 04:05:06`.
+fn format_uptime(uptime: Duration) -> String {
+    let seconds = uptime.as_secs();
+    format!(
+        "{} days {:02}:{:02}:{:02}",
+        seconds / 86400,
+        seconds / 3600 % 24,
+        seconds / 60 % 60,
+        seconds % 60
+    )
+}
+
+/// Parses an address, or a network in CIDR notation, into its address and prefix length.
+pub fn parse_network(network: &str) -> Option<(IpAddr, u32)> {
+    let (address, prefix) = match network.split_once('/') {
+        Some((address, prefix)) => (address.parse::<IpAddr>().ok()?, Some(prefix.parse::<u32>().ok()?)),
+        None => (network.parse::<IpAddr>().ok()?, None),
+    };
+    let bits = if address.is_ipv4() { 32 } else { 128 };
+    match prefix {
+        Some(prefix) if prefix > bits => None,
+        prefix => Some((address, prefix.unwrap_or(bits))),
+    }
+}
+
+/// Returns true if `client` is `network` or part of it. IPv4 clients connecting over IPv6,
+/// and networks written as IPv4-mapped IPv6 addresses, are matched as IPv4.
+fn in_network(client: IpAddr, network: &str) -> bool {
+    let Some((address, prefix)) = parse_network(network) else {
+        return false;
+    };
+    let (address, prefix) = match address {
+        IpAddr::V6(v6) if prefix >= 96 => v6.to_ipv4_mapped().map_or((address, prefix), |v4| (IpAddr::V4(v4), prefix - 96)),
+        _ => (address, prefix),
+    };
+    let client = match client {
+        IpAddr::V6(v6) => v6.to_ipv4_mapped().map_or(client, IpAddr::V4),
+        v4 => v4,
+    };
+
+    match (client, address) {
+        (IpAddr::V4(c), IpAddr::V4(a)) => {
+            let mask = u32::MAX.checked_shl(32 - prefix).unwrap_or(0);
+            u32::from(c) & mask == u32::from(a) & mask
+        }
+        (IpAddr::V6(c), IpAddr::V6(a)) => {
+            let mask = u128::MAX.checked_shl(128 - prefix).unwrap_or(0);
+            u128::from(c) & mask == u128::from(a) & mask
+        }
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{in_network, parse_network};
+    use std::net::IpAddr;
+
+    fn ip(address: &str) -> IpAddr {
+        address.parse().unwrap()
+    }
+
+    #[test]
+    fn parses_networks() {
+        assert_eq!(parse_network("10.0.0.0/8"), Some((ip("10.0.0.0"), 8)));
+        assert_eq!(parse_network("10.0.0.1"), Some((ip("10.0.0.1"), 32)));
+        assert_eq!(parse_network("::1"), Some((ip("::1"), 128)));
+        assert_eq!(parse_network("0.0.0.0/0"), Some((ip("0.0.0.0"), 0)));
+        assert_eq!(parse_network("10.0.0.0/33"), None);
+        assert_eq!(parse_network("::/129"), None);
+        assert_eq!(parse_network("10.0.0.0/"), None);
+        assert_eq!(parse_network("localhost"), None);
+    }
+
+    #[test]
+    fn zero_prefix_matches_everything_of_its_family() {
+        assert!(in_network(ip("203.0.113.9"), "0.0.0.0/0"));
+        assert!(in_network(ip("2001:db8::1"), "::/0"));
+        assert!(!in_network(ip("2001:db8::1"), "0.0.0.0/0"));
+        assert!(!in_network(ip("203.0.113.9"), "::/0"));
+    }
+
+    #[test]
+    fn full_prefix_matches_one_address() {
+        assert!(in_network(ip("192.0.2.1"), "192.0.2.1/32"));
+        assert!(in_network(ip("192.0.2.1"), "192.0.2.1"));
+        assert!(!in_network(ip("192.0.2.2"), "192.0.2.1/32"));
+        assert!(in_network(ip("2001:db8::1"), "2001:db8::1/128"));
+        assert!(!in_network(ip("2001:db8::2"), "2001:db8::1/128"));
+    }
+
+    #[test]
+    fn partial_prefixes() {
+        assert!(in_network(ip("192.168.1.200"), "192.168.1.0/24"));
+        assert!(!in_network(ip("192.168.2.1"), "192.168.1.0/24"));
+        assert!(in_network(ip("10.255.0.1"), "10.0.0.0/8"));
+        assert!(in_network(ip("2001:db8:0:1::5"), "2001:db8::/32"));
+        assert!(!in_network(ip("2001:db9::5"), "2001:db8::/32"));
+    }
+
+    #[test]
+    fn ipv4_mapped_clients_match_as_ipv4() {
+        assert!(in_network(ip("::ffff:127.0.0.1"), "127.0.0.0/8"));
+        assert!(in_network(ip("::ffff:192.0.2.1"), "192.0.2.1"));
+        assert!(!in_network(ip("::ffff:192.0.2.1"), "::1"));
+        assert!(in_network(ip("192.0.2.1"), "::ffff:192.0.2.0/120"));
+        assert!(in_network(ip("::ffff:192.0.2.1"), "::ffff:192.0.2.1"));
+        assert!(!in_network(ip("192.0.3.1"), "::ffff:192.0.2.0/120"));
+    }
+
+    #[test]
+    fn invalid_networks_match_nothing() {
+        assert!(!in_network(ip("127.0.0.1"), "127.0.0.0/40"));
+        assert!(!in_network(ip("127.0.0.1"), ""));
+    }
+}
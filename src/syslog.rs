@@ -0,0 +1,321 @@
+use log::{Level, Record};
+use std::borrow::Cow;
+use std::io;
+use std::os::unix::net::UnixDatagram;
+use std::path::PathBuf;
+
+use crate::config::{LoggingConfig, SystemLogProtocol};
+use crate::datetime::DateTime;
+use crate::errors::HttpErrors;
+use crate::logging::FieldValue;
+
+/// Name the server logs under.
+const APP_NAME: &str = "http-server-rs";
+
+const SYSLOG_SOCKET: &str = "/dev/log";
+const JOURNALD_SOCKET: &str = "/run/systemd/journal/socket";
+
+/// Syslog facility codes by name.
+const FACILITIES: [(&str, u8); 12] = [
+    ("user", 1),
+    ("daemon", 3),
+    ("auth", 4),
+    ("syslog", 5),
+    ("local0", 16),
+    ("local1", 17),
+    ("local2", 18),
+    ("local3", 19),
+    ("local4", 20),
+    ("local5", 21),
+    ("local6", 22),
+    ("local7", 23),
+];
+
+/// Sends log records to the local syslog daemon or to journald over their Unix sockets.
+pub struct SystemLog {
+    protocol: SystemLogProtocol,
+    path: PathBuf,
+    socket: UnixDatagram,
+    facility: u8,
+    hostname: String,
+}
+
+impl SystemLog {
+    /// Connects to the socket of `logging.system_log`, if one is chosen.
+    pub fn connect(config: &LoggingConfig) -> Result<Option<Self>, HttpErrors> {
+        let default_socket = match config.system_log {
+            SystemLogProtocol::None => return Ok(None),
+            SystemLogProtocol::Syslog => SYSLOG_SOCKET,
+            SystemLogProtocol::Journald => JOURNALD_SOCKET,
+        };
+        let path = PathBuf::from(match config.system_log_socket.as_str() {
+            "" => default_socket,
+            path => path,
+        });
+        let facility = FACILITIES
+            .iter()
+            .find(|(name, _)| *name == config.syslog_facility)
+            .map(|(_, code)| *code)
+            .ok_or_else(|| HttpErrors::LoggerInitFailure(format!("unknown syslog facility `{}`", config.syslog_facility)))?;
+
+        let socket = UnixDatagram::unbound().map_err(|e| HttpErrors::LoggerInitFailure(e.to_string()))?;
+        socket
+            .connect(&path)
+            .map_err(|e| HttpErrors::LoggerInitFailure(format!("{}: {e}", path.display())))?;
+
+        Ok(Some(SystemLog {
+            protocol: config.system_log,
+            path,
+            socket,
+            facility,
+            hostname: hostname(),
+        }))
+    }
+
+    pub fn send(&self, record: &Record, request_id: Option<&str>, fields: &[(&'static str, FieldValue)]) {
+        let text = record.args().to_string();
+        let mut length = text.len();
+        loop {
+            let text = truncate(&text, length);
+            let message = match self.protocol {
+                SystemLogProtocol::Journald => self.journald_message(record, &text, request_id, fields),
+                _ => self.syslog_message(record, &text),
+            };
+
+            // The daemon may have been restarted since, which leaves the socket disconnected.
+            let mut sent = self.socket.send(&message);
+            if sent.as_ref().is_err_and(|e| !is_too_long(e)) && self.socket.connect(&self.path).is_ok() {
+                sent = self.socket.send(&message);
+            }
+
+            // Datagrams over the socket's size limit are refused outright, so the message is
+            // cut down until it fits. There's nowhere left to report other failures.
+            match sent {
+                Err(e) if is_too_long(&e) && length > 0 => length /= 2,
+                _ => return,
+            }
+        }
+    }
+
+    /// Formats a record as an RFC 5424 message.
+    fn syslog_message(&self, record: &Record, text: &str) -> Vec<u8> {
+        format!(
+            "<{}>1 {} {} {APP_NAME} {} - - {}: {text}",
+            self.facility * 8 + severity(record.level()),
+            DateTime::now().to_rfc3339(),
+            self.hostname,
+            std::process::id(),
+            record.target(),
+        )
+        .into_bytes()
+    }
+
+    /// Formats a record with the journal's native protocol: one `FIELD=value` per line,
+    /// with the request ID and extra fields as fields of their own.
+    fn journald_message(
+        &self,
+        record: &Record,
+        text: &str,
+        request_id: Option<&str>,
+        fields: &[(&'static str, FieldValue)],
+    ) -> Vec<u8> {
+        let mut message = vec![];
+        journald_field(&mut message, "MESSAGE", text);
+        journald_field(&mut message, "PRIORITY", &severity(record.level()).to_string());
+        journald_field(&mut message, "SYSLOG_FACILITY", &self.facility.to_string());
+        journald_field(&mut message, "SYSLOG_IDENTIFIER", APP_NAME);
+        journald_field(&mut message, "TARGET", record.target());
+        if let Some(id) = request_id {
+            journald_field(&mut message, "REQUEST_ID", id);
+        }
+        for (name, value) in fields {
+            let value = match value {
+                FieldValue::Text(text) => text.clone(),
+                FieldValue::Number(number) => number.to_string(),
+                FieldValue::Null => continue,
+            };
+            journald_field(&mut message, &name.to_ascii_uppercase(), &value);
+        }
+        message
+    }
+}
+
+/// Appends a field, in the binary form if the value spans several lines.
+fn journald_field(message: &mut Vec<u8>, name: &str, value: &str) {
+    message.extend_from_slice(name.as_bytes());
+    if value.contains('\n') {
+        message.push(b'\n');
+        message.extend_from_slice(&(value.len() as u64).to_le_bytes());
+    } else {
+        message.push(b'=');
+    }
+    message.extend_from_slice(value.as_bytes());
+    message.push(b'\n');
+}
+
+/// Cuts `text` down to at most `length` bytes, marking it as truncated.
+fn truncate(text: &str, length: usize) -> Cow<'_, str> {
+    if length >= text.len() {
+        return Cow::Borrowed(text);
+    }
+    let end = (0..=length).rev().find(|i| text.is_char_boundary(*i)).unwrap_or(0);
+    Cow::Owned(format!("{}…", &text[..end]))
+}
+
+fn is_too_long(error: &io::Error) -> bool {
+    error.raw_os_error() == Some(libc::EMSGSIZE)
+}
+
+/// Maps a log level onto a syslog severity.
+fn severity(level: Level) -> u8 {
+    match level {
+        Level::Error => 3,
+        Level::Warn => 4,
+        Level::Info => 6,
+        Level::Debug | Level::Trace => 7,
+    }
+}
+
+fn hostname() -> String {
+    let mut name = [0u8; 256];
+    // SAFETY: gethostname writes at most `name.len()` bytes into the buffer.
+    if unsafe { libc::gethostname(name.as_mut_ptr() as *mut libc::c_char, name.len()) } != 0 {
+        return String::from("-");
+    }
+    let end = name.iter().position(|&b| b == 0).unwrap_or(name.len());
+    match String::from_utf8_lossy(&name[..end]).as_ref() {
+        "" => String::from("-"),
+        name => name.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SystemLog;
+    use crate::config::LoggingConfig;
+    use crate::logging::FieldValue;
+    use log::{Level, Record};
+    use std::os::unix::net::UnixDatagram;
+    use std::path::PathBuf;
+
+    /// Binds a stand-in for the daemon and connects a [`SystemLog`] speaking `protocol` to it.
+    fn connect(protocol: &str, name: &str) -> (SystemLog, UnixDatagram, PathBuf) {
+        let path = std::env::temp_dir().join(format!("http-server-rs-{}-{name}.sock", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let daemon = UnixDatagram::bind(&path).unwrap();
+        let config: LoggingConfig = toml::from_str(&format!(
+            "default_level = \"info\"\nlog_file_level = \"off\"\nlog_file = \"\"\nsystem_log = \"{protocol}\"\nsystem_log_socket = \"{}\"\nsyslog_facility = \"local3\"\n",
+            path.display()
+        ))
+        .unwrap();
+        (SystemLog::connect(&config).unwrap().unwrap(), daemon, path)
+    }
+
+    fn send(log: &SystemLog, text: &str, fields: &[(&'static str, FieldValue)]) {
+        log.send(
+            &Record::builder().args(format_args!("{text}")).level(Level::Warn).target("requests").build(),
+            Some("abc123"),
+            fields,
+        );
+    }
+
+    fn receive(daemon: &UnixDatagram) -> Vec<u8> {
+        let mut buffer = vec![0; 256 * 1024];
+        let length = daemon.recv(&mut buffer).unwrap();
+        buffer.truncate(length);
+        buffer
+    }
+
+    /// Splits a journal datagram into its fields, reading the binary form by its length.
+    fn journald_fields(mut message: &[u8]) -> Vec<(String, Vec<u8>)> {
+        let mut fields = vec![];
+        while !message.is_empty() {
+            let end = message.iter().position(|b| *b == b'\n').unwrap();
+            let line = &message[..end];
+            if let Some(equals) = line.iter().position(|b| *b == b'=') {
+                fields.push((String::from_utf8(line[..equals].to_vec()).unwrap(), line[equals + 1..].to_vec()));
+                message = &message[end + 1..];
+            } else {
+                let length = u64::from_le_bytes(message[end + 1..end + 9].try_into().unwrap()) as usize;
+                let value = &message[end + 9..end + 9 + length];
+                assert_eq!(message[end + 9 + length], b'\n');
+                fields.push((String::from_utf8(line.to_vec()).unwrap(), value.to_vec()));
+                message = &message[end + 10 + length..];
+            }
+        }
+        fields
+    }
+
+    /// Makes the socket refuse datagrams over a few kilobytes, like a daemon with small buffers.
+    fn shrink_send_buffer(log: &SystemLog) {
+        use std::os::fd::AsRawFd;
+        let size: libc::c_int = 1;
+        // SAFETY: the option value is a valid c_int of the given length.
+        let result = unsafe {
+            libc::setsockopt(
+                log.socket.as_raw_fd(),
+                libc::SOL_SOCKET,
+                libc::SO_SNDBUF,
+                &size as *const libc::c_int as *const libc::c_void,
+                std::mem::size_of::<libc::c_int>() as libc::socklen_t,
+            )
+        };
+        assert_eq!(result, 0);
+    }
+
+    #[test]
+    fn syslog_framing() {
+        let (log, daemon, path) = connect("syslog", "framing");
+        send(&log, "Disk full", &[]);
+        let message = String::from_utf8(receive(&daemon)).unwrap();
+        let _ = std::fs::remove_file(path);
+
+        // local3 (19) * 8 + warning (4)
+        let parts = message.splitn(8, ' ').collect::<Vec<_>>();
+        assert_eq!(parts[0], "<156>1");
+        assert!(parts[1].contains('T'));
+        assert_eq!(parts[3], "http-server-rs");
+        assert_eq!(parts[4], std::process::id().to_string());
+        assert_eq!(parts[5..], ["-", "-", "requests: Disk full"]);
+    }
+
+    #[test]
+    fn journald_fields_and_binary_values() {
+        let (log, daemon, path) = connect("journald", "fields");
+        send(&log, "first line\nsecond line", &[("status", FieldValue::Number(404)), ("user", FieldValue::Null)]);
+        let fields = journald_fields(&receive(&daemon));
+        let _ = std::fs::remove_file(path);
+
+        let field = |name: &str| fields.iter().find(|(n, _)| n == name).map(|(_, v)| String::from_utf8(v.clone()).unwrap());
+        assert_eq!(field("MESSAGE").as_deref(), Some("first line\nsecond line"));
+        assert_eq!(field("PRIORITY").as_deref(), Some("4"));
+        assert_eq!(field("SYSLOG_FACILITY").as_deref(), Some("19"));
+        assert_eq!(field("SYSLOG_IDENTIFIER").as_deref(), Some("http-server-rs"));
+        assert_eq!(field("TARGET").as_deref(), Some("requests"));
+        assert_eq!(field("REQUEST_ID").as_deref(), Some("abc123"));
+        assert_eq!(field("STATUS").as_deref(), Some("404"));
+        assert_eq!(field("USER"), None);
+    }
+
+    #[test]
+    fn oversized_messages_are_truncated() {
+        for protocol in ["syslog", "journald"] {
+            let (log, daemon, path) = connect(protocol, &format!("oversized-{protocol}"));
+            shrink_send_buffer(&log);
+            send(&log, &"é\n".repeat(50_000), &[]);
+            let message = receive(&daemon);
+            let _ = std::fs::remove_file(path);
+
+            assert!(message.len() < 100_000, "{protocol}");
+            let text = match protocol {
+                "journald" => {
+                    let fields = journald_fields(&message);
+                    String::from_utf8(fields.into_iter().find(|(n, _)| n == "MESSAGE").unwrap().1).unwrap()
+                }
+                _ => String::from_utf8(message).unwrap(),
+            };
+            assert!(text.ends_with('…'), "{protocol}");
+            assert!(text.contains("é\né\n"), "{protocol}");
+        }
+    }
+}
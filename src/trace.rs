@@ -0,0 +1,467 @@
+use log::{debug, error, info, warn};
+use sha2::{Digest, Sha256};
+use std::io::{Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError, SyncSender, TrySendError};
+use std::sync::{Mutex, OnceLock};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use crate::config::Config;
+use crate::errors::HttpErrors;
+use crate::escape::json_escape;
+use crate::logging::AccessEntry;
+
+/// Span kinds of the OTLP protocol.
+const SPAN_KIND_INTERNAL: u8 = 1;
+const SPAN_KIND_SERVER: u8 = 2;
+
+/// Status code of failed spans.
+const STATUS_ERROR: u8 = 2;
+
+/// How long the exporter waits for the collector.
+const EXPORT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Number of batches that may wait for the exporter before spans are dropped.
+const QUEUED_BATCHES: usize = 4;
+
+/// Spans waiting to be exported, already encoded as JSON. Taken away on shutdown.
+static EXPORTER: Mutex<Option<SyncSender<String>>> = Mutex::new(None);
+
+/// Spans dropped because the exporter fell behind.
+static DROPPED_SPANS: AtomicU64 = AtomicU64::new(0);
+
+/// Thread sending the spans, waited for on shutdown.
+static EXPORT_THREAD: OnceLock<Mutex<Option<JoinHandle<()>>>> = OnceLock::new();
+
+/// Keeps IDs generated within the same nanosecond apart.
+static ID_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+/// The `traceparent` of a request (W3C Trace Context).
+struct TraceParent {
+    trace_id: String,
+    span_id: String,
+    sampled: bool,
+}
+
+/// Collector address, taken apart from `tracing.endpoint`.
+struct Endpoint {
+    host: String,
+    path: String,
+}
+
+/// Starts the thread that sends spans to the collector in batches, if tracing is enabled.
+pub fn spawn_exporter(config: &Config) -> Result<(), HttpErrors> {
+    let tracing = &config.tracing;
+    if !tracing.enable {
+        return Ok(());
+    }
+
+    let endpoint = parse_endpoint(&tracing.endpoint)
+        .ok_or_else(|| HttpErrors::ConfigParseFailure(format!("invalid tracing endpoint `{}`", tracing.endpoint)))?;
+    let batch_size = tracing.batch_size.max(1);
+    // A slow or unreachable collector must not make the queue grow without bounds.
+    let (sender, receiver) = mpsc::sync_channel::<String>(batch_size.saturating_mul(QUEUED_BATCHES));
+    *EXPORTER.lock().unwrap_or_else(|e| e.into_inner()) = Some(sender);
+    info!("Exporting traces to {}", tracing.endpoint);
+
+    let service_name = tracing.service_name.clone();
+    let interval = Duration::from_secs(tracing.flush_interval.max(1));
+    let thread = thread::spawn(move || {
+        let mut batch = vec![];
+        let mut deadline = Instant::now() + interval;
+        loop {
+            let disconnected = match receiver.recv_timeout(deadline.saturating_duration_since(Instant::now())) {
+                Ok(span) => {
+                    batch.push(span);
+                    false
+                }
+                Err(RecvTimeoutError::Timeout) => false,
+                Err(RecvTimeoutError::Disconnected) => true,
+            };
+
+            if disconnected {
+                if !batch.is_empty() {
+                    export(&endpoint, &service_name, &batch);
+                }
+                break;
+            }
+            if batch.len() >= batch_size || Instant::now() >= deadline {
+                if !batch.is_empty() {
+                    export(&endpoint, &service_name, &batch);
+                    batch.clear();
+                }
+                deadline = Instant::now() + interval;
+            }
+        }
+    });
+    let _ = EXPORT_THREAD.set(Mutex::new(Some(thread)));
+
+    Ok(())
+}
+
+/// Stops taking spans and waits for the waiting ones to be exported.
+pub fn shutdown() {
+    // Dropping the sender tells the exporter to send its last batch.
+    EXPORTER.lock().unwrap_or_else(|e| e.into_inner()).take();
+    if let Some(thread) = EXPORT_THREAD.get().and_then(|t| t.lock().unwrap_or_else(|e| e.into_inner()).take()) {
+        let _ = thread.join();
+    }
+}
+
+/// Records the spans of a request whose response was sent: the request itself, with
+/// children for parsing it, opening the file (or whatever else produced the response) and
+/// sending the response. They continue the trace of the `traceparent` header, if any.
+pub fn record(entry: &AccessEntry) {
+    let exporter = EXPORTER.lock().unwrap_or_else(|e| e.into_inner());
+    let Some(exporter) = exporter.as_ref() else {
+        return;
+    };
+
+    let parent = entry.request.header("traceparent").and_then(parse_traceparent);
+    if parent.as_ref().is_some_and(|p| !p.sampled) {
+        return;
+    }
+    let trace_id = parent.as_ref().map_or_else(|| new_id(16), |p| p.trace_id.clone());
+    let root_id = new_id(8);
+
+    let end = unix_nanos(SystemTime::now());
+    let start = end - entry.duration.as_nanos();
+    let parsed = start + entry.parse.as_nanos();
+    let sending = end - entry.send.as_nanos();
+
+    let request = entry.request;
+    let attributes = [
+        string_attribute("http.request.method", &request.method),
+        string_attribute("url.path", &request.path),
+        int_attribute("http.response.status_code", entry.status as u64),
+        int_attribute("http.response.body.size", entry.bytes as u64),
+        string_attribute("client.address", &entry.peer.ip().to_string()),
+        string_attribute("user_agent.original", request.header("User-Agent").unwrap_or_default()),
+    ];
+    let root = Span {
+        trace_id: &trace_id,
+        span_id: &root_id,
+        parent_span_id: parent.as_ref().map(|p| p.span_id.as_str()),
+        name: &format!("{} {}", request.method, request.path),
+        kind: SPAN_KIND_SERVER,
+        start,
+        end,
+        attributes: &attributes,
+        failed: entry.status >= 500,
+    };
+
+    let children = [("parse", start, parsed), ("open", parsed, sending), ("send", sending, end)].map(|(name, start, end)| {
+        Span {
+            trace_id: &trace_id,
+            span_id: &new_id(8),
+            parent_span_id: Some(&root_id),
+            name,
+            kind: SPAN_KIND_INTERNAL,
+            start,
+            end,
+            attributes: &[],
+            failed: false,
+        }
+        .to_json()
+    });
+
+    for span in std::iter::once(root.to_json()).chain(children) {
+        if let Err(TrySendError::Full(_)) = exporter.try_send(span) {
+            if DROPPED_SPANS.fetch_add(1, Ordering::Relaxed) == 0 {
+                warn!("Trace exporter is falling behind, dropping spans");
+            }
+        }
+    }
+}
+
+/// Returns the number of spans dropped because the exporter fell behind.
+pub fn dropped_spans() -> u64 {
+    DROPPED_SPANS.load(Ordering::Relaxed)
+}
+
+struct Span<'a> {
+    trace_id: &'a str,
+    span_id: &'a str,
+    parent_span_id: Option<&'a str>,
+    name: &'a str,
+    kind: u8,
+    start: u128,
+    end: u128,
+    attributes: &'a [String],
+    failed: bool,
+}
+
+impl Span<'_> {
+    /// Encodes the span with the OTLP/JSON mapping, where IDs are hex and 64 bit integers
+    /// are strings.
+    fn to_json(&self) -> String {
+        let mut json = format!(
+            r#"{{"traceId":"{}","spanId":"{}","name":"{}","kind":{},"startTimeUnixNano":"{}","endTimeUnixNano":"{}","attributes":[{}]"#,
+            self.trace_id,
+            self.span_id,
+            json_escape(self.name),
+            self.kind,
+            self.start,
+            self.end,
+            self.attributes.join(","),
+        );
+        if let Some(parent) = self.parent_span_id {
+            json.push_str(&format!(r#","parentSpanId":"{parent}""#));
+        }
+        if self.failed {
+            json.push_str(&format!(r#","status":{{"code":{STATUS_ERROR}}}"#));
+        }
+        json.push('}');
+        json
+    }
+}
+
+fn string_attribute(key: &str, value: &str) -> String {
+    format!(r#"{{"key":"{key}","value":{{"stringValue":"{}"}}}}"#, json_escape(value))
+}
+
+fn int_attribute(key: &str, value: u64) -> String {
+    format!(r#"{{"key":"{key}","value":{{"intValue":"{value}"}}}}"#)
+}
+
+/// Posts a batch of spans to the collector. Failures are logged and the spans dropped.
+fn export(endpoint: &Endpoint, service_name: &str, spans: &[String]) {
+    let body = format!(
+        r#"{{"resourceSpans":[{{"resource":{{"attributes":[{}]}},"scopeSpans":[{{"scope":{{"name":"http-server-rs"}},"spans":[{}]}}]}}]}}"#,
+        string_attribute("service.name", service_name),
+        spans.join(","),
+    );
+
+    match post(endpoint, &body) {
+        Ok(status) if (200..300).contains(&status) => debug!("Exported {} spans", spans.len()),
+        Ok(status) => warn!("Trace collector answered {status}, dropped {} spans", spans.len()),
+        Err(e) => error!("Failed to export {} spans: {e}", spans.len()),
+    }
+}
+
+/// Sends a JSON body with a bare HTTP/1.1 POST and returns the status code of the answer.
+fn post(endpoint: &Endpoint, body: &str) -> std::io::Result<u16> {
+    let address = endpoint
+        .host
+        .to_socket_addrs()?
+        .next()
+        .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::NotFound, "collector address not found"))?;
+    let mut stream = TcpStream::connect_timeout(&address, EXPORT_TIMEOUT)?;
+    stream.set_read_timeout(Some(EXPORT_TIMEOUT))?;
+    stream.set_write_timeout(Some(EXPORT_TIMEOUT))?;
+    write!(
+        stream,
+        "POST {} HTTP/1.1\r\nHost: {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+        endpoint.path,
+        endpoint.host,
+        body.len()
+    )?;
+    stream.flush()?;
+
+    // Reading the whole answer lets the collector close the connection cleanly.
+    let mut response = vec![];
+    stream.read_to_end(&mut response)?;
+    String::from_utf8_lossy(response.get(9..12).unwrap_or_default())
+        .parse()
+        .map_err(|_| std::io::Error::new(std::io::ErrorKind::InvalidData, "invalid status line"))
+}
+
+/// Parses `http://host:port/path`. HTTPS isn't supported.
+fn parse_endpoint(endpoint: &str) -> Option<Endpoint> {
+    let rest = endpoint.strip_prefix("http://")?;
+    let (host, path) = match rest.find('/') {
+        Some(i) => (&rest[..i], &rest[i..]),
+        None => (rest, "/"),
+    };
+    if host.is_empty() {
+        return None;
+    }
+    let host = if host.rsplit_once(':').is_some_and(|(_, port)| port.parse::<u16>().is_ok()) {
+        host.to_string()
+    } else {
+        format!("{host}:80")
+    };
+
+    Some(Endpoint { host, path: path.to_string() })
+}
+
+/// Parses a version 00 `traceparent` header, e.g.
+/// `00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01`.
+fn parse_traceparent(header: &str) -> Option<TraceParent> {
+    let parts = header.trim().split('-').collect::<Vec<_>>();
+    let [version, trace_id, span_id, flags] = parts[..] else {
+        return None;
+    };
+    let is_hex = |s: &str, len: usize| s.len() == len && s.bytes().all(|b| b.is_ascii_digit() || (b'a'..=b'f').contains(&b));
+    if version != "00" || !is_hex(trace_id, 32) || !is_hex(span_id, 16) || !is_hex(flags, 2) {
+        return None;
+    }
+    if trace_id.bytes().all(|b| b == b'0') || span_id.bytes().all(|b| b == b'0') {
+        return None;
+    }
+
+    Some(TraceParent {
+        trace_id: trace_id.to_string(),
+        span_id: span_id.to_string(),
+        sampled: u8::from_str_radix(flags, 16).ok()? & 1 == 1,
+    })
+}
+
+/// Returns a new ID of `bytes` bytes as hex, hashed from the time, the process and a counter.
+fn new_id(bytes: usize) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(unix_nanos(SystemTime::now()).to_le_bytes());
+    hasher.update(std::process::id().to_le_bytes());
+    hasher.update(ID_COUNTER.fetch_add(1, Ordering::Relaxed).to_le_bytes());
+    hasher.finalize()[..bytes].iter().map(|b| format!("{b:02x}")).collect()
+}
+
+fn unix_nanos(time: SystemTime) -> u128 {
+    time.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_nanos())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::{new_id, parse_endpoint, parse_traceparent, post, Span, SPAN_KIND_SERVER};
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+    use std::thread;
+
+    /// Accepts one request like an OTLP collector would, answers `status` and returns the
+    /// request head and body.
+    fn collector(status: &str) -> (String, thread::JoinHandle<(String, String)>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let endpoint = format!("http://{}/v1/traces", listener.local_addr().unwrap());
+        let status = status.to_string();
+        let thread = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = vec![];
+            let mut buffer = [0; 4096];
+            let (head, length) = loop {
+                let read = stream.read(&mut buffer).unwrap();
+                request.extend_from_slice(&buffer[..read]);
+                let text = String::from_utf8_lossy(&request);
+                if let Some(end) = text.find("\r\n\r\n") {
+                    let head = text[..end].to_string();
+                    let length = head
+                        .lines()
+                        .find_map(|l| l.strip_prefix("Content-Length: "))
+                        .unwrap()
+                        .parse::<usize>()
+                        .unwrap();
+                    break (head, end + 4 + length);
+                }
+            };
+            while request.len() < length {
+                let read = stream.read(&mut buffer).unwrap();
+                request.extend_from_slice(&buffer[..read]);
+            }
+            write!(stream, "HTTP/1.1 {status}\r\nContent-Length: 0\r\nConnection: close\r\n\r\n").unwrap();
+            let body = String::from_utf8(request[head.len() + 4..].to_vec()).unwrap();
+            (head, body)
+        });
+        (endpoint, thread)
+    }
+
+    #[test]
+    fn posts_spans_to_the_collector() {
+        let (endpoint, collector) = collector("200 OK");
+        let span = Span {
+            trace_id: "4bf92f3577b34da6a3ce929d0e0e4736",
+            span_id: "00f067aa0ba902b7",
+            parent_span_id: None,
+            name: "GET /index.html",
+            kind: SPAN_KIND_SERVER,
+            start: 1,
+            end: 2,
+            attributes: &[],
+            failed: true,
+        };
+        let endpoint = parse_endpoint(&endpoint).unwrap();
+        let body = format!(r#"{{"spans":[{}]}}"#, span.to_json());
+        assert_eq!(post(&endpoint, &body).unwrap(), 200);
+
+        let (head, received) = collector.join().unwrap();
+        assert!(head.starts_with("POST /v1/traces HTTP/1.1\r\n"));
+        assert!(head.contains("Content-Type: application/json"));
+        assert_eq!(received, body);
+        assert!(received.contains(r#""traceId":"4bf92f3577b34da6a3ce929d0e0e4736""#));
+        assert!(received.contains(r#""status":{"code":2}"#));
+    }
+
+    #[test]
+    fn reports_the_collector_status() {
+        let (endpoint, collector) = collector("503 Service Unavailable");
+        assert_eq!(post(&parse_endpoint(&endpoint).unwrap(), "{}").unwrap(), 503);
+        collector.join().unwrap();
+    }
+
+    #[test]
+    fn fails_when_nothing_listens() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let endpoint = format!("http://{}/v1/traces", listener.local_addr().unwrap());
+        drop(listener);
+        assert!(post(&parse_endpoint(&endpoint).unwrap(), "{}").is_err());
+    }
+
+    #[test]
+    fn accepts_valid_traceparents() {
+        let parent = parse_traceparent("00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01").unwrap();
+        assert_eq!(parent.trace_id, "4bf92f3577b34da6a3ce929d0e0e4736");
+        assert_eq!(parent.span_id, "00f067aa0ba902b7");
+        assert!(parent.sampled);
+
+        let parent = parse_traceparent(" 00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-00 ").unwrap();
+        assert!(!parent.sampled);
+        // Only the sampled flag is looked at.
+        assert!(parse_traceparent("00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-03").unwrap().sampled);
+    }
+
+    #[test]
+    fn rejects_invalid_traceparents() {
+        for header in [
+            "",
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7",
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01-00",
+            "01-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01",
+            "ff-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01",
+            "00-4BF92F3577B34DA6A3CE929D0E0E4736-00f067aa0ba902b7-01",
+            "00-4bf92f3577b34da6a3ce929d0e0e473-00f067aa0ba902b7-01",
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b-01",
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-1",
+            "00-4bf92f3577b34da6a3ce929d0e0e473g-00f067aa0ba902b7-01",
+            "00-00000000000000000000000000000000-00f067aa0ba902b7-01",
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-0000000000000000-01",
+        ] {
+            assert!(parse_traceparent(header).is_none(), "{header}");
+        }
+    }
+
+    #[test]
+    fn parses_endpoints() {
+        let endpoint = parse_endpoint("http://localhost:4318/v1/traces").unwrap();
+        assert_eq!((endpoint.host.as_str(), endpoint.path.as_str()), ("localhost:4318", "/v1/traces"));
+
+        let endpoint = parse_endpoint("http://collector").unwrap();
+        assert_eq!((endpoint.host.as_str(), endpoint.path.as_str()), ("collector:80", "/"));
+
+        let endpoint = parse_endpoint("http://[::1]:4318/").unwrap();
+        assert_eq!((endpoint.host.as_str(), endpoint.path.as_str()), ("[::1]:4318", "/"));
+
+        assert!(parse_endpoint("https://collector:4318/v1/traces").is_none());
+        assert!(parse_endpoint("collector:4318").is_none());
+        assert!(parse_endpoint("http:///v1/traces").is_none());
+    }
+
+    #[test]
+    fn new_ids_are_unique_lowercase_hex() {
+        let (first, second) = (new_id(16), new_id(16));
+        assert_eq!(first.len(), 32);
+        assert_eq!(new_id(8).len(), 16);
+        assert!(first.bytes().all(|b| b.is_ascii_digit() || (b'a'..=b'f').contains(&b)));
+        assert_ne!(first, second);
+    }
+}
@@ -0,0 +1,461 @@
+use log::info;
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+use fs_err as fs;
+
+use crate::body::HttpBody;
+use crate::config::Config;
+use crate::errors::HttpErrors;
+use crate::escape::percent_encode;
+use crate::forms::{sanitize_filename, unique_path};
+use crate::headers;
+use crate::headers::{
+    HttpCacheControlHeader, HttpContent, HttpContentLengthHeader, HttpHeader, HttpLocationHeader, HttpResponse,
+    HttpStatusHeader, HttpTusExtensionHeader, HttpTusMaxSizeHeader, HttpTusResumableHeader, HttpTusVersionHeader,
+    HttpUploadLengthHeader, HttpUploadMetadataHeader, HttpUploadOffsetHeader, BAD_REQUEST, CONFLICT, CREATED,
+    NOT_FOUND, NO_CONTENT, OK, PAYLOAD_TOO_LARGE, PRECONDITION_FAILED, UNAUTHORIZED, UNSUPPORTED_MEDIA_TYPE,
+};
+use crate::parser::HttpRequest;
+use crate::quota;
+use crate::requests::to_local_path;
+use crate::upload::{is_under_root, matches_prefix};
+
+/// Version of the tus protocol that is implemented.
+const TUS_VERSION: &str = "1.0.0";
+
+/// Extensions supported on top of the core protocol.
+const TUS_EXTENSIONS: &str = "creation,termination";
+
+/// Used to keep upload IDs unique within the same nanosecond.
+static UPLOAD_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+/// State of an upload, stored next to its data as `<id>.info`.
+struct UploadInfo {
+    length: u64,
+    metadata: String,
+}
+
+impl UploadInfo {
+    fn read(path: &Path) -> Option<Self> {
+        let mut contents = String::new();
+        fs::File::open(path).ok()?.read_to_string(&mut contents).ok()?;
+
+        let mut info = UploadInfo { length: 0, metadata: String::new() };
+        for line in contents.lines() {
+            match line.split_once('=') {
+                Some(("length", v)) => info.length = v.parse().ok()?,
+                Some(("metadata", v)) => info.metadata = v.to_string(),
+                _ => (),
+            }
+        }
+        Some(info)
+    }
+
+    fn write(&self, path: &Path) -> Result<(), HttpErrors> {
+        fs::write(path, format!("length={}\nmetadata={}\n", self.length, self.metadata))
+            .map_err(|e| HttpErrors::FileWriteFailure(e.to_string()))
+    }
+}
+
+/// Returns true if the request is addressed to the tus endpoint.
+pub fn is_tus_request(request: &HttpRequest, config: &Config) -> bool {
+    config.server.tus.enable && matches_prefix(&request.path, &config.server.tus.endpoint)
+}
+
+/// Returns true if `path` lies in the staging directory, whose uploads in progress aren't
+/// served or listed.
+pub fn is_staging_path(path: &str, config: &Config) -> bool {
+    config.server.tus.enable && matches_prefix(path, &config.server.tus.staging_dir)
+}
+
+/// Handles a request to the tus endpoint: `OPTIONS` for discovery, `POST` to create an
+/// upload, `HEAD` to find its offset, `PATCH` to resume it and `DELETE` to terminate it.
+pub fn handle(request: &HttpRequest, body: &mut HttpBody, config: &Config) -> Result<HttpResponse, HttpErrors> {
+    if request.method == "OPTIONS" {
+        let mut headers = headers!(
+            HttpStatusHeader::new_from(NO_CONTENT.code);
+            HttpTusResumableHeader::new_from(String::from(TUS_VERSION));
+            HttpTusVersionHeader::new_from(String::from(TUS_VERSION));
+            HttpTusExtensionHeader::new_from(String::from(TUS_EXTENSIONS));
+        );
+        if config.server.tus.max_size > 0 {
+            headers = headers.add_header(HttpTusMaxSizeHeader::new_from(config.server.tus.max_size.to_string()));
+        }
+        return Ok(HttpResponse { headers: headers.build(), content: HttpContent::default() });
+    }
+
+    if request.header("Tus-Resumable") != Some(TUS_VERSION) {
+        return Ok(tus_response(
+            headers!(
+                HttpStatusHeader::new_from(PRECONDITION_FAILED.code);
+                HttpTusVersionHeader::new_from(String::from(TUS_VERSION));
+            ),
+        ));
+    }
+
+    let staging = PathBuf::from(to_local_path(&config.server.root, &config.server.tus.staging_dir));
+    if config.server.tus.staging_dir.contains("..") {
+        return Ok(tus_error(UNAUTHORIZED.code));
+    }
+    if !is_under_root(&staging, config) {
+        return Ok(tus_error(UNAUTHORIZED.code));
+    }
+    fs::create_dir_all(&staging).map_err(|e| HttpErrors::FileWriteFailure(e.to_string()))?;
+
+    let endpoint = config.server.tus.endpoint.trim_end_matches('/');
+    let id = request.path[endpoint.len()..].trim_matches('/');
+
+    if id.is_empty() {
+        return match request.method.as_str() {
+            "POST" => create(request, &staging, config),
+            _ => Ok(tus_error(NOT_FOUND.code)),
+        };
+    }
+
+    // IDs are generated by `create`, anything else can't be an upload.
+    if !id.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return Ok(tus_error(NOT_FOUND.code));
+    }
+
+    let data_path = staging.join(id);
+    let info_path = staging.join(format!("{id}.info"));
+    let Some(info) = UploadInfo::read(&info_path) else {
+        return Ok(tus_error(NOT_FOUND.code));
+    };
+    let offset = fs::metadata(&data_path).map(|m| m.len()).unwrap_or(0);
+
+    match request.method.as_str() {
+        "HEAD" => {
+            let mut headers = headers!(
+                HttpStatusHeader::new_from(OK.code);
+                HttpUploadOffsetHeader::new_from(offset.to_string());
+                HttpUploadLengthHeader::new_from(info.length.to_string());
+                HttpCacheControlHeader::new_from(String::from("no-store"));
+            );
+            if !info.metadata.is_empty() {
+                headers = headers.add_header(HttpUploadMetadataHeader::new_from(info.metadata.clone()));
+            }
+            Ok(tus_response(headers))
+        }
+        "PATCH" => {
+            if request.header("Content-Type") != Some("application/offset+octet-stream") {
+                return Ok(tus_error(UNSUPPORTED_MEDIA_TYPE.code));
+            }
+
+            let client_offset = request.header("Upload-Offset").and_then(|o| o.parse::<u64>().ok());
+            if client_offset.is_none() {
+                return Ok(tus_error(BAD_REQUEST.code));
+            }
+            if client_offset != Some(offset) {
+                return Ok(tus_error(CONFLICT.code));
+            }
+
+            let remaining = info.length - offset.min(info.length);
+            if request.content_length()?.is_some_and(|l| l > remaining) {
+                return Ok(tus_error(PAYLOAD_TOO_LARGE.code));
+            }
+
+            // Other uploads may have used up the space since this one was created.
+            let allowance = quota::allowance(&config.server.tus.staging_dir, 0, config);
+            if !allowance.permits(request.content_length()?.unwrap_or(0)) {
+                return Ok(tus_error(allowance.code));
+            }
+
+            let mut file = fs::OpenOptions::new()
+                .append(true)
+                .open(&data_path)
+                .map_err(|e| HttpErrors::FileWriteFailure(e.to_string()))?;
+            // Keep whatever arrived before the connection dropped, that's the point of resuming.
+            let copied = io::copy(&mut body.take(remaining), &mut file);
+            file.flush().map_err(|e| HttpErrors::FileWriteFailure(e.to_string()))?;
+            copied.map_err(|e| HttpErrors::StreamReadFailure(e.to_string()))?;
+
+            let written = fs::metadata(&data_path).map(|m| m.len()).unwrap_or(0);
+            quota::record_write(&config.server.tus.staging_dir, written.saturating_sub(offset), 0);
+            let offset = written;
+            if offset == info.length {
+                let target = complete(id, &staging, &info, config)?;
+                info!("tus upload `{id}` complete ({offset} bytes), saved as {}", target.display());
+            }
+
+            Ok(tus_response(headers!(
+                HttpStatusHeader::new_from(NO_CONTENT.code);
+                HttpUploadOffsetHeader::new_from(offset.to_string());
+            )))
+        }
+        "DELETE" => {
+            // The info file is what makes the upload exist, so it goes first. If it's already
+            // gone, the upload was finished or terminated in the meantime.
+            match fs::remove_file(&info_path) {
+                Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(tus_error(NOT_FOUND.code)),
+                removed => removed.map_err(|e| HttpErrors::FileWriteFailure(e.to_string()))?,
+            }
+            match fs::remove_file(&data_path) {
+                Err(e) if e.kind() == io::ErrorKind::NotFound => (),
+                removed => removed.map_err(|e| HttpErrors::FileWriteFailure(e.to_string()))?,
+            }
+            quota::record_removal(&config.server.tus.staging_dir, offset);
+            Ok(tus_response(headers!(HttpStatusHeader::new_from(NO_CONTENT.code);)))
+        }
+        _ => Ok(tus_error(NOT_FOUND.code)),
+    }
+}
+
+/// Creates a new, empty upload (creation extension).
+fn create(request: &HttpRequest, staging: &Path, config: &Config) -> Result<HttpResponse, HttpErrors> {
+    let Some(length) = request.header("Upload-Length").and_then(|l| l.parse::<u64>().ok()) else {
+        return Ok(tus_error(BAD_REQUEST.code));
+    };
+
+    let max_size = config.server.tus.max_size;
+    if max_size > 0 && length > max_size {
+        return Ok(tus_error(PAYLOAD_TOO_LARGE.code));
+    }
+
+    let allowance = quota::allowance(&config.server.tus.staging_dir, 0, config);
+    if !allowance.permits(length) {
+        return Ok(tus_error(allowance.code));
+    }
+
+    let nanos = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_nanos()).unwrap_or(0);
+    let id = format!("{nanos:x}{:x}{:04x}", std::process::id(), UPLOAD_COUNTER.fetch_add(1, Ordering::Relaxed));
+
+    fs::File::create(staging.join(&id)).map_err(|e| HttpErrors::FileWriteFailure(e.to_string()))?;
+    let info = UploadInfo {
+        length,
+        metadata: request.header("Upload-Metadata").unwrap_or_default().to_string(),
+    };
+    info.write(&staging.join(format!("{id}.info")))?;
+    // An empty upload is finished as soon as it exists.
+    if length == 0 {
+        complete(&id, staging, &info, config)?;
+    }
+
+    let location = format!("{}/{id}", config.server.tus.endpoint.trim_end_matches('/'));
+    Ok(tus_response(headers!(
+        HttpStatusHeader::new_from(CREATED.code);
+        HttpLocationHeader::new_from(percent_encode(&location));
+    )))
+}
+
+/// Moves a finished upload out of the staging directory into `tus.destination_dir`, under
+/// the file name from its metadata or else its ID. Returns where it ended up.
+fn complete(id: &str, staging: &Path, info: &UploadInfo, config: &Config) -> Result<PathBuf, HttpErrors> {
+    let options = &config.server.tus;
+    let destination = PathBuf::from(to_local_path(&config.server.root, &options.destination_dir));
+    if options.destination_dir.contains("..") || !is_under_root(&destination, config) {
+        return Err(HttpErrors::UnauthorizedPath(options.destination_dir.clone()));
+    }
+    fs::create_dir_all(&destination).map_err(|e| HttpErrors::FileWriteFailure(e.to_string()))?;
+
+    let filename = metadata_value(&info.metadata, "filename")
+        .map(|f| sanitize_filename(&String::from_utf8_lossy(&f)))
+        .filter(|f| !f.is_empty())
+        .unwrap_or_else(|| id.to_string());
+    let target = unique_path(&destination, &filename);
+    fs::rename(staging.join(id), &target).map_err(|e| HttpErrors::FileWriteFailure(e.to_string()))?;
+    fs::remove_file(staging.join(format!("{id}.info"))).map_err(|e| HttpErrors::FileWriteFailure(e.to_string()))?;
+    quota::record_removal(&options.staging_dir, info.length);
+    quota::record_write(&options.destination_dir, info.length, 0);
+
+    Ok(target)
+}
+
+/// Returns the decoded value of `key` in an `Upload-Metadata` header, which lists
+/// comma separated keys, each followed by its value in base64.
+fn metadata_value(metadata: &str, key: &str) -> Option<Vec<u8>> {
+    metadata
+        .split(',')
+        .filter_map(|pair| pair.trim().split_once(' '))
+        .find(|(k, _)| *k == key)
+        .and_then(|(_, value)| decode_base64(value.trim()))
+}
+
+/// Decodes standard base64, with or without padding. Returns [`None`] on characters outside
+/// the alphabet and on lengths no encoder produces.
+fn decode_base64(text: &str) -> Option<Vec<u8>> {
+    let unpadded = text.trim_end_matches('=');
+    let padding = text.len() - unpadded.len();
+    if unpadded.len() % 4 == 1 || padding > 2 || (padding > 0 && !text.len().is_multiple_of(4)) {
+        return None;
+    }
+
+    let mut decoded = vec![];
+    let mut buffer = 0u32;
+    let mut bits = 0;
+    for b in unpadded.bytes() {
+        let value = match b {
+            b'A'..=b'Z' => b - b'A',
+            b'a'..=b'z' => b - b'a' + 26,
+            b'0'..=b'9' => b - b'0' + 52,
+            b'+' => 62,
+            b'/' => 63,
+            _ => return None,
+        };
+        buffer = buffer << 6 | value as u32;
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            decoded.push((buffer >> bits) as u8);
+        }
+    }
+    Some(decoded)
+}
+
+/// Builds a bodyless error response. tus clients only look at the status code.
+fn tus_error(code: u16) -> HttpResponse {
+    tus_response(headers!(HttpStatusHeader::new_from(code);))
+}
+
+/// Finishes a response by adding the `Tus-Resumable` header every tus response carries.
+fn tus_response(headers: headers::HeaderVec) -> HttpResponse {
+    HttpResponse {
+        headers: headers
+            .add_header(HttpTusResumableHeader::new_from(String::from(TUS_VERSION)))
+            .add_header(HttpContentLengthHeader::new_from(0))
+            .build(),
+        content: HttpContent::default(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{decode_base64, handle, metadata_value};
+    use crate::body::HttpBody;
+    use crate::config::{for_tests, Config};
+    use crate::headers::HttpResponse;
+    use crate::parser::HttpRequest;
+    use std::net::{TcpListener, TcpStream};
+    use std::path::PathBuf;
+
+    #[test]
+    fn decodes_base64_with_and_without_padding() {
+        assert_eq!(decode_base64("").unwrap(), b"");
+        assert_eq!(decode_base64("Zg==").unwrap(), b"f");
+        assert_eq!(decode_base64("Zm8=").unwrap(), b"fo");
+        assert_eq!(decode_base64("Zm9v").unwrap(), b"foo");
+        assert_eq!(decode_base64("Zg").unwrap(), b"f");
+        assert_eq!(decode_base64("Zm8").unwrap(), b"fo");
+        assert_eq!(decode_base64("aGVsbG8gd29ybGQ=").unwrap(), b"hello world");
+        assert_eq!(decode_base64("+/8=").unwrap(), [0xfb, 0xff]);
+    }
+
+    #[test]
+    fn rejects_invalid_base64() {
+        assert_eq!(decode_base64("Zm9v!"), None);
+        assert_eq!(decode_base64("Zm-v"), None);
+        assert_eq!(decode_base64("Z"), None);
+        assert_eq!(decode_base64("Zg="), None);
+        assert_eq!(decode_base64("Z==="), None);
+        assert_eq!(decode_base64("Zg==Zg=="), None);
+        assert_eq!(decode_base64("===="), None);
+    }
+
+    #[test]
+    fn reads_upload_metadata() {
+        let metadata = "filename d29ybGRfZG9taW5hdGlvbl9wbGFuLnBkZg==, is_confidential,filetype dGV4dC9wbGFpbg==";
+        assert_eq!(metadata_value(metadata, "filename").unwrap(), b"world_domination_plan.pdf");
+        assert_eq!(metadata_value(metadata, "filetype").unwrap(), b"text/plain");
+        // Keys without a value can't be decoded into anything.
+        assert_eq!(metadata_value(metadata, "is_confidential"), None);
+        assert_eq!(metadata_value(metadata, "missing"), None);
+        assert_eq!(metadata_value("filename !!", "filename"), None);
+        assert_eq!(metadata_value("", "filename"), None);
+    }
+
+    /// A tus endpoint serving a fresh directory.
+    struct Server {
+        root: PathBuf,
+        config: Config,
+        listener: TcpListener,
+    }
+
+    impl Server {
+        fn new(name: &str) -> Self {
+            let root = std::env::temp_dir().join(format!("http-server-rs-{}-tus-{name}", std::process::id()));
+            let _ = std::fs::remove_dir_all(&root);
+            std::fs::create_dir_all(&root).unwrap();
+            let mut config = for_tests("", "[server.tus]\nenable = true\n");
+            config.server.root = root.display().to_string();
+            Server { root, config, listener: TcpListener::bind("127.0.0.1:0").unwrap() }
+        }
+
+        /// Sends a request with `body` to the tus handler.
+        fn send(&self, head: &str, body: &[u8]) -> HttpResponse {
+            let request = HttpRequest::parse(&format!("{head}\r\nTus-Resumable: 1.0.0\r\nContent-Length: {}\r\n\r\n", body.len())).unwrap();
+            let _client = TcpStream::connect(self.listener.local_addr().unwrap()).unwrap();
+            let (stream, _) = self.listener.accept().unwrap();
+            let mut body = HttpBody::new(&request, body.to_vec(), &stream).unwrap();
+            handle(&request, &mut body, &self.config).unwrap()
+        }
+
+        fn create(&self, length: u64, metadata: &str) -> String {
+            let response = self.send(&format!("POST /files HTTP/1.1\r\nUpload-Length: {length}\r\nUpload-Metadata: {metadata}"), b"");
+            assert_eq!(response.status_code(), 201);
+            header(&response, "Location").to_string()
+        }
+    }
+
+    impl Drop for Server {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.root);
+        }
+    }
+
+    fn header<'a>(response: &'a HttpResponse, name: &str) -> &'a str {
+        response
+            .headers
+            .lines()
+            .find_map(|l| l.split_once(": ").filter(|(n, _)| n.eq_ignore_ascii_case(name)))
+            .map(|(_, v)| v)
+            .unwrap_or_default()
+    }
+
+    #[test]
+    fn resumes_at_the_right_offset_only() {
+        let server = Server::new("offset");
+        let location = server.create(10, "");
+        let patch = |offset: u64, body: &[u8]| {
+            server.send(
+                &format!("PATCH {location} HTTP/1.1\r\nContent-Type: application/offset+octet-stream\r\nUpload-Offset: {offset}"),
+                body,
+            )
+        };
+
+        let response = patch(0, b"hello");
+        assert_eq!(response.status_code(), 204);
+        assert_eq!(header(&response, "Upload-Offset"), "5");
+        assert_eq!(patch(0, b"hello").status_code(), 409);
+        assert_eq!(patch(7, b"abc").status_code(), 409);
+        assert_eq!(patch(5, b"world!").status_code(), 413);
+        assert_eq!(patch(5, b"world").status_code(), 204);
+        assert_eq!(std::fs::read(server.root.join("uploads").join(location.rsplit('/').next().unwrap())).unwrap(), b"helloworld");
+    }
+
+    #[test]
+    fn finished_uploads_are_named_from_metadata() {
+        let server = Server::new("name");
+        let location = server.create(2, "filename Li4vYS50eHQ=");
+        let response = server.send(
+            &format!("PATCH {location} HTTP/1.1\r\nContent-Type: application/offset+octet-stream\r\nUpload-Offset: 0"),
+            b"hi",
+        );
+        assert_eq!(response.status_code(), 204);
+        assert_eq!(std::fs::read(server.root.join("uploads/a.txt")).unwrap(), b"hi");
+    }
+
+    #[test]
+    fn terminating_unknown_or_finished_uploads_is_not_found() {
+        let server = Server::new("terminate");
+        let location = server.create(4, "");
+        assert_eq!(server.send(&format!("DELETE {location} HTTP/1.1"), b"").status_code(), 204);
+        assert_eq!(server.send(&format!("DELETE {location} HTTP/1.1"), b"").status_code(), 404);
+        assert_eq!(server.send(&format!("HEAD {location} HTTP/1.1"), b"").status_code(), 404);
+        assert_eq!(server.send("DELETE /files/abc123 HTTP/1.1", b"").status_code(), 404);
+
+        let finished = server.create(0, "");
+        assert_eq!(server.send(&format!("DELETE {finished} HTTP/1.1"), b"").status_code(), 404);
+    }
+}
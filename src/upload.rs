@@ -0,0 +1,143 @@
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+use fs_err as fs;
+
+use crate::body::HttpBody;
+use crate::config::Config;
+use crate::error_pages::error_response;
+use crate::errors::HttpErrors;
+use crate::escape::percent_encode;
+use crate::headers;
+use crate::headers::{
+    HttpContent, HttpContentLengthHeader, HttpHeader, HttpLocationHeader, HttpResponse, HttpStatusHeader,
+    BAD_REQUEST, CONFLICT, CREATED, FORBIDDEN, LENGTH_REQUIRED, NO_CONTENT, PRECONDITION_FAILED, UNAUTHORIZED,
+};
+use crate::parser::HttpRequest;
+use crate::preconditions;
+use crate::quota;
+use crate::requests::{empty_response, to_local_path};
+
+/// Returns true if `path` equals `prefix` or lies underneath it.
+pub fn matches_prefix(path: &str, prefix: &str) -> bool {
+    let prefix = prefix.trim_end_matches('/');
+    path == prefix || path.starts_with(&format!("{prefix}/"))
+}
+
+/// Returns true if the configuration allows modifying `path`.
+pub fn is_writable(path: &str, config: &Config) -> bool {
+    let uploads = &config.server.uploads;
+    uploads.enable && uploads.allowed_prefixes.iter().any(|p| matches_prefix(path, p))
+}
+
+/// Checks that the directory `dir` resolves to somewhere under the root, so that
+/// symlinks can't be used to write outside of it. If `dir` doesn't exist yet, its deepest
+/// existing ancestor is checked instead, before anything gets created in it.
+pub fn is_under_root(dir: &Path, config: &Config) -> bool {
+    let Ok(root) = fs::canonicalize(&config.server.root) else {
+        return false;
+    };
+    dir.ancestors()
+        .find(|a| std::fs::symlink_metadata(a).is_ok())
+        .and_then(|a| fs::canonicalize(a).ok())
+        .is_some_and(|dir| dir.starts_with(root))
+}
+
+/// Creates a hidden temporary file next to `target`, to be renamed over it once complete.
+pub fn create_temp_file(target: &Path) -> Result<(fs::File, PathBuf), HttpErrors> {
+    let parent = target.parent().unwrap_or(Path::new("."));
+    let name = target.file_name().unwrap_or_default().to_string_lossy();
+    let nanos = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_nanos()).unwrap_or(0);
+    let temp = parent.join(format!(".{name}.upload-{}-{nanos}", std::process::id()));
+
+    let file = fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(&temp)
+        .map_err(|e| HttpErrors::FileWriteFailure(e.to_string()))?;
+
+    Ok((file, temp))
+}
+
+/// Streams the request body into a temporary file, then atomically renames it over the target.
+pub fn put(request: &HttpRequest, body: &mut HttpBody, config: &Config) -> Result<HttpResponse, HttpErrors> {
+    if request.path.contains("..") {
+        return Ok(error_response(UNAUTHORIZED.code, request, config));
+    }
+
+    if !is_writable(&request.path, config) {
+        return Ok(error_response(FORBIDDEN.code, request, config));
+    }
+
+    if request.path.ends_with('/') {
+        return Ok(error_response(BAD_REQUEST.code, request, config));
+    }
+
+    if request.header("Content-Length").is_none() && request.header("Transfer-Encoding").is_none() {
+        return Ok(error_response(LENGTH_REQUIRED.code, request, config));
+    }
+
+    let target = PathBuf::from(to_local_path(&config.server.root, &request.path));
+    let existing = fs::metadata(&target).ok();
+    if existing.as_ref().is_some_and(|m| m.is_dir()) {
+        return Ok(error_response(CONFLICT.code, request, config));
+    }
+
+    if !preconditions::check(request, existing.as_ref()) {
+        return Ok(error_response(PRECONDITION_FAILED.code, request, config));
+    }
+
+    let allowance = quota::allowance(&request.path, existing.as_ref().map_or(0, |m| m.len()), config);
+    if !allowance.permits(request.content_length()?.unwrap_or(0)) {
+        return Ok(error_response(allowance.code, request, config));
+    }
+
+    let parent = target.parent().unwrap_or(Path::new("."));
+    if !is_under_root(parent, config) {
+        return Ok(error_response(UNAUTHORIZED.code, request, config));
+    }
+
+    if !parent.is_dir() {
+        if !config.server.uploads.create_dirs {
+            return Ok(error_response(CONFLICT.code, request, config));
+        }
+        fs::create_dir_all(parent).map_err(|e| HttpErrors::FileWriteFailure(e.to_string()))?;
+    }
+
+    let (mut file, temp) = create_temp_file(&target)?;
+    // Chunked bodies don't announce their length, so the allowance is enforced while copying too.
+    let copied = io::copy(&mut body.take(allowance.max.saturating_add(1)), &mut file);
+    if copied.as_ref().is_ok_and(|c| !allowance.permits(*c)) {
+        let _ = fs::remove_file(&temp);
+        return Ok(error_response(allowance.code, request, config));
+    }
+
+    let written = copied
+        .map_err(|e| HttpErrors::StreamReadFailure(e.to_string()))
+        .and_then(|c| file.flush().map(|_| c).map_err(|e| HttpErrors::FileWriteFailure(e.to_string())))
+        .and_then(|c| file.file().sync_all().map(|_| c).map_err(|e| HttpErrors::FileWriteFailure(e.to_string())))
+        .and_then(|c| fs::rename(&temp, &target).map(|_| c).map_err(|e| HttpErrors::FileWriteFailure(e.to_string())));
+
+    match written {
+        Ok(written) => quota::record_write(&request.path, written, existing.as_ref().map_or(0, |m| m.len())),
+        Err(e) => {
+            let _ = fs::remove_file(&temp);
+            return Err(e);
+        }
+    }
+
+    if existing.is_some() {
+        return Ok(empty_response(NO_CONTENT.code));
+    }
+
+    let content = HttpContent::default();
+    Ok(HttpResponse {
+        headers: headers!(
+            HttpStatusHeader::new_from(CREATED.code);
+            HttpLocationHeader::new_from(percent_encode(&request.path));
+            HttpContentLengthHeader::new_from(content.content.len());
+        )
+        .build(),
+        content,
+    })
+}
@@ -0,0 +1,693 @@
+use std::fs::Metadata;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use fs_err as fs;
+
+use crate::access;
+use crate::autoindex::is_hidden;
+use crate::body::HttpBody;
+use crate::config::Config;
+use crate::datetime::DateTime;
+use crate::delete;
+use crate::error_pages::error_response;
+use crate::errors::HttpErrors;
+use crate::escape::{html_escape, percent_encode};
+use crate::headers;
+use crate::headers::{
+    HttpAllowHeader, HttpContent, HttpContentLengthHeader, HttpContentTypeHeader, HttpDavHeader, HttpHeader,
+    HttpLockTokenHeader, HttpMsAuthorViaHeader, HttpResponse, HttpStatusCode, HttpStatusHeader, BAD_GATEWAY,
+    BAD_REQUEST, CONFLICT, CREATED, FORBIDDEN, LOCKED, METHOD_NOT_ALLOWED, MULTI_STATUS, NOT_FOUND, NO_CONTENT, OK,
+    PAYLOAD_TOO_LARGE, PRECONDITION_FAILED, UNAUTHORIZED, UNSUPPORTED_MEDIA_TYPE,
+};
+use crate::locks::{self, Lock, LockScope};
+use crate::media;
+use crate::quota;
+use crate::parser::{percent_decode, HttpRequest};
+use crate::requests::{empty_response, extension, is_internal_path, to_local_path};
+use crate::status_code_string;
+use crate::upload::{is_under_root, is_writable};
+use crate::xml::{self, XmlEvent, DAV_NAMESPACE};
+
+/// WebDAV compliance classes advertised in the `DAV` header.
+const DAV_CLASSES: &str = "1, 2";
+
+/// Methods advertised in the `Allow` header of `OPTIONS` responses, if the access policy
+/// allows them.
+const ALLOWED_METHODS: &str = "OPTIONS, GET, HEAD, PUT, DELETE, PROPFIND, PROPPATCH, MKCOL, COPY, MOVE, LOCK, UNLOCK";
+
+/// Maximum size of an XML request body, in bytes.
+const MAX_XML_BODY_LENGTH: u64 = 1024 * 1024;
+
+/// Properties computed from the filesystem, in the order they're reported.
+const LIVE_PROPERTIES: [&str; 9] = [
+    "creationdate",
+    "displayname",
+    "getcontentlength",
+    "getcontenttype",
+    "getetag",
+    "getlastmodified",
+    "lockdiscovery",
+    "resourcetype",
+    "supportedlock",
+];
+
+/// Lock kinds advertised in the `supportedlock` property.
+const SUPPORTED_LOCKS: &str = "<D:lockentry><D:lockscope><D:exclusive/></D:lockscope><D:locktype><D:write/></D:locktype></D:lockentry><D:lockentry><D:lockscope><D:shared/></D:lockscope><D:locktype><D:write/></D:locktype></D:lockentry>";
+
+/// What a `PROPFIND` request asks for.
+enum PropQuery {
+    /// Names and values of every property (`<allprop/>` or an empty body).
+    All,
+    /// Names of every property, without values (`<propname/>`).
+    Names,
+    /// Only the listed `(namespace, name)` properties (`<prop>`).
+    Props(Vec<(String, String)>),
+}
+
+/// Works out what a `PROPFIND` body asks for. An empty body means `<allprop/>`.
+fn prop_query(events: Option<&[XmlEvent]>) -> PropQuery {
+    match events {
+        Some(e) if xml::contains(e, "propname") => PropQuery::Names,
+        Some(e) if !xml::contains(e, "allprop") && xml::contains(e, "prop") => PropQuery::Props(xml::children_of(e, "prop")),
+        _ => PropQuery::All,
+    }
+}
+
+/// Returns the `(namespace, name)` of every property a `PROPPATCH` body sets or removes,
+/// or [`None`] if the body is not a `<propertyupdate>`.
+fn patched_properties(events: Option<&[XmlEvent]>) -> Option<Vec<(String, String)>> {
+    events
+        .filter(|e| xml::contains(e, "propertyupdate"))
+        .map(|e| xml::children_of(e, "prop"))
+}
+
+/// A file or collection reported in a multistatus response.
+struct Resource {
+    /// URL path of the resource, collections end with `/`.
+    href: String,
+    metadata: Metadata,
+}
+
+/// Returns true if `method` is handled by [`handle`].
+pub fn is_webdav_method(method: &str) -> bool {
+    matches!(method, "OPTIONS" | "PROPFIND" | "PROPPATCH" | "MKCOL" | "COPY" | "MOVE" | "LOCK" | "UNLOCK")
+}
+
+/// Handles the WebDAV methods that aren't covered by the regular `GET`, `PUT` and
+/// `DELETE` handlers.
+pub fn handle(request: &HttpRequest, body: &mut HttpBody, config: &Config) -> Result<HttpResponse, HttpErrors> {
+    if request.path.contains("..") {
+        return Ok(error_response(UNAUTHORIZED.code, request, config));
+    }
+
+    match request.method.as_str() {
+        "OPTIONS" => Ok(options(request, config)),
+        "PROPFIND" => propfind(request, body, config),
+        "PROPPATCH" => proppatch(request, body, config),
+        "MKCOL" => mkcol(request, config),
+        "COPY" | "MOVE" => copy_or_move(request, config),
+        "LOCK" => lock(request, body, config),
+        "UNLOCK" => unlock(request, config),
+        method => Err(HttpErrors::UnsupportedRequestType(method.to_string())),
+    }
+}
+
+fn options(request: &HttpRequest, config: &Config) -> HttpResponse {
+    let allowed = ALLOWED_METHODS
+        .split(", ")
+        .filter(|m| access::is_allowed(m, &request.path, config))
+        .collect::<Vec<_>>()
+        .join(", ");
+
+    HttpResponse {
+        headers: headers!(
+            HttpStatusHeader::new_from(OK.code);
+            HttpDavHeader::new_from(String::from(DAV_CLASSES));
+            HttpAllowHeader::new_from(allowed);
+            HttpMsAuthorViaHeader::new_from(String::from("DAV"));
+            HttpContentLengthHeader::new_from(0);
+        )
+        .build(),
+        content: HttpContent::default(),
+    }
+}
+
+/// Reports the properties of a resource and, depending on the `Depth` header, its members.
+fn propfind(request: &HttpRequest, body: &mut HttpBody, config: &Config) -> Result<HttpResponse, HttpErrors> {
+    let target = PathBuf::from(to_local_path(&config.server.root, &request.path));
+    let Ok(metadata) = fs::metadata(&target) else {
+        return Ok(error_response(NOT_FOUND.code, request, config));
+    };
+    if is_internal_path(&request.path, config) {
+        return Ok(error_response(NOT_FOUND.code, request, config));
+    }
+
+    let depth = match request.header("Depth") {
+        Some("0") => 0,
+        Some("1") => 1,
+        Some(d) if d.eq_ignore_ascii_case("infinity") => usize::MAX,
+        // RFC 4918 makes a missing header mean infinity, which is too easy to send by accident.
+        None => 1,
+        Some(_) => return Ok(error_response(BAD_REQUEST.code, request, config)),
+    };
+
+    let events = match read_xml(body) {
+        Ok(e) => e,
+        Err(HttpErrors::PayloadTooLarge(_)) => return Ok(error_response(PAYLOAD_TOO_LARGE.code, request, config)),
+        Err(HttpErrors::InvalidRequest(_)) => return Ok(error_response(BAD_REQUEST.code, request, config)),
+        Err(e) => return Err(e),
+    };
+    let query = prop_query(events.as_deref());
+
+    let root = fs::canonicalize(&config.server.root).map_err(|e| HttpErrors::FileReadFailure(e.to_string()))?;
+    let mut href = request.path.clone();
+    if metadata.is_dir() && !href.ends_with('/') {
+        href.push('/');
+    }
+    let mut resources = vec![];
+    collect(Resource { href, metadata }, &target, depth, &root, config, &mut resources);
+
+    let mut responses = String::new();
+    for resource in &resources {
+        responses.push_str(&format!("<D:response>\n<D:href>{}</D:href>\n", html_escape(&percent_encode(&resource.href))));
+        match &query {
+            PropQuery::All => {
+                let found: String = LIVE_PROPERTIES.iter().filter_map(|p| live_property(p, resource, config)).collect();
+                responses.push_str(&propstat(&found, OK));
+            }
+            PropQuery::Names => {
+                let names: String = LIVE_PROPERTIES
+                    .iter()
+                    .filter(|p| live_property(p, resource, config).is_some())
+                    .map(|p| xml::empty_element(DAV_NAMESPACE, p))
+                    .collect();
+                responses.push_str(&propstat(&names, OK));
+            }
+            PropQuery::Props(props) => {
+                let mut found = String::new();
+                let mut missing = String::new();
+                for (namespace, name) in props {
+                    match live_property(name, resource, config).filter(|_| namespace == DAV_NAMESPACE) {
+                        Some(p) => found.push_str(&p),
+                        None => missing.push_str(&xml::empty_element(namespace, name)),
+                    }
+                }
+                if !found.is_empty() {
+                    responses.push_str(&propstat(&found, OK));
+                }
+                if !missing.is_empty() {
+                    responses.push_str(&propstat(&missing, NOT_FOUND));
+                }
+            }
+        }
+        responses.push_str("</D:response>\n");
+    }
+
+    Ok(multistatus(&responses))
+}
+
+/// Adds `resource` to `out`, followed by its members up to `depth` levels deep. Hidden
+/// entries, the tus staging directory and symlinks leading out of the root are left out.
+/// Symlinked directories are listed but not descended into, so a link to one of its own
+/// ancestors can't make this recurse forever.
+fn collect(resource: Resource, local: &Path, depth: usize, root: &Path, config: &Config, out: &mut Vec<Resource>) {
+    let is_dir = resource.metadata.is_dir();
+    let href = resource.href.clone();
+    out.push(resource);
+
+    if !is_dir || depth == 0 {
+        return;
+    }
+    let Ok(entries) = fs::read_dir(local) else {
+        return;
+    };
+
+    let mut entries: Vec<_> = entries.flatten().collect();
+    entries.sort_by_key(|e| e.file_name());
+    for entry in entries {
+        let name = entry.file_name().to_string_lossy().to_string();
+        if !config.server.autoindex.show_hidden && is_hidden(&name) {
+            continue;
+        }
+        let path = entry.path();
+        if !fs::canonicalize(&path).is_ok_and(|p| p.starts_with(root)) || is_internal_path(&format!("{href}{name}"), config) {
+            continue;
+        }
+        let Ok(metadata) = fs::metadata(&path) else {
+            continue;
+        };
+
+        let mut child_href = format!("{href}{name}");
+        if metadata.is_dir() {
+            child_href.push('/');
+        }
+        let depth = if entry.file_type().is_ok_and(|t| t.is_symlink()) { 0 } else { depth - 1 };
+        collect(Resource { href: child_href, metadata }, &path, depth, root, config, out);
+    }
+}
+
+/// Returns the DAV: property `name` of `resource` as an XML element, or [`None`] if the
+/// resource doesn't have it.
+fn live_property(name: &str, resource: &Resource, config: &Config) -> Option<String> {
+    let metadata = &resource.metadata;
+    let is_dir = metadata.is_dir();
+
+    let value = match name {
+        "creationdate" => DateTime::from(metadata.created().or_else(|_| metadata.modified()).ok()?).to_rfc3339(),
+        "displayname" => html_escape(resource.href.trim_end_matches('/').rsplit('/').next().unwrap_or_default()),
+        "getcontentlength" if !is_dir => metadata.len().to_string(),
+        "getcontenttype" if !is_dir => {
+            let (content_type, _) = media::resolve(extension(&resource.href), &[], &config.mime);
+            let mut value = format!("{}/{}", content_type.first, content_type.second);
+            for (name, parameter) in &content_type.parameters {
+                value.push_str(&format!("; {name}={parameter}"));
+            }
+            html_escape(&value)
+        }
+        "getetag" => html_escape(&crate::preconditions::etag(metadata)),
+        "getlastmodified" => DateTime::from(metadata.modified().ok()?).to_http_date(),
+        "lockdiscovery" => locks::discover(resource.href.trim_end_matches('/'), config).iter().map(Lock::to_xml).collect(),
+        "resourcetype" if is_dir => String::from("<D:collection/>"),
+        "resourcetype" => String::new(),
+        "supportedlock" => String::from(SUPPORTED_LOCKS),
+        _ => return None,
+    };
+
+    if value.is_empty() {
+        Some(xml::empty_element(DAV_NAMESPACE, name))
+    } else {
+        Some(format!("<D:{name}>{value}</D:{name}>"))
+    }
+}
+
+/// Answers a property update. Only live properties exist and none of them can be changed,
+/// so every property is reported as forbidden and nothing is stored.
+fn proppatch(request: &HttpRequest, body: &mut HttpBody, config: &Config) -> Result<HttpResponse, HttpErrors> {
+    if !is_writable(&request.path, config) {
+        return Ok(error_response(FORBIDDEN.code, request, config));
+    }
+
+    let target = PathBuf::from(to_local_path(&config.server.root, &request.path));
+    let Ok(metadata) = fs::metadata(&target) else {
+        return Ok(error_response(NOT_FOUND.code, request, config));
+    };
+
+    let patched = match read_xml(body) {
+        Ok(e) => patched_properties(e.as_deref()),
+        Err(HttpErrors::InvalidRequest(_)) => None,
+        Err(HttpErrors::PayloadTooLarge(_)) => return Ok(error_response(PAYLOAD_TOO_LARGE.code, request, config)),
+        Err(e) => return Err(e),
+    };
+    let Some(patched) = patched else {
+        return Ok(error_response(BAD_REQUEST.code, request, config));
+    };
+
+    let props: String = patched
+        .iter()
+        .map(|(namespace, name)| xml::empty_element(namespace, name))
+        .collect();
+
+    let mut href = request.path.clone();
+    if metadata.is_dir() && !href.ends_with('/') {
+        href.push('/');
+    }
+    Ok(multistatus(&format!(
+        "<D:response>\n<D:href>{}</D:href>\n{}</D:response>\n",
+        html_escape(&percent_encode(&href)),
+        propstat(&props, FORBIDDEN)
+    )))
+}
+
+/// Creates a collection. The parent has to exist already.
+fn mkcol(request: &HttpRequest, config: &Config) -> Result<HttpResponse, HttpErrors> {
+    if !is_writable(&request.path, config) {
+        return Ok(error_response(FORBIDDEN.code, request, config));
+    }
+
+    // No body format for MKCOL is defined, so any body is unsupported.
+    if request.content_length()?.is_some_and(|l| l > 0) || request.header("Transfer-Encoding").is_some() {
+        return Ok(error_response(UNSUPPORTED_MEDIA_TYPE.code, request, config));
+    }
+
+    let target = PathBuf::from(to_local_path(&config.server.root, request.path.trim_end_matches('/')));
+    if fs::symlink_metadata(&target).is_ok() {
+        return Ok(error_response(METHOD_NOT_ALLOWED.code, request, config));
+    }
+
+    let parent = target.parent().unwrap_or(Path::new("."));
+    if !parent.is_dir() {
+        return Ok(error_response(CONFLICT.code, request, config));
+    }
+    if !is_under_root(parent, config) {
+        return Ok(error_response(UNAUTHORIZED.code, request, config));
+    }
+
+    fs::create_dir(&target).map_err(|e| HttpErrors::FileWriteFailure(e.to_string()))?;
+    Ok(empty_response(CREATED.code))
+}
+
+/// Copies or moves a resource to the path in the `Destination` header. An existing
+/// destination is replaced unless `Overwrite: F` is sent.
+fn copy_or_move(request: &HttpRequest, config: &Config) -> Result<HttpResponse, HttpErrors> {
+    let is_move = request.method == "MOVE";
+    let destination = match destination_path(request) {
+        Ok(d) => d,
+        Err(code) => return Ok(error_response(code, request, config)),
+    };
+    if destination.contains("..") {
+        return Ok(error_response(UNAUTHORIZED.code, request, config));
+    }
+
+    let source_path = request.path.trim_end_matches('/');
+    let destination_path = destination.trim_end_matches('/');
+    if !is_writable(destination_path, config)
+        || (is_move && (!is_writable(source_path, config) || source_path.is_empty()))
+    {
+        return Ok(error_response(FORBIDDEN.code, request, config));
+    }
+
+    let source = PathBuf::from(to_local_path(&config.server.root, source_path));
+    let Ok(metadata) = fs::metadata(&source) else {
+        return Ok(error_response(NOT_FOUND.code, request, config));
+    };
+
+    // Copying or moving a collection into itself would never end.
+    if destination_path == source_path || destination_path.starts_with(&format!("{source_path}/")) {
+        return Ok(error_response(FORBIDDEN.code, request, config));
+    }
+
+    let overwrite = !request.header("Overwrite").is_some_and(|o| o.eq_ignore_ascii_case("F"));
+    let target = PathBuf::from(to_local_path(&config.server.root, destination_path));
+    let existing = fs::symlink_metadata(&target).ok();
+    if existing.is_some() && !overwrite {
+        return Ok(error_response(PRECONDITION_FAILED.code, request, config));
+    }
+
+    let parent = target.parent().unwrap_or(Path::new("."));
+    if !parent.is_dir() {
+        return Ok(error_response(CONFLICT.code, request, config));
+    }
+    if !is_under_root(parent, config) {
+        return Ok(error_response(UNAUTHORIZED.code, request, config));
+    }
+
+    let recursive = match request.header("Depth") {
+        Some("0") if !is_move => false,
+        Some(d) if !d.eq_ignore_ascii_case("infinity") => return Ok(error_response(BAD_REQUEST.code, request, config)),
+        _ => true,
+    };
+
+    // A move within the same quota directories only renames, it doesn't take up more space.
+    let charged = !is_move || quota::crosses_quota(source_path, destination_path, config);
+    let size = if charged && (recursive || !metadata.is_dir()) { quota::directory_size(&source) } else { 0 };
+    let replaced = match existing {
+        Some(_) if quota::is_limited(destination_path, config) => quota::directory_size(&target),
+        _ => 0,
+    };
+    if charged {
+        let allowance = quota::allowance(destination_path, replaced, config);
+        if !allowance.permits(size) {
+            return Ok(error_response(allowance.code, request, config));
+        }
+    }
+
+    // Overwriting is deleting the destination first, so the same policy applies.
+    if let Some(existing) = &existing {
+        if let Some(code) = delete::remove(&target, existing, config)? {
+            return Ok(error_response(code, request, config));
+        }
+    }
+
+    if is_move {
+        fs::rename(&source, &target).map_err(|e| HttpErrors::FileWriteFailure(e.to_string()))?;
+    } else {
+        copy_tree(&source, &metadata, &target, recursive)?;
+    }
+    quota::record_write(destination_path, size, replaced);
+    if is_move {
+        quota::record_removal(source_path, size);
+    }
+
+    Ok(empty_response(if existing.is_some() { NO_CONTENT.code } else { CREATED.code }))
+}
+
+/// Copies a file, or a directory and (if `recursive`) its contents. Symlinks inside
+/// directories are skipped so a copy can't pull in data from outside the root.
+fn copy_tree(source: &Path, metadata: &Metadata, target: &Path, recursive: bool) -> Result<(), HttpErrors> {
+    if !metadata.is_dir() {
+        fs::copy(source, target).map_err(|e| HttpErrors::FileWriteFailure(e.to_string()))?;
+        return Ok(());
+    }
+
+    fs::create_dir(target).map_err(|e| HttpErrors::FileWriteFailure(e.to_string()))?;
+    if !recursive {
+        return Ok(());
+    }
+
+    for entry in fs::read_dir(source).map_err(|e| HttpErrors::FileReadFailure(e.to_string()))? {
+        let entry = entry.map_err(|e| HttpErrors::FileReadFailure(e.to_string()))?;
+        let metadata = fs::symlink_metadata(entry.path()).map_err(|e| HttpErrors::FileReadFailure(e.to_string()))?;
+        if metadata.is_symlink() {
+            continue;
+        }
+        copy_tree(&entry.path(), &metadata, &target.join(entry.file_name()), true)?;
+    }
+
+    Ok(())
+}
+
+/// Takes a write lock on a resource, creating it as an empty file if it doesn't exist yet.
+/// A `LOCK` without a body refreshes the lock submitted in the `If` header instead.
+fn lock(request: &HttpRequest, body: &mut HttpBody, config: &Config) -> Result<HttpResponse, HttpErrors> {
+    if !is_writable(&request.path, config) {
+        return Ok(error_response(FORBIDDEN.code, request, config));
+    }
+
+    let path = request.path.trim_end_matches('/');
+    let timeout = lock_timeout(request, config);
+    let events = match read_xml(body) {
+        Ok(e) => e,
+        Err(HttpErrors::PayloadTooLarge(_)) => return Ok(error_response(PAYLOAD_TOO_LARGE.code, request, config)),
+        Err(HttpErrors::InvalidRequest(_)) => return Ok(error_response(BAD_REQUEST.code, request, config)),
+        Err(e) => return Err(e),
+    };
+
+    let Some(events) = events else {
+        let Ok(tokens) = locks::submitted_tokens(request) else {
+            return Ok(error_response(BAD_REQUEST.code, request, config));
+        };
+        return match locks::refresh(path, &tokens, timeout, config) {
+            Some(lock) => Ok(lock_response(OK.code, &lock, false)),
+            None => Ok(error_response(PRECONDITION_FAILED.code, request, config)),
+        };
+    };
+    if !xml::contains(&events, "lockinfo") {
+        return Ok(error_response(BAD_REQUEST.code, request, config));
+    }
+
+    let scope = if xml::contains(&events, "shared") { LockScope::Shared } else { LockScope::Exclusive };
+    let infinite = match request.header("Depth") {
+        Some("0") => false,
+        Some(d) if !d.eq_ignore_ascii_case("infinity") => return Ok(error_response(BAD_REQUEST.code, request, config)),
+        _ => true,
+    };
+    let owner = xml::text_of(&events, "owner").unwrap_or_default();
+
+    let target = PathBuf::from(to_local_path(&config.server.root, path));
+    let exists = fs::symlink_metadata(&target).is_ok();
+    if !exists {
+        let parent = target.parent().unwrap_or(Path::new("."));
+        if !parent.is_dir() {
+            return Ok(error_response(CONFLICT.code, request, config));
+        }
+        if !is_under_root(parent, config) {
+            return Ok(error_response(UNAUTHORIZED.code, request, config));
+        }
+        fs::File::create(&target).map_err(|e| HttpErrors::FileWriteFailure(e.to_string()))?;
+    }
+
+    match locks::acquire(path, scope, infinite, owner, timeout, config) {
+        Some(lock) => Ok(lock_response(if exists { OK.code } else { CREATED.code }, &lock, true)),
+        None => {
+            // Don't leave the empty file behind for a lock that was never granted.
+            if !exists {
+                let _ = fs::remove_file(&target);
+            }
+            Ok(error_response(LOCKED.code, request, config))
+        }
+    }
+}
+
+/// Releases the lock named in the `Lock-Token` header.
+fn unlock(request: &HttpRequest, config: &Config) -> Result<HttpResponse, HttpErrors> {
+    let Some(token) = request.header("Lock-Token").map(|t| t.trim().trim_start_matches('<').trim_end_matches('>')) else {
+        return Ok(error_response(BAD_REQUEST.code, request, config));
+    };
+
+    if !locks::release(request.path.trim_end_matches('/'), token, config) {
+        return Ok(error_response(CONFLICT.code, request, config));
+    }
+
+    Ok(empty_response(NO_CONTENT.code))
+}
+
+/// Reads the lock timeout requested through the `Timeout` header (e.g. `Second-600`),
+/// capped at the configured maximum.
+fn lock_timeout(request: &HttpRequest, config: &Config) -> u64 {
+    let max = config.server.webdav.lock_timeout;
+    request
+        .header("Timeout")
+        .unwrap_or_default()
+        .split(',')
+        .find_map(|t| match t.trim() {
+            "Infinite" => Some(max),
+            t => t.strip_prefix("Second-").and_then(|s| s.parse::<u64>().ok()),
+        })
+        .unwrap_or(max)
+        .min(max)
+}
+
+fn lock_response(code: u16, lock: &Lock, with_token: bool) -> HttpResponse {
+    let content = HttpContent::from(format!(
+        "<?xml version=\"1.0\" encoding=\"utf-8\"?>\n<D:prop xmlns:D=\"DAV:\"><D:lockdiscovery>{}</D:lockdiscovery></D:prop>\n",
+        lock.to_xml()
+    ));
+
+    let mut headers = headers!(
+        HttpStatusHeader::new_from(code);
+        HttpContentTypeHeader::new_from(media::parse_content_type("application/xml; charset=utf-8").unwrap());
+        HttpContentLengthHeader::new_from(content.content.len());
+    );
+    if with_token {
+        headers = headers.add_header(HttpLockTokenHeader::new_from(format!("<{}>", lock.token)));
+    }
+
+    HttpResponse { headers: headers.build(), content }
+}
+
+/// Extracts the decoded path from the `Destination` header, which may be an absolute URL.
+/// Returns the status code to respond with if it's missing, malformed or on another server.
+pub fn destination_path(request: &HttpRequest) -> Result<String, u16> {
+    let raw = request.header("Destination").ok_or(BAD_REQUEST.code)?;
+
+    let path = match raw.split_once("://") {
+        Some((_, rest)) if !raw.starts_with('/') => {
+            let (authority, path) = rest.find('/').map(|i| rest.split_at(i)).unwrap_or((rest, "/"));
+            if request.header("Host").is_some_and(|h| !h.eq_ignore_ascii_case(authority)) {
+                return Err(BAD_GATEWAY.code);
+            }
+            path
+        }
+        _ => raw,
+    };
+    let path = path.split(['?', '#']).next().unwrap_or_default();
+
+    match percent_decode(path) {
+        Some(p) if p.starts_with('/') => Ok(p),
+        _ => Err(BAD_REQUEST.code),
+    }
+}
+
+/// Reads and parses an XML request body. Returns [`None`] if the body is empty.
+fn read_xml(body: &mut HttpBody) -> Result<Option<Vec<XmlEvent>>, HttpErrors> {
+    let mut raw = vec![];
+    body.take(MAX_XML_BODY_LENGTH + 1)
+        .read_to_end(&mut raw)
+        .map_err(|e| HttpErrors::StreamReadFailure(e.to_string()))?;
+
+    if raw.len() as u64 > MAX_XML_BODY_LENGTH {
+        return Err(HttpErrors::PayloadTooLarge(format!("body exceeds {MAX_XML_BODY_LENGTH} bytes")));
+    }
+    if raw.iter().all(|b| b.is_ascii_whitespace()) {
+        return Ok(None);
+    }
+
+    let raw = String::from_utf8(raw).map_err(|_| HttpErrors::InvalidRequest(String::from("XML body is not UTF-8")))?;
+    xml::parse(&raw).map(Some)
+}
+
+fn propstat(props: &str, status: HttpStatusCode) -> String {
+    format!(
+        "<D:propstat>\n<D:prop>{props}</D:prop>\n<D:status>HTTP/1.1 {}</D:status>\n</D:propstat>\n",
+        status_code_string!(status.code, status.phrase)
+    )
+}
+
+/// Wraps `<D:response>` elements into a `207 Multi-Status` response.
+fn multistatus(responses: &str) -> HttpResponse {
+    let content = HttpContent::from(format!(
+        "<?xml version=\"1.0\" encoding=\"utf-8\"?>\n<D:multistatus xmlns:D=\"DAV:\">\n{responses}</D:multistatus>\n"
+    ));
+
+    HttpResponse {
+        headers: headers!(
+            HttpStatusHeader::new_from(MULTI_STATUS.code);
+            HttpContentTypeHeader::new_from(media::parse_content_type("application/xml; charset=utf-8").unwrap());
+            HttpContentLengthHeader::new_from(content.content.len());
+        )
+        .build(),
+        content,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{patched_properties, prop_query, PropQuery};
+    use crate::xml::{parse, DAV_NAMESPACE};
+
+    fn query(body: &str) -> PropQuery {
+        prop_query(Some(&parse(body).unwrap()))
+    }
+
+    #[test]
+    fn empty_or_allprop_body_asks_for_everything() {
+        assert!(matches!(prop_query(None), PropQuery::All));
+        assert!(matches!(query(r#"<D:propfind xmlns:D="DAV:"><D:allprop/></D:propfind>"#), PropQuery::All));
+        // `<include>` lists extra properties next to `<allprop>`, which are all reported anyway.
+        assert!(matches!(
+            query(r#"<D:propfind xmlns:D="DAV:"><D:allprop/><D:include><D:prop/></D:include></D:propfind>"#),
+            PropQuery::All
+        ));
+    }
+
+    #[test]
+    fn propname_body_asks_for_names() {
+        assert!(matches!(query(r#"<D:propfind xmlns:D="DAV:"><D:propname/></D:propfind>"#), PropQuery::Names));
+    }
+
+    #[test]
+    fn prop_body_lists_properties_with_namespaces() {
+        let body = r#"<D:propfind xmlns:D="DAV:"><D:prop><D:getetag/><x:color xmlns:x="urn:x"/></D:prop></D:propfind>"#;
+        let PropQuery::Props(props) = query(body) else {
+            panic!("expected a property list");
+        };
+        assert_eq!(
+            props,
+            vec![(DAV_NAMESPACE.to_string(), "getetag".to_string()), ("urn:x".to_string(), "color".to_string())]
+        );
+    }
+
+    #[test]
+    fn prop_outside_dav_namespace_is_not_a_query() {
+        assert!(matches!(query(r#"<propfind xmlns="urn:x"><prop><a/></prop></propfind>"#), PropQuery::All));
+    }
+
+    #[test]
+    fn proppatch_lists_set_and_removed_properties() {
+        let body = r#"<D:propertyupdate xmlns:D="DAV:" xmlns:Z="urn:z">
+            <D:set><D:prop><Z:author>me</Z:author></D:prop></D:set>
+            <D:remove><D:prop><D:displayname/></D:prop></D:remove>
+        </D:propertyupdate>"#;
+        let props = patched_properties(Some(&parse(body).unwrap())).unwrap();
+        assert_eq!(
+            props,
+            vec![("urn:z".to_string(), "author".to_string()), (DAV_NAMESPACE.to_string(), "displayname".to_string())]
+        );
+    }
+
+    #[test]
+    fn proppatch_needs_a_propertyupdate() {
+        assert_eq!(patched_properties(None), None);
+        assert_eq!(patched_properties(Some(&parse(r#"<D:propfind xmlns:D="DAV:"/>"#).unwrap())), None);
+    }
+}
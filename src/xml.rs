@@ -0,0 +1,312 @@
+use crate::errors::HttpErrors;
+
+/// The `DAV:` namespace used by WebDAV request and response bodies.
+pub const DAV_NAMESPACE: &str = "DAV:";
+
+/// A piece of an XML document, as produced by [`parse`].
+pub enum XmlEvent {
+    /// Start of an element, with its namespace already resolved.
+    Start { namespace: String, name: String },
+    End,
+    Text(String),
+}
+
+/// Minimal namespace-aware XML reader, enough for the request bodies WebDAV clients send.
+/// Self-closing elements produce a [`XmlEvent::Start`] directly followed by an [`XmlEvent::End`].
+/// Comments, processing instructions and doctypes are skipped.
+pub fn parse(input: &str) -> Result<Vec<XmlEvent>, HttpErrors> {
+    let mut events = vec![];
+    // Namespace declarations of every open element, innermost last.
+    let mut scopes: Vec<Vec<(String, String)>> = vec![];
+    let mut rest = input;
+
+    while !rest.is_empty() {
+        let Some(start) = rest.find('<') else {
+            push_text(&mut events, rest);
+            break;
+        };
+        push_text(&mut events, &rest[..start]);
+        rest = &rest[start..];
+
+        if let Some(after) = rest.strip_prefix("<!--") {
+            let end = after.find("-->").ok_or_else(|| malformed("unterminated comment"))?;
+            rest = &after[end + 3..];
+            continue;
+        }
+        if rest.starts_with("<?") || rest.starts_with("<!") {
+            let end = rest.find('>').ok_or_else(|| malformed("unterminated declaration"))?;
+            rest = &rest[end + 1..];
+            continue;
+        }
+
+        let end = rest.find('>').ok_or_else(|| malformed("unterminated tag"))?;
+        let tag = &rest[1..end];
+        rest = &rest[end + 1..];
+
+        if tag.starts_with('/') {
+            if scopes.pop().is_none() {
+                return Err(malformed("unbalanced closing tag"));
+            }
+            events.push(XmlEvent::End);
+            continue;
+        }
+
+        let self_closing = tag.ends_with('/');
+        let tag = tag.trim_end_matches('/').trim();
+        let (qualified, rest_of_tag) = tag.split_once(char::is_whitespace).unwrap_or((tag, ""));
+        if qualified.is_empty() {
+            return Err(malformed("empty tag"));
+        }
+
+        let mut declarations = vec![];
+        for (name, value) in attributes(rest_of_tag) {
+            if name == "xmlns" {
+                declarations.push((String::new(), value));
+            } else if let Some(prefix) = name.strip_prefix("xmlns:") {
+                declarations.push((prefix.to_string(), value));
+            }
+        }
+        scopes.push(declarations);
+
+        let (prefix, name) = qualified.split_once(':').unwrap_or(("", qualified));
+        let namespace = scopes
+            .iter()
+            .rev()
+            .flat_map(|s| s.iter())
+            .find(|(p, _)| p == prefix)
+            .map(|(_, ns)| ns.clone())
+            .unwrap_or_default();
+
+        events.push(XmlEvent::Start { namespace, name: name.to_string() });
+        if self_closing {
+            scopes.pop();
+            events.push(XmlEvent::End);
+        }
+    }
+
+    if !scopes.is_empty() {
+        return Err(malformed("unclosed element"));
+    }
+
+    Ok(events)
+}
+
+/// Returns the `(namespace, name)` of every direct child of the `DAV:` elements called
+/// `parent`, e.g. the properties listed inside `<D:prop>`.
+pub fn children_of(events: &[XmlEvent], parent: &str) -> Vec<(String, String)> {
+    let mut children = vec![];
+    let mut depth = 0;
+    let mut parent_depth = None;
+
+    for event in events {
+        match event {
+            XmlEvent::Start { namespace, name } => {
+                depth += 1;
+                match parent_depth {
+                    None if namespace == DAV_NAMESPACE && name == parent => parent_depth = Some(depth),
+                    Some(d) if depth == d + 1 => children.push((namespace.clone(), name.clone())),
+                    _ => (),
+                }
+            }
+            XmlEvent::End => {
+                if parent_depth == Some(depth) {
+                    parent_depth = None;
+                }
+                depth -= 1;
+            }
+            XmlEvent::Text(_) => (),
+        }
+    }
+
+    children
+}
+
+/// Returns true if the document contains a `DAV:` element called `name`.
+pub fn contains(events: &[XmlEvent], name: &str) -> bool {
+    events.iter().any(|e| matches!(e, XmlEvent::Start { namespace, name: n } if namespace == DAV_NAMESPACE && n == name))
+}
+
+/// Returns the text inside the first `DAV:` element called `name`, including the text of
+/// its children, or [`None`] if there is no such element.
+pub fn text_of(events: &[XmlEvent], name: &str) -> Option<String> {
+    let start = events
+        .iter()
+        .position(|e| matches!(e, XmlEvent::Start { namespace, name: n } if namespace == DAV_NAMESPACE && n == name))?;
+
+    let mut text = String::new();
+    let mut depth = 0;
+    for event in &events[start..] {
+        match event {
+            XmlEvent::Start { .. } => depth += 1,
+            XmlEvent::End => {
+                depth -= 1;
+                if depth == 0 {
+                    break;
+                }
+            }
+            XmlEvent::Text(t) => text.push_str(t),
+        }
+    }
+
+    Some(text)
+}
+
+/// Writes an empty element in its own namespace, e.g. `<x:foo xmlns:x="urn:bar"/>`.
+pub fn empty_element(namespace: &str, name: &str) -> String {
+    if namespace == DAV_NAMESPACE {
+        format!("<D:{name}/>")
+    } else {
+        format!("<x:{name} xmlns:x=\"{}\"/>", crate::escape::html_escape(namespace))
+    }
+}
+
+fn push_text(events: &mut Vec<XmlEvent>, text: &str) {
+    if !text.trim().is_empty() {
+        events.push(XmlEvent::Text(unescape(text.trim())));
+    }
+}
+
+/// Splits `name="value"` pairs out of the inside of a tag.
+fn attributes(input: &str) -> Vec<(String, String)> {
+    let mut attributes = vec![];
+    let mut rest = input;
+
+    while let Some((name, after)) = rest.split_once('=') {
+        let mut chars = after.trim_start().chars();
+        let Some(quote) = chars.next().filter(|c| *c == '"' || *c == '\'') else {
+            break;
+        };
+        let Some((value, remaining)) = chars.as_str().split_once(quote) else {
+            break;
+        };
+        attributes.push((name.trim().to_string(), unescape(value)));
+        rest = remaining;
+    }
+
+    attributes
+}
+
+/// Resolves the predefined entities and character references.
+fn unescape(input: &str) -> String {
+    let mut output = String::with_capacity(input.len());
+    let mut rest = input;
+
+    while let Some(amp) = rest.find('&') {
+        output.push_str(&rest[..amp]);
+        rest = &rest[amp..];
+        let Some(end) = rest.find(';') else {
+            break;
+        };
+        let entity = &rest[1..end];
+        let resolved = match entity {
+            "amp" => Some('&'),
+            "lt" => Some('<'),
+            "gt" => Some('>'),
+            "quot" => Some('"'),
+            "apos" => Some('\''),
+            _ => entity
+                .strip_prefix("#x")
+                .and_then(|h| u32::from_str_radix(h, 16).ok())
+                .or_else(|| entity.strip_prefix('#').and_then(|d| d.parse().ok()))
+                .and_then(char::from_u32),
+        };
+        match resolved {
+            Some(c) => {
+                output.push(c);
+                rest = &rest[end + 1..];
+            }
+            None => {
+                output.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+
+    output.push_str(rest);
+    output
+}
+
+fn malformed(reason: &str) -> HttpErrors {
+    HttpErrors::InvalidRequest(format!("malformed XML body: {reason}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{children_of, contains, parse, text_of, XmlEvent, DAV_NAMESPACE};
+
+    fn starts(events: &[XmlEvent]) -> Vec<(&str, &str)> {
+        events
+            .iter()
+            .filter_map(|e| match e {
+                XmlEvent::Start { namespace, name } => Some((namespace.as_str(), name.as_str())),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn resolves_prefixed_and_default_namespaces() {
+        let events = parse(r#"<D:propfind xmlns:D="DAV:"><D:prop><x:a xmlns:x="urn:x"/><b xmlns='urn:y'/></D:prop></D:propfind>"#).unwrap();
+        assert_eq!(
+            starts(&events),
+            vec![(DAV_NAMESPACE, "propfind"), (DAV_NAMESPACE, "prop"), ("urn:x", "a"), ("urn:y", "b")]
+        );
+    }
+
+    #[test]
+    fn declarations_end_with_their_element() {
+        let events = parse(r#"<r><a xmlns:p="urn:a"><p:x/></a><p:y/></r>"#).unwrap();
+        assert_eq!(starts(&events), vec![("", "r"), ("", "a"), ("urn:a", "x"), ("", "y")]);
+    }
+
+    #[test]
+    fn whitespace_and_multibyte_names_do_not_panic() {
+        let events = parse(r#"<D:propfind xmlns:D="DAV:">< aé/></D:propfind>"#).unwrap();
+        assert_eq!(starts(&events), vec![(DAV_NAMESPACE, "propfind"), ("", "aé")]);
+
+        let events = parse("<é:ü xmlns:é=\"urn:é\"  >ö</é:ü>").unwrap();
+        assert_eq!(starts(&events), vec![("urn:é", "ü")]);
+        assert!(parse("<  />").is_err());
+        assert!(parse("< >").is_err());
+    }
+
+    #[test]
+    fn skips_comments_and_declarations() {
+        let events = parse("<?xml version=\"1.0\"?><!DOCTYPE x><!-- <a> --><r/>").unwrap();
+        assert_eq!(starts(&events), vec![("", "r")]);
+    }
+
+    #[test]
+    fn rejects_malformed_documents() {
+        assert!(parse("<a>").is_err());
+        assert!(parse("</a>").is_err());
+        assert!(parse("<a").is_err());
+        assert!(parse("<!-- a").is_err());
+        assert!(parse("<?xml").is_err());
+    }
+
+    #[test]
+    fn unescapes_text_and_attributes() {
+        let events = parse(r#"<D:owner xmlns:D="DAV:" a="&lt;&#x41;&#66;&gt;">a &amp; b &bogus; &#xD800;</D:owner>"#).unwrap();
+        assert_eq!(text_of(&events, "owner").as_deref(), Some("a & b &bogus; &#xD800;"));
+
+        let events = parse(r#"<r xmlns:x="urn:&quot;q&apos;"><x:a/></r>"#).unwrap();
+        assert_eq!(starts(&events)[1], ("urn:\"q'", "a"));
+    }
+
+    #[test]
+    fn collects_direct_children_only() {
+        let events = parse(r#"<D:prop xmlns:D="DAV:"><D:a><D:b/></D:a><D:c/></D:prop><D:prop xmlns:D="DAV:"><D:d/></D:prop>"#).unwrap();
+        let names: Vec<_> = children_of(&events, "prop").into_iter().map(|(_, n)| n).collect();
+        assert_eq!(names, vec!["a", "c", "d"]);
+        assert!(contains(&events, "b"));
+        assert!(!contains(&events, "e"));
+    }
+
+    #[test]
+    fn text_includes_children() {
+        let events = parse(r#"<D:owner xmlns:D="DAV:"> <D:href>mailto:a</D:href> x </D:owner>"#).unwrap();
+        assert_eq!(text_of(&events, "owner").as_deref(), Some("mailto:ax"));
+        assert_eq!(text_of(&events, "missing"), None);
+    }
+}